@@ -0,0 +1,127 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use genetic_rl::neuralnetwork::batch::SequentialBatch;
+use genetic_rl::neuralnetwork::layer::GRULayer;
+use genetic_rl::neuralnetwork::matrix::Matrix;
+use genetic_rl::neuralnetwork::sequential::Sequential;
+use genetic_rl::neuralnetwork::simd::{Kernels, SimdLevel};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+fn random_vec(n: usize, rng: &mut ChaCha8Rng) -> Vec<f64> {
+    (0..n).map(|_| rng.gen_range(-1. ..1.)).collect()
+}
+
+/// Scalar path against every vectorised level the CPU supports.
+fn kernels(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+    for n in [16, 256, 4096] {
+        let a = random_vec(n, &mut rng);
+        let b = random_vec(n, &mut rng);
+        let a32: Vec<f32> = a.iter().map(|x| *x as f32).collect();
+        let b32: Vec<f32> = b.iter().map(|x| *x as f32).collect();
+
+        let mut group = c.benchmark_group(format!("kernels/{}", n));
+        for level in SimdLevel::supported() {
+            group.bench_with_input(
+                BenchmarkId::new("dot_f64", format!("{:?}", level)),
+                &level,
+                |bench, &level| bench.iter(|| f64::dot(level, black_box(&a), black_box(&b))),
+            );
+            group.bench_with_input(
+                BenchmarkId::new("dot_f32", format!("{:?}", level)),
+                &level,
+                |bench, &level| bench.iter(|| f32::dot(level, black_box(&a32), black_box(&b32))),
+            );
+            group.bench_with_input(
+                BenchmarkId::new("axpy_f64", format!("{:?}", level)),
+                &level,
+                |bench, &level| {
+                    let mut y = b.clone();
+                    bench.iter(|| f64::axpy(level, 1e-3, black_box(&a), black_box(&mut y)))
+                },
+            );
+            group.bench_with_input(
+                BenchmarkId::new("mul_assign_f64", format!("{:?}", level)),
+                &level,
+                |bench, &level| {
+                    let mut y = b.clone();
+                    bench.iter(|| {
+                        <f64 as Kernels>::mul_assign(level, black_box(&mut y), black_box(&a))
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+/// Forward pass of a GRU layer, at the best level the CPU supports.
+fn gru_forward(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+    for size in [8, 64] {
+        let mut layer_f64: GRULayer = GRULayer::new_with_rng(size, size, 16, &mut rng);
+        let mut layer_f32: GRULayer<f32> = GRULayer::new_with_rng(size, size, 16, &mut rng);
+        let input = Matrix::from_vec(16, size, random_vec(16 * size, &mut rng));
+        let input_f32 = input.cast();
+
+        c.bench_function(&format!("gru_forward_f64/{}", size), |bench| {
+            bench.iter(|| layer_f64.forward(black_box(&input)))
+        });
+        c.bench_function(&format!("gru_forward_f32/{}", size), |bench| {
+            bench.iter(|| layer_f32.forward(black_box(&input_f32)))
+        });
+    }
+}
+
+/// Stacked forward pass of a population against stepping every model, with
+/// an input shared by the population and with one input per model.
+fn sequential_batch(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+    for nb_agents in [16, 128] {
+        let mut models: Vec<Sequential> = (0..nb_agents)
+            .map(|_| {
+                Sequential::new()
+                    .linear(16, 32)
+                    .tanh()
+                    .gru(32, 32)
+                    .linear(32, 4)
+            })
+            .collect();
+        let mut batch = SequentialBatch::stack(&models).unwrap();
+        let input = Matrix::from_vec(1, 16, random_vec(16, &mut rng));
+        let inputs = Matrix::from_vec(nb_agents, 16, random_vec(nb_agents * 16, &mut rng));
+
+        let mut group = c.benchmark_group(format!("sequential_batch/{}", nb_agents));
+        group.bench_function("shared_one_by_one", |bench| {
+            bench.iter(|| {
+                for model in models.iter_mut() {
+                    black_box(model.forward(black_box(&input)));
+                }
+            })
+        });
+        group.bench_function("shared_stacked", |bench| {
+            bench.iter(|| {
+                black_box(batch.forward_shared(black_box(&input)));
+            })
+        });
+        group.bench_function("each_one_by_one", |bench| {
+            bench.iter(|| {
+                for (model, row) in models.iter_mut().zip(inputs.iter_rows()) {
+                    black_box(model.forward(&Matrix::from_vec(1, 16, row.to_vec())));
+                }
+            })
+        });
+        group.bench_function("each_stacked", |bench| {
+            bench.iter(|| {
+                black_box(batch.forward_each(black_box(&inputs)));
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, kernels, gru_forward, sequential_batch);
+criterion_main!(benches);
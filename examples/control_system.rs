@@ -1,10 +1,17 @@
 extern crate genetic_rl;
 
 use crate::genetic_rl::genetic_training::agent::Agent;
+use crate::genetic_rl::genetic_training::cmaes::{CmaEs, CmaEsConfig, Restart};
+use crate::genetic_rl::genetic_training::config::TrainingConfig;
+use crate::genetic_rl::genetic_training::nsga2::{Nsga2, Nsga2Config};
+use crate::genetic_rl::genetic_training::observer::ConsoleObserver;
+use crate::genetic_rl::genetic_training::rng::TrainingRng;
 use crate::genetic_rl::genetic_training::simulation::Simulation;
-use crate::genetic_rl::genetic_training::training::training_from_checkpoint;
+use crate::genetic_rl::genetic_training::training::Trainer;
+use crate::genetic_rl::neuralnetwork::matrix::Matrix;
+use crate::genetic_rl::neuralnetwork::parameters::Parameters;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use std::f64::consts::PI;
 
 const DT: f64 = 0.01; // Time step for simulation
@@ -12,6 +19,7 @@ const TOTAL_TIME: f64 = 3.0; // Total simulation time
 const MAX_U: f64 = 9.; // Max kart acceleration
 
 const MAX_STARTING_ANGLE: f64 = PI / 4.; // Maximum pole init*ial angle
+const SEED: u64 = 42; // Seed of the trainers and of the initial population
 
 #[derive(Clone, Copy)]
 pub struct Controller {
@@ -25,22 +33,22 @@ unsafe impl Send for Controller {}
 unsafe impl Sync for Controller {}
 
 impl Agent for Controller {
-    fn step(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    fn step(&mut self, input: &Matrix) -> Matrix {
         let mut a = (self.x_coeff * input[0][0])
             + (self.x_dot_coeff * input[0][1])
             + (self.theta_coeff * input[0][2])
             + (self.theta_dot_coeff * input[0][3]);
-        a = f64::max(-MAX_U, f64::min(a, MAX_U));
-        vec![vec![a]]
+        a = a.clamp(-MAX_U, MAX_U);
+        Matrix::from_vec(1, 1, vec![a])
     }
 
     fn reset(&mut self) {}
 
-    fn mutate(&self, mutation_rate: f64) -> Self {
-        let x_coeff_delta = rand::thread_rng().gen_range(-mutation_rate..=mutation_rate);
-        let x_dot_coeff_delta = rand::thread_rng().gen_range(-mutation_rate..=mutation_rate);
-        let theta_dot_coeff_delta = rand::thread_rng().gen_range(-mutation_rate..=mutation_rate);
-        let theta_coeff_delta = rand::thread_rng().gen_range(-mutation_rate..=mutation_rate);
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        let x_coeff_delta = rng.gen_range(-mutation_rate..=mutation_rate);
+        let x_dot_coeff_delta = rng.gen_range(-mutation_rate..=mutation_rate);
+        let theta_dot_coeff_delta = rng.gen_range(-mutation_rate..=mutation_rate);
+        let theta_coeff_delta = rng.gen_range(-mutation_rate..=mutation_rate);
 
         Controller {
             x_coeff: self.x_coeff + x_coeff_delta,
@@ -51,12 +59,39 @@ impl Agent for Controller {
     }
 }
 
+// The four gains are the search space of CMA-ES
+impl Parameters for Controller {
+    fn params(&self) -> Box<dyn Iterator<Item = &f64> + '_> {
+        Box::new(
+            [
+                &self.x_coeff,
+                &self.x_dot_coeff,
+                &self.theta_coeff,
+                &self.theta_dot_coeff,
+            ]
+            .into_iter(),
+        )
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut f64> + '_> {
+        Box::new(
+            [
+                &mut self.x_coeff,
+                &mut self.x_dot_coeff,
+                &mut self.theta_coeff,
+                &mut self.theta_dot_coeff,
+            ]
+            .into_iter(),
+        )
+    }
+}
+
 impl Controller {
-    fn new() -> Self {
-        let x_coeff = rand::thread_rng().gen_range(-1.0..=1.0);
-        let x_dot_coeff = rand::thread_rng().gen_range(-1.0..=1.0);
-        let theta_coeff = rand::thread_rng().gen_range(-1.0..=1.0);
-        let theta_dot_coeff = rand::thread_rng().gen_range(-1.0..=1.0);
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let x_coeff = rng.gen_range(-1.0..=1.0);
+        let x_dot_coeff = rng.gen_range(-1.0..=1.0);
+        let theta_coeff = rng.gen_range(-1.0..=1.0);
+        let theta_dot_coeff = rng.gen_range(-1.0..=1.0);
         Controller {
             x_coeff,
             x_dot_coeff,
@@ -80,18 +115,33 @@ impl Simulation for InvertedPendulum {
     where
         A: Agent,
     {
-        self.simulate_agent(agent, true)
+        match self.simulate_agent(agent, true) {
+            Ok([x_error, theta_error, effort]) => 10000. - x_error - theta_error - effort,
+            Err(steps) => steps as f64,
+        }
+    }
+
+    /// The cart error, the angle error and the control effort, minimised. A
+    /// pendulum that falls is worse than any balanced one on every objective.
+    fn evaluate_objectives<A>(&self, agent: &mut A) -> Vec<f64>
+    where
+        A: Agent,
+    {
+        match self.simulate_agent(agent, true) {
+            Ok(costs) => costs.iter().map(|cost| -cost).collect(),
+            Err(steps) => vec![-1000. - (TOTAL_TIME / DT) + steps as f64; 3],
+        }
     }
 
-    fn on_generation(&mut self, _generation_number: usize) {
-        let starting_angle = rand::thread_rng().gen_range(-MAX_STARTING_ANGLE..=MAX_STARTING_ANGLE);
+    fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, rng: &mut R) {
+        let starting_angle = rng.gen_range(-MAX_STARTING_ANGLE..=MAX_STARTING_ANGLE);
         self.starting_angle = starting_angle;
     }
 }
 
 impl InvertedPendulum {
-    fn new() -> Self {
-        let starting_angle = rand::thread_rng().gen_range(-MAX_STARTING_ANGLE..=MAX_STARTING_ANGLE);
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let starting_angle = rng.gen_range(-MAX_STARTING_ANGLE..=MAX_STARTING_ANGLE);
         println!("Starting_angle: {:.2} deg", starting_angle.to_degrees());
         InvertedPendulum {
             m: 0.25,      // Mass of the pendulum
@@ -102,7 +152,9 @@ impl InvertedPendulum {
         }
     }
 
-    fn simulate_agent<A>(&self, agent: &mut A, training: bool) -> f64
+    /// RMS of the cart error, of the angle error and of the control, or the
+    /// number of steps before the pendulum falls.
+    fn simulate_agent<A>(&self, agent: &mut A, training: bool) -> Result<[f64; 3], usize>
     where
         A: Agent,
     {
@@ -126,7 +178,11 @@ impl InvertedPendulum {
             cum_squared_error_x += x_error.powf(2.);
             cum_squared_error_theta += theta_error.powf(2.);
 
-            let a = agent.step(&vec![vec![x * 10., x_dot, theta * 3., theta_dot]])[0][0];
+            let a = agent.step(&Matrix::from_vec(
+                1,
+                4,
+                vec![x * 10., x_dot, theta * 3., theta_dot],
+            ))[0][0];
             cum_squared_u += a.powf(2.);
 
             // Update state
@@ -156,38 +212,91 @@ impl InvertedPendulum {
             }
 
             if theta.abs() > PI / 2. || x_dot.abs() > 25. {
-                return step as f64;
+                return Err(step);
             }
         }
 
-        10000.
-            - (cum_squared_error_x / total_steps as f64).powf(0.5)
-            - (cum_squared_error_theta / total_steps as f64).powf(0.5)
-            - (cum_squared_u / total_steps as f64).powf(0.5)
+        Ok([
+            (cum_squared_error_x / total_steps as f64).powf(0.5),
+            (cum_squared_error_theta / total_steps as f64).powf(0.5),
+            (cum_squared_u / total_steps as f64).powf(0.5),
+        ])
     }
 }
 
+/// Run with `cargo run --example control_system -- cmaes` to train the gains with CMA-ES,
+/// or with `-- nsga2` to get the trade-offs between the errors and the control effort.
 pub fn main() {
-    let nb_generation: usize = 100;
-    let nb_individus: usize = 100;
-    let survivial_rate: f64 = 0.1;
-
-    let mutation_rate: f64 = 0.1;
-    let mutation_decay: f64 = 0.999;
-
-    let mut simulation = InvertedPendulum::new();
-    let mut population: Vec<Controller> = (0..nb_individus).map(|_| Controller::new()).collect();
-
-    population = training_from_checkpoint::<Controller, InvertedPendulum>(
-        population,
-        &mut simulation,
-        nb_individus,
-        nb_generation,
-        survivial_rate,
-        mutation_rate,
-        mutation_decay,
-    );
+    let mut rng = TrainingRng::seed_from_u64(SEED);
+    let mut simulation = InvertedPendulum::new(&mut rng);
+
+    let mut best_agent = if std::env::args().any(|arg| arg == "nsga2") {
+        let config = Nsga2Config::new()
+            .population_size(100)
+            .nb_generation(100)
+            .mutation_rate(0.1)
+            .reference_point(vec![-10.; 3])
+            .seed(SEED);
+        let mut trainer = Nsga2::new(config).unwrap().with_observer(ConsoleObserver);
+        let population: Vec<Controller> = (0..trainer.config().population_size)
+            .map(|_| Controller::new(&mut rng))
+            .collect();
+
+        let front = trainer
+            .train(population, &mut simulation)
+            .unwrap()
+            .pareto_front;
+        println!("Pareto front: cart error, angle error, control effort");
+        for (_, objectives) in front.iter() {
+            println!(
+                "{:.4} {:.4} {:.4}",
+                -objectives[0], -objectives[1], -objectives[2]
+            );
+        }
+        // The controller with the smallest angle error
+        front
+            .into_iter()
+            .max_by(|a, b| a.1[1].total_cmp(&b.1[1]))
+            .unwrap()
+            .0
+    } else if std::env::args().any(|arg| arg == "cmaes") {
+        let config = CmaEsConfig::new()
+            .sigma(0.5)
+            .nb_generation(300)
+            .restart(Restart::Ipop { max_restarts: 3 })
+            .seed(SEED);
+        let mut trainer = CmaEs::new(config).unwrap().with_observer(ConsoleObserver);
+
+        trainer
+            .train(Controller::new(&mut rng), &mut simulation)
+            .unwrap()
+            .best_agent
+    } else {
+        let config = TrainingConfig::new()
+            .nb_individus(100)
+            .nb_generation(100)
+            .survival_rate(0.1)
+            .mutation_rate(0.1)
+            .mutation_decay(0.999)
+            .seed(SEED);
+        let mut trainer = Trainer::new(config).unwrap().with_observer(ConsoleObserver);
+
+        let population: Vec<Controller> = (0..trainer.config().nb_individus)
+            .map(|_| Controller::new(&mut rng))
+            .collect();
+
+        trainer
+            .train(population, &mut simulation)
+            .unwrap()
+            .best_agent
+    };
 
     println!("\n\n\n");
-    simulation.simulate_agent(&mut population[0], false);
+    match simulation.simulate_agent(&mut best_agent, false) {
+        Ok([x_error, theta_error, effort]) => println!(
+            "RMS cart error: {:.4}m, angle error: {:.4}rad, control: {:.4}m/s²",
+            x_error, theta_error, effort
+        ),
+        Err(steps) => println!("The pendulum fell after {} steps", steps),
+    }
 }
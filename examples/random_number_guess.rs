@@ -1,9 +1,15 @@
 extern crate genetic_rl;
 
 use crate::genetic_rl::genetic_training::agent::Agent;
+use crate::genetic_rl::genetic_training::config::TrainingConfig;
+use crate::genetic_rl::genetic_training::observer::ConsoleObserver;
+use crate::genetic_rl::genetic_training::rng::TrainingRng;
 use crate::genetic_rl::genetic_training::simulation::Simulation;
-use crate::genetic_rl::genetic_training::training::training_from_checkpoint;
-use rand::Rng;
+use crate::genetic_rl::genetic_training::training::Trainer;
+use crate::genetic_rl::neuralnetwork::matrix::Matrix;
+use rand::{Rng, SeedableRng};
+
+const SEED: u64 = 42;
 
 #[derive(Clone)]
 struct TestAgent {
@@ -14,17 +20,16 @@ unsafe impl Send for TestAgent {}
 unsafe impl Sync for TestAgent {}
 
 impl Agent for TestAgent {
-
-    fn step(&mut self, _input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    fn step(&mut self, _input: &Matrix) -> Matrix {
         //println!("Guess: {}", self.guess);
-        vec![vec![self.guess]]
+        Matrix::from_vec(1, 1, vec![self.guess])
     }
 
     fn reset(&mut self) {}
 
-    fn mutate(&self, mutation_rate: f64) -> Self {
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
         let amplitude = mutation_rate * 10.;
-        let random_number = rand::thread_rng().gen_range(-amplitude..=amplitude);
+        let random_number = rng.gen_range(-amplitude..=amplitude);
         TestAgent {
             guess: self.guess + random_number,
         }
@@ -32,8 +37,8 @@ impl Agent for TestAgent {
 }
 
 impl TestAgent {
-    fn new() -> Self {
-        let random_number = rand::thread_rng().gen_range(-100.0..=100.0) as f64;
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let random_number: f64 = rng.gen_range(-100.0..=100.0);
         TestAgent {
             guess: random_number,
         }
@@ -52,22 +57,20 @@ impl Simulation for TestSimulation {
         A: Agent,
     {
         let mut agent = agent.clone();
-        let fitness = -(agent.step(&vec![])[0][0] - self.obs).abs();
-
-        fitness
+        -(agent.step(&Matrix::default())[0][0] - self.obs).abs()
     }
 
-    fn on_generation(&mut self, generation_number: usize) {
+    fn on_generation<R: Rng + ?Sized>(&mut self, generation_number: usize, rng: &mut R) {
         let ampl = 1. / (generation_number + 1) as f64;
-        let random_number = rand::thread_rng().gen_range(-ampl..=ampl);
+        let random_number = rng.gen_range(-ampl..=ampl);
         self.obs = self.target + random_number;
         println!("Generation observation: {}", self.obs);
     }
 }
 
 impl TestSimulation {
-    fn new() -> Self {
-        let random_number = rand::thread_rng().gen_range(-100.0..=100.0);
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let random_number = rng.gen_range(-100.0..=100.0);
         println!("Number to guess: {}", random_number);
         TestSimulation {
             target: random_number,
@@ -77,29 +80,30 @@ impl TestSimulation {
 }
 
 pub fn main() {
-    let nb_generation: usize = 10;
-    let nb_individus: usize = 100;
-    let survivial_rate: f64 = 0.1;
-
-    let mutation_rate: f64 = 1.;
-    let mutation_decay: f64 = 0.99;
-
-    let mut simulation = TestSimulation::new();
-
-    let mut population: Vec<TestAgent> = (0..nb_individus).map(|_| TestAgent::new()).collect();
-
-    population = training_from_checkpoint::<TestAgent, TestSimulation>(
-        population,
-        &mut simulation,
-        nb_individus,
-        nb_generation,
-        survivial_rate,
-        mutation_rate,
-        mutation_decay,
-    );
+    let config = TrainingConfig::new()
+        .nb_individus(100)
+        .nb_generation(10)
+        .survival_rate(0.1)
+        .mutation_rate(1.)
+        .mutation_decay(0.99)
+        .seed(SEED);
+    let mut trainer = Trainer::new(config).unwrap().with_observer(ConsoleObserver);
+
+    let mut rng = TrainingRng::seed_from_u64(SEED);
+    let mut simulation = TestSimulation::new(&mut rng);
+
+    let population: Vec<TestAgent> = (0..trainer.config().nb_individus)
+        .map(|_| TestAgent::new(&mut rng))
+        .collect();
+
+    let mut best_agent = trainer
+        .train(population, &mut simulation)
+        .unwrap()
+        .best_agent;
 
     println!(
         "\n\nFinal guess: {}\nTarget: {}",
-        population[0].to_owned().step(&vec![])[0][0], simulation.target
+        best_agent.step(&Matrix::default())[0][0],
+        simulation.target
     );
 }
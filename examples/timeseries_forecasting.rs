@@ -1,14 +1,22 @@
 extern crate genetic_rl;
 
-use serde_derive::{Serialize, Deserialize};
-use std::fs;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::genetic_rl::genetic_training::agent::Agent;
+use crate::genetic_rl::genetic_training::checkpoint::Checkpoint;
+use crate::genetic_rl::genetic_training::config::TrainingConfig;
+use crate::genetic_rl::genetic_training::observer::ConsoleObserver;
+use crate::genetic_rl::genetic_training::rng::TrainingRng;
 use crate::genetic_rl::genetic_training::simulation::Simulation;
-use crate::genetic_rl::genetic_training::training::training_from_checkpoint;
+use crate::genetic_rl::genetic_training::training::Trainer;
 use crate::genetic_rl::neuralnetwork::activation::tanh;
+use crate::genetic_rl::neuralnetwork::crossover::Crossover;
 use crate::genetic_rl::neuralnetwork::layer::{GRULayer, LinearLayer};
+use crate::genetic_rl::neuralnetwork::matrix::Matrix;
 use crate::genetic_rl::neuralnetwork::metrics::calculate_mse_time_series;
+use rand::{Rng, SeedableRng};
+
+const SEED: u64 = 42;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NeuralNet {
@@ -26,7 +34,7 @@ impl Agent for NeuralNet {
         self.layer2.reset();
     }
 
-    fn step(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    fn step(&mut self, input: &Matrix) -> Matrix {
         let mut output = self.layer1.forward(input);
 
         output = self.layer2.forward(&output);
@@ -35,29 +43,43 @@ impl Agent for NeuralNet {
         tanh(output)
     }
 
-    fn mutate(&self, mutation_rate: f64) -> Self {
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        NeuralNet {
+            layer1: self.layer1.mutate(mutation_rate, rng),
+            layer2: self.layer2.mutate(mutation_rate, rng),
+            layer3: self.layer3.mutate(mutation_rate, rng),
+        }
+    }
+
+    fn crossover<R: Rng + ?Sized>(&self, other: &Self, rng: &mut R) -> Self {
         NeuralNet {
-            layer1: self.layer1.mutate(mutation_rate),
-            layer2: self.layer2.mutate(mutation_rate),
-            layer3: self.layer3.mutate(mutation_rate),
+            layer1: self
+                .layer1
+                .crossover(&other.layer1, Crossover::Uniform, rng),
+            layer2: self
+                .layer2
+                .crossover(&other.layer2, Crossover::Uniform, rng),
+            layer3: self
+                .layer3
+                .crossover(&other.layer3, Crossover::Uniform, rng),
         }
     }
 }
 
 impl NeuralNet {
-    fn new() -> Self {
+    fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         NeuralNet {
-            layer1: GRULayer::new(1, 7, 1),
-            layer2: GRULayer::new(7, 5, 1),
-            layer3: LinearLayer::new(5, 1),
+            layer1: GRULayer::new_with_rng(1, 7, 1, rng),
+            layer2: GRULayer::new_with_rng(7, 5, 1, rng),
+            layer3: LinearLayer::new_with_rng(5, 1, rng),
         }
     }
 }
 
 #[derive(Clone)]
 struct Timeserie {
-    pub inputs: Vec<Vec<Vec<f64>>>,
-    targets: Vec<Vec<Vec<f64>>>,
+    pub inputs: Vec<Matrix>,
+    targets: Vec<Matrix>,
 }
 
 impl Simulation for Timeserie {
@@ -75,25 +97,17 @@ impl Simulation for Timeserie {
                 let _ = agent.step(input_i);
             });
 
-        let prediction: Vec<Vec<Vec<f64>>> = self
+        let prediction: Vec<Matrix> = self
             .inputs
             .iter()
             .skip(memory_loading_steps)
             .map(|input_i| agent.step(input_i))
             .collect();
 
-        -calculate_mse_time_series(
-            &self
-                .targets
-                .iter()
-                .skip(memory_loading_steps)
-                .map(|x| x.iter().map(|y| y.iter().map(|z| *z).collect()).collect())
-                .collect(),
-            &prediction,
-        )
+        -calculate_mse_time_series(&self.targets[memory_loading_steps..], &prediction)
     }
 
-    fn on_generation(&mut self, _generation_number: usize) {}
+    fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
 }
 
 impl Timeserie {
@@ -114,23 +128,22 @@ impl Timeserie {
             inputs: timeserie
                 .iter()
                 .take(timeserie.len() - 1)
-                .map(|x| vec![vec![*x]])
+                .map(|x| Matrix::from_vec(1, 1, vec![*x]))
+                .collect(),
+            targets: timeserie
+                .iter()
+                .skip(1)
+                .map(|x| Matrix::from_vec(1, 1, vec![*x]))
                 .collect(),
-            targets: timeserie.iter().skip(1).map(|x| vec![vec![*x]]).collect(),
         }
     }
 
-    pub fn forecast<A>(
-        &self,
-        agent: &mut A,
-        horizon: usize,
-        current_input: &Vec<Vec<f64>>,
-    ) -> Vec<Vec<Vec<f64>>>
+    pub fn forecast<A>(&self, agent: &mut A, horizon: usize, current_input: &Matrix) -> Vec<Matrix>
     where
         A: Agent,
     {
-        let mut forecast: Vec<Vec<Vec<f64>>> = vec![];
-        let mut last_prediction: Vec<Vec<f64>> = current_input.clone();
+        let mut forecast: Vec<Matrix> = vec![];
+        let mut last_prediction: Matrix = current_input.clone();
 
         (0..horizon).for_each(|_| {
             last_prediction = agent.step(&last_prediction);
@@ -152,7 +165,7 @@ impl Timeserie {
 
         let forecast = self.forecast(agent, horizon, &self.inputs[forecast_start + 1]);
 
-        let targets: Vec<Vec<Vec<f64>>> = self
+        let targets: Vec<Matrix> = self
             .inputs
             .iter()
             .skip(forecast_start)
@@ -176,35 +189,41 @@ impl Timeserie {
 }
 
 pub fn main() {
-    let nb_generation: usize = 100;
-    let nb_individus: usize = 1000;
-    let survivial_rate: f64 = 0.02;
+    let config = TrainingConfig::new()
+        .nb_individus(1000)
+        .nb_generation(100)
+        .survival_rate(0.02)
+        .mutation_rate(1.5)
+        .mutation_decay(0.998)
+        .crossover_rate(0.3)
+        .seed(SEED);
+    let mut trainer = Trainer::new(config).unwrap().with_observer(ConsoleObserver);
+
+    let mut rng = TrainingRng::seed_from_u64(SEED);
+    let mut simulation = Timeserie::new();
+    let population: Vec<NeuralNet> = (0..trainer.config().nb_individus)
+        .map(|_| NeuralNet::new(&mut rng))
+        .collect();
 
-    let mutation_rate: f64 = 1.5;
-    let mutation_decay: f64 = 0.998;
+    // An interrupted run restarts from its last checkpoint
+    let checkpoint_path = "timeseries_checkpoint.json";
+    let checkpoint =
+        Checkpoint::load(checkpoint_path).unwrap_or_else(|_| Checkpoint::new(population));
 
-    let mut simulation = Timeserie::new();
-    let mut population: Vec<NeuralNet> = (0..nb_individus).map(|_| NeuralNet::new()).collect();
-
-    population = training_from_checkpoint::<NeuralNet, Timeserie>(
-        population,
-        &mut simulation,
-        nb_individus,
-        nb_generation,
-        survivial_rate,
-        mutation_rate,
-        mutation_decay,
-    );
+    let mut best_agent = trainer
+        .train_with_checkpoints(checkpoint, &mut simulation, checkpoint_path)
+        .unwrap()
+        .best_agent;
 
     let mut sim = Timeserie::new();
     let horizon: usize = 100;
-    sim.test_forecast(&mut population[0], horizon, 20);
-    sim.test_forecast(&mut population[0], horizon, 30);
-    sim.test_forecast(&mut population[0], horizon, 40);
-    sim.test_forecast(&mut population[0], horizon, 50);
-    sim.test_forecast(&mut population[0], horizon, 60);
+    sim.test_forecast(&mut best_agent, horizon, 20);
+    sim.test_forecast(&mut best_agent, horizon, 30);
+    sim.test_forecast(&mut best_agent, horizon, 40);
+    sim.test_forecast(&mut best_agent, horizon, 50);
+    sim.test_forecast(&mut best_agent, horizon, 60);
 
-    // let best_agent_json = serde_json::to_string(&population[0]).unwrap();
+    // let best_agent_json = serde_json::to_string(&best_agent).unwrap();
     // if let Err(err) = fs::write("best_agent.json", &best_agent_json) {
     //     eprintln!("Error writing to file: {}", err);
     // } else {
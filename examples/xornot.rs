@@ -1,54 +1,30 @@
 extern crate genetic_rl;
 
-use crate::genetic_rl::genetic_training::agent::Agent;
+use crate::genetic_rl::genetic_training::agent::{Agent, AgentBatch};
+use crate::genetic_rl::genetic_training::config::TrainingConfig;
+use crate::genetic_rl::genetic_training::observer::ConsoleObserver;
+use crate::genetic_rl::genetic_training::rng::TrainingRng;
 use crate::genetic_rl::genetic_training::simulation::Simulation;
-use crate::genetic_rl::genetic_training::training::training_from_checkpoint;
-use crate::genetic_rl::neuralnetwork::activation::{relu, sigmoid};
-use crate::genetic_rl::neuralnetwork::layer::LinearLayer;
+use crate::genetic_rl::genetic_training::training::Trainer;
+use crate::genetic_rl::neuralnetwork::initializer::Initializer;
+use crate::genetic_rl::neuralnetwork::matrix::Matrix;
 use crate::genetic_rl::neuralnetwork::metrics::calculate_mse;
-
-#[derive(Clone)]
-pub struct NeuralNet {
-    layer1: LinearLayer,
-    layer2: LinearLayer,
-}
-
-unsafe impl Send for NeuralNet {}
-unsafe impl Sync for NeuralNet {}
-
-impl Agent for NeuralNet {
-
-    fn reset(&mut self) {}
-
-    fn step(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-        let mut output = self.layer1.forward(input);
-        output = relu(output);
-
-        output = self.layer2.forward(&output);
-        sigmoid(output)
-    }
-
-    fn mutate(&self, mutation_rate: f64) -> Self {
-        NeuralNet {
-            layer1: self.layer1.mutate(mutation_rate),
-            layer2: self.layer2.mutate(mutation_rate),
-        }
-    }
-}
-
-impl NeuralNet {
-    fn new() -> Self {
-        NeuralNet {
-            layer1: LinearLayer::new(2, 2),
-            layer2: LinearLayer::new(2, 1),
-        }
-    }
+use crate::genetic_rl::neuralnetwork::parameters::Parameters;
+use crate::genetic_rl::neuralnetwork::sequential::Sequential;
+use rand::{Rng, SeedableRng};
+
+fn neural_net<R: Rng + ?Sized>(rng: &mut R) -> Sequential {
+    Sequential::new()
+        .linear_with_initializer(2, 2, &Initializer::HeUniform, rng)
+        .relu()
+        .linear_with_initializer(2, 1, &Initializer::XavierUniform, rng)
+        .sigmoid()
 }
 
 #[derive(Clone)]
 struct XorNot {
-    pub inputs: Vec<Vec<f64>>,
-    targets: Vec<Vec<f64>>,
+    pub inputs: Matrix,
+    targets: Matrix,
 }
 
 impl Simulation for XorNot {
@@ -61,43 +37,62 @@ impl Simulation for XorNot {
         -calculate_mse(&self.targets, &prediction)
     }
 
-    fn on_generation(&mut self, _generation_number: usize) {}
+    fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+
+    /// Every network gets the same inputs, the whole batch runs as a few large products.
+    fn evaluate_batch(&self, batch: &mut dyn AgentBatch) -> Option<Vec<f64>> {
+        let nb_agents = batch.nb_agents();
+        let predictions = batch.step(&self.inputs);
+        let nb_rows = self.targets.rows();
+
+        Some(
+            (0..nb_agents)
+                .map(|i| {
+                    let rows = predictions.as_slice()[i * nb_rows..(i + 1) * nb_rows].to_vec();
+                    -calculate_mse(&self.targets, &Matrix::from_vec(nb_rows, 1, rows))
+                })
+                .collect(),
+        )
+    }
 }
 
 impl XorNot {
     fn new() -> Self {
         XorNot {
-            inputs: vec![vec![0., 0.], vec![1., 0.], vec![0., 1.], vec![1., 1.]],
-            targets: vec![vec![0.], vec![1.], vec![1.], vec![0.]],
+            inputs: Matrix::from(vec![vec![0., 0.], vec![1., 0.], vec![0., 1.], vec![1., 1.]]),
+            targets: Matrix::from(vec![vec![0.], vec![1.], vec![1.], vec![0.]]),
         }
     }
 }
 
 pub fn main() {
-    let nb_generation: usize = 1000;
-    let nb_individus: usize = 100;
-    let survivial_rate: f64 = 0.05;
-
-    let mutation_rate: f64 = 0.3;
-    let mutation_decay: f64 = 0.999;
-
+    let config = TrainingConfig::new()
+        .nb_individus(100)
+        .nb_generation(1000)
+        .survival_rate(0.05)
+        .mutation_rate(0.3)
+        .mutation_decay(0.999)
+        .batch_evaluation(25)
+        .seed(42);
+    let mut trainer = Trainer::new(config).unwrap().with_observer(ConsoleObserver);
+
+    // Same seed for the initial population and the training gives a reproducible run
+    let mut rng = TrainingRng::seed_from_u64(42);
     let mut simulation = XorNot::new();
-    let mut population: Vec<NeuralNet> = (0..nb_individus).map(|_| NeuralNet::new()).collect();
+    let population: Vec<Sequential> = (0..trainer.config().nb_individus)
+        .map(|_| neural_net(&mut rng))
+        .collect();
 
-    population = training_from_checkpoint::<NeuralNet, XorNot>(
-        population,
-        &mut simulation,
-        nb_individus,
-        nb_generation,
-        survivial_rate,
-        mutation_rate,
-        mutation_decay,
-    );
+    let mut best_agent = trainer
+        .train(population, &mut simulation)
+        .unwrap()
+        .best_agent;
 
-    println!("Layer 1 weights: {:?}", population[0].layer1.weights);
-    println!("Layer 1 bias: {:?}", population[0].layer1.bias);
-    println!("Layer 2 weights: {:?}", population[0].layer2.weights);
-    println!("Layer 2 biass: {:?}", population[0].layer2.bias);
+    println!("{}", best_agent.summary());
+    println!("Parameters: {:?}", best_agent.to_flat_vec());
 
-    println!("\nPredictions: {:?}", population[0].step(&simulation.targets));
+    println!(
+        "\nPredictions: {:?}",
+        best_agent.step(&simulation.inputs).to_rows()
+    );
 }
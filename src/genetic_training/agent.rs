@@ -1,5 +1,48 @@
+use rand::Rng;
+
+use crate::neuralnetwork::matrix::Matrix;
+
 pub trait Agent: Clone + Send + Sync + 'static {
-    fn step(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>>;
+    /// `input` has one row per sample of the batch.
+    fn step(&mut self, input: &Matrix) -> Matrix;
+    fn reset(&mut self);
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self;
+
+    /// Recombines two parents, the default implementation doesn't support
+    /// crossover and returns a copy of `self`.
+    fn crossover<R: Rng + ?Sized>(&self, _other: &Self, _rng: &mut R) -> Self {
+        self.clone()
+    }
+
+    /// Distance between two agents, used to group them in species when
+    /// `TrainingConfig::speciation` is set. Returns `None` by default, see
+    /// `parameter_distance` for agents implementing `Parameters`.
+    fn distance(&self, _other: &Self) -> Option<f64> {
+        None
+    }
+
+    /// Stacks structurally identical agents so that a `Simulation` can
+    /// evaluate them together, see `Simulation::evaluate_batch`. Returns `None`
+    /// when the agents can't be stacked, which is always the case by default.
+    fn stack(_agents: &[Self]) -> Option<Box<dyn AgentBatch>> {
+        None
+    }
+}
+
+/// Agents stacked by `Agent::stack`, stepped together.
+///
+/// The outputs hold the rows of every agent one after the other: with `b`
+/// rows per agent, the agent `i` owns the rows `i * b..(i + 1) * b`.
+pub trait AgentBatch {
+    fn nb_agents(&self) -> usize;
+
+    /// Feeds the same `input` to every agent. The output is borrowed from
+    /// the batch, which reuses it at the next step.
+    fn step(&mut self, input: &Matrix) -> &Matrix;
+
+    /// Feeds every agent its own input, `inputs` holds the rows of every
+    /// agent one after the other.
+    fn step_each(&mut self, inputs: &Matrix) -> &Matrix;
+
     fn reset(&mut self);
-    fn mutate(&self, mutation_rate: f64) -> Self;
 }
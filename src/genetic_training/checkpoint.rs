@@ -0,0 +1,80 @@
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::speciation::Species;
+use crate::genetic_training::stats::GenerationStats;
+
+/// State of a training run between two generations.
+///
+/// The simulation isn't stored: on resume, `Simulation::on_generation` is
+/// replayed with the random streams of the generations already run, which
+/// restores the state of any simulation that only depends on them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint<A> {
+    /// Next generation to run, 0 for a checkpoint that hasn't been trained yet.
+    pub generation: usize,
+    /// Mutation rate of the next generation, `None` until the training starts.
+    /// On resume, the decay schedule continues from it rather than from the
+    /// `mutation_rate` of the config.
+    pub mutation_rate: Option<f64>,
+    /// Seed of the run, `None` until the training starts.
+    pub seed: Option<u64>,
+    pub nb_simulations: usize,
+    pub population: Vec<A>,
+    #[serde(default = "Vec::new")]
+    pub hall_of_fame: Vec<(A, f64)>,
+    #[serde(default = "Vec::new")]
+    pub history: Vec<GenerationStats>,
+    /// Species of the last generation, empty without speciation.
+    #[serde(default = "Vec::new")]
+    pub species: Vec<Species<A>>,
+    #[serde(default)]
+    pub next_species_id: usize,
+}
+
+impl<A> Checkpoint<A> {
+    /// Checkpoint from a population of random or pre-trained agents.
+    pub fn new(population: Vec<A>) -> Self {
+        Checkpoint {
+            generation: 0,
+            mutation_rate: None,
+            seed: None,
+            nb_simulations: 0,
+            population,
+            hall_of_fame: Vec::new(),
+            history: Vec::new(),
+            species: Vec::new(),
+            next_species_id: 0,
+        }
+    }
+}
+
+impl<A> Checkpoint<A>
+where
+    A: Serialize,
+{
+    /// The file is written next to `path` and then renamed, so an interrupted
+    /// write never corrupts the previous checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TrainingError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl<A> Checkpoint<A>
+where
+    A: DeserializeOwned,
+{
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TrainingError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
@@ -0,0 +1,555 @@
+//! Covariance Matrix Adaptation Evolution Strategy, following "The CMA
+//! Evolution Strategy: A Tutorial" (Hansen, 2016).
+//!
+//! CMA-ES searches the flat parameter vector of an agent, see `Parameters`.
+//! It is much more sample-efficient than the genetic training on small
+//! continuous search spaces, e.g. a few controller gains, but its cost grows
+//! with the square of the number of parameters.
+
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::genetic_training::agent::Agent;
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::TrainingObserver;
+use crate::genetic_training::result::TrainingResult;
+use crate::genetic_training::rng::{stream_rng, RngStream};
+use crate::genetic_training::run::{run_options_builders, RunLoop, RunOptions};
+use crate::genetic_training::simulation::Simulation;
+use crate::genetic_training::stats::GenerationStats;
+use crate::genetic_training::stop::StopReason;
+use crate::genetic_training::training::{run_generation, update_hall_of_fame};
+use crate::neuralnetwork::matrix::Matrix;
+use crate::neuralnetwork::parameters::Parameters;
+
+/// What `CmaEs` does once the search has converged.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Restart {
+    /// Stops the training with `StopReason::Converged`.
+    Never,
+    /// Restarts from the initial agent with a population twice as large every
+    /// time (IPOP-CMA-ES).
+    Ipop { max_restarts: usize },
+    /// Alternates the IPOP restarts with runs of a small population and a
+    /// smaller step size, whichever regime used fewer evaluations so far
+    /// (BIPOP-CMA-ES).
+    Bipop { max_restarts: usize },
+}
+
+/// Hyper-parameters of a `CmaEs` run.
+///
+/// Every field has a default, the population size defaults to `4 + 3 ln(n)`
+/// for `n` parameters. The generations of every restart count towards
+/// `run.nb_generation`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CmaEsConfig {
+    /// Initial step size, in the units of the parameters.
+    pub sigma: f64,
+    /// Number of candidates per generation before any restart.
+    pub population_size: Option<usize>,
+    #[serde(flatten)]
+    pub run: RunOptions,
+    pub restart: Restart,
+    /// The search has converged when the best fitness of the recent
+    /// generations, and the fitness of the last one, vary by less than this.
+    pub tol_fun: f64,
+    /// The search has converged when the steps in every direction are smaller than this.
+    pub tol_x: f64,
+}
+
+impl Default for CmaEsConfig {
+    fn default() -> Self {
+        CmaEsConfig {
+            sigma: 0.5,
+            population_size: None,
+            run: RunOptions::default(),
+            restart: Restart::Never,
+            tol_fun: 1e-12,
+            tol_x: 1e-12,
+        }
+    }
+}
+
+impl CmaEsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sigma(mut self, sigma: f64) -> Self {
+        self.sigma = sigma;
+        self
+    }
+
+    pub fn population_size(mut self, population_size: usize) -> Self {
+        self.population_size = Some(population_size);
+        self
+    }
+
+    run_options_builders!();
+
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    pub fn tol_fun(mut self, tol_fun: f64) -> Self {
+        self.tol_fun = tol_fun;
+        self
+    }
+
+    pub fn tol_x(mut self, tol_x: f64) -> Self {
+        self.tol_x = tol_x;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if !(self.sigma.is_finite() && self.sigma > 0.) {
+            return invalid(format!("sigma must be greater than 0, got {}", self.sigma));
+        }
+        if self.population_size.is_some_and(|size| size < 2) {
+            return invalid("population_size must be at least 2".to_string());
+        }
+        if !(self.tol_fun >= 0. && self.tol_x >= 0.) {
+            return invalid("tol_fun and tol_x must be non-negative".to_string());
+        }
+
+        self.run.validate()
+    }
+}
+
+/// State of one CMA-ES run, between two restarts.
+struct Search {
+    lambda: usize,
+    weights: Vec<f64>,
+    mu_eff: f64,
+    c_c: f64,
+    c_sigma: f64,
+    c_1: f64,
+    c_mu: f64,
+    damping: f64,
+    /// Expected norm of a standard normal vector.
+    chi_n: f64,
+
+    mean: Vec<f64>,
+    sigma: f64,
+    covariance: Matrix,
+    /// Eigenvectors of the covariance, as columns.
+    eigenvectors: Matrix,
+    /// Square roots of the eigenvalues of the covariance.
+    scales: Vec<f64>,
+    inv_sqrt_covariance: Matrix,
+    path_c: Vec<f64>,
+    path_sigma: Vec<f64>,
+
+    generation: usize,
+    eigen_generation: usize,
+    best_history: Vec<f64>,
+    last_fitness_range: f64,
+}
+
+impl Search {
+    fn new(mean: Vec<f64>, sigma: f64, lambda: usize) -> Self {
+        let n = mean.len() as f64;
+        let mu = lambda / 2;
+        let mut weights: Vec<f64> = (0..mu)
+            .map(|i| (lambda as f64 / 2. + 0.5).ln() - ((i + 1) as f64).ln())
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= sum);
+        let mu_eff = 1. / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let c_sigma = (mu_eff + 2.) / (n + mu_eff + 5.);
+        let c_1 = 2. / ((n + 1.3).powi(2) + mu_eff);
+
+        Search {
+            lambda,
+            c_c: (4. + mu_eff / n) / (n + 4. + 2. * mu_eff / n),
+            c_sigma,
+            c_1,
+            c_mu: (1. - c_1).min(2. * (mu_eff - 2. + 1. / mu_eff) / ((n + 2.).powi(2) + mu_eff)),
+            damping: 1. + 2. * (((mu_eff - 1.) / (n + 1.)).sqrt() - 1.).max(0.) + c_sigma,
+            chi_n: n.sqrt() * (1. - 1. / (4. * n) + 1. / (21. * n * n)),
+            weights,
+            mu_eff,
+            sigma,
+            covariance: identity(mean.len()),
+            eigenvectors: identity(mean.len()),
+            scales: vec![1.; mean.len()],
+            inv_sqrt_covariance: identity(mean.len()),
+            path_c: vec![0.; mean.len()],
+            path_sigma: vec![0.; mean.len()],
+            mean,
+            generation: 0,
+            eigen_generation: 0,
+            best_history: Vec::new(),
+            last_fitness_range: f64::INFINITY,
+        }
+    }
+
+    fn mu(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// `mean + sigma * B * D * z` for `lambda` standard normal vectors `z`.
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<Vec<f64>> {
+        let n = self.mean.len();
+        (0..self.lambda)
+            .map(|_| {
+                let z: Vec<f64> = (0..n)
+                    .map(|j| self.scales[j] * rng.sample::<f64, _>(StandardNormal))
+                    .collect();
+                (0..n)
+                    .map(|i| {
+                        let y: f64 = (0..n).map(|j| self.eigenvectors[(i, j)] * z[j]).sum();
+                        self.mean[i] + self.sigma * y
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// `order` ranks the candidates from best to worst, `fitness` is sorted the same way.
+    fn update(&mut self, candidates: &[Vec<f64>], order: &[usize], fitness: &[f64]) {
+        let n = self.mean.len();
+        let old_mean = std::mem::take(&mut self.mean);
+        let steps: Vec<Vec<f64>> = order[..self.mu()]
+            .iter()
+            .map(|&k| {
+                candidates[k]
+                    .iter()
+                    .zip(old_mean.iter())
+                    .map(|(x, m)| (x - m) / self.sigma)
+                    .collect()
+            })
+            .collect();
+        let mean_step: Vec<f64> = (0..n)
+            .map(|i| {
+                self.weights
+                    .iter()
+                    .zip(steps.iter())
+                    .map(|(w, y)| w * y[i])
+                    .sum()
+            })
+            .collect();
+        self.mean = (0..n)
+            .map(|i| old_mean[i] + self.sigma * mean_step[i])
+            .collect();
+
+        // Evolution paths
+        self.generation += 1;
+        let coef = (self.c_sigma * (2. - self.c_sigma) * self.mu_eff).sqrt();
+        for i in 0..n {
+            let whitened: f64 = (0..n)
+                .map(|j| self.inv_sqrt_covariance[(i, j)] * mean_step[j])
+                .sum();
+            self.path_sigma[i] = (1. - self.c_sigma) * self.path_sigma[i] + coef * whitened;
+        }
+        let norm_sigma = norm(&self.path_sigma);
+        let h_sigma = norm_sigma
+            / (1. - (1. - self.c_sigma).powi(2 * self.generation as i32)).sqrt()
+            / self.chi_n
+            < 1.4 + 2. / (n as f64 + 1.);
+        let coef = (self.c_c * (2. - self.c_c) * self.mu_eff).sqrt();
+        for (path, step) in self.path_c.iter_mut().zip(mean_step.iter()) {
+            *path = (1. - self.c_c) * *path + if h_sigma { coef * step } else { 0. };
+        }
+
+        // Rank-one and rank-mu updates of the covariance
+        let decay = 1. - self.c_1 - self.c_mu
+            + if h_sigma {
+                0.
+            } else {
+                self.c_1 * self.c_c * (2. - self.c_c)
+            };
+        for i in 0..n {
+            for j in 0..=i {
+                let rank_mu: f64 = self
+                    .weights
+                    .iter()
+                    .zip(steps.iter())
+                    .map(|(w, y)| w * y[i] * y[j])
+                    .sum();
+                let value = decay * self.covariance[(i, j)]
+                    + self.c_1 * self.path_c[i] * self.path_c[j]
+                    + self.c_mu * rank_mu;
+                self.covariance[(i, j)] = value;
+                self.covariance[(j, i)] = value;
+            }
+        }
+
+        self.sigma *= ((self.c_sigma / self.damping) * (norm_sigma / self.chi_n - 1.)).exp();
+
+        // The decomposition is O(n^3), it's only refreshed when the covariance
+        // has changed enough
+        let period = self.lambda as f64 / (self.c_1 + self.c_mu) / n as f64 / 10.;
+        if (self.generation - self.eigen_generation) as f64 > period {
+            self.update_eigen();
+        }
+
+        self.best_history.push(fitness[0]);
+        let finite: Vec<f64> = fitness.iter().cloned().filter(|f| f.is_finite()).collect();
+        self.last_fitness_range = match (finite.first(), finite.last()) {
+            (Some(best), Some(worst)) if finite.len() == fitness.len() => best - worst,
+            _ => f64::INFINITY,
+        };
+    }
+
+    fn update_eigen(&mut self) {
+        let n = self.mean.len();
+        self.eigen_generation = self.generation;
+
+        let (eigenvalues, eigenvectors) = symmetric_eigen(&self.covariance);
+        self.scales = eigenvalues.iter().map(|v| v.max(1e-300).sqrt()).collect();
+        self.eigenvectors = eigenvectors;
+        for i in 0..n {
+            for j in 0..n {
+                self.inv_sqrt_covariance[(i, j)] = (0..n)
+                    .map(|k| self.eigenvectors[(i, k)] * self.eigenvectors[(j, k)] / self.scales[k])
+                    .sum();
+            }
+        }
+    }
+
+    fn converged(&self, tol_fun: f64, tol_x: f64) -> bool {
+        let n = self.mean.len();
+        let window = 10 + (30. * n as f64 / self.lambda as f64).ceil() as usize;
+
+        let stalled = self.best_history.len() >= window && {
+            let recent = &self.best_history[self.best_history.len() - window..];
+            let max = recent.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let min = recent.iter().cloned().fold(f64::INFINITY, f64::min);
+            max - min < tol_fun && self.last_fitness_range < tol_fun
+        };
+        let tiny_steps = (0..n).all(|i| {
+            self.sigma * self.covariance[(i, i)].sqrt() < tol_x
+                && self.sigma * self.path_c[i].abs() < tol_x
+        });
+        let max_scale = self.scales.iter().cloned().fold(0., f64::max);
+        let min_scale = self.scales.iter().cloned().fold(f64::INFINITY, f64::min);
+        let ill_conditioned = (max_scale / min_scale).powi(2) > 1e14;
+        let diverged = !self.sigma.is_finite() || self.mean.iter().any(|m| !m.is_finite());
+
+        stalled || tiny_steps || ill_conditioned || diverged
+    }
+}
+
+fn identity(n: usize) -> Matrix {
+    let mut matrix = Matrix::zeros(n, n);
+    (0..n).for_each(|i| matrix[(i, i)] = 1.);
+    matrix
+}
+
+fn norm(v: &[f64]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+/// Eigenvalues and eigenvectors, as columns, of a symmetric matrix with the
+/// cyclic Jacobi method.
+fn symmetric_eigen(matrix: &Matrix) -> (Vec<f64>, Matrix) {
+    let n = matrix.rows();
+    let mut a = matrix.clone();
+    let mut v = identity(n);
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|p| (p + 1..n).map(move |q| (p, q)))
+            .map(|(p, q)| a[(p, q)] * a[(p, q)])
+            .sum();
+        let diagonal: f64 = (0..n).map(|i| a[(i, i)] * a[(i, i)]).sum();
+        if off_diagonal <= 1e-30 * diagonal {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[(p, q)] == 0. {
+                    continue;
+                }
+                let theta = (a[(q, q)] - a[(p, p)]) / (2. * a[(p, q)]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                    a[(k, p)] = c * akp - s * akq;
+                    a[(k, q)] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                    a[(p, k)] = c * apk - s * aqk;
+                    a[(q, k)] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[(k, p)], v[(k, q)]);
+                    v[(k, p)] = c * vkp - s * vkq;
+                    v[(k, q)] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[(i, i)]).collect(), v)
+}
+
+/// CMA-ES trainer over the flat parameters of an agent.
+///
+/// It uses the same `Simulation`, observers and `TrainingResult` as the
+/// `Trainer`. `GenerationStats::mutation_rate` holds the step size of the
+/// generation and `survivors_fitness` the fitness of the candidates that
+/// moved the mean.
+pub struct CmaEs {
+    config: CmaEsConfig,
+    observers: Vec<Box<dyn TrainingObserver>>,
+}
+
+impl CmaEs {
+    pub fn new(config: CmaEsConfig) -> Result<Self, TrainingError> {
+        config.validate()?;
+        Ok(CmaEs {
+            config,
+            observers: Vec::new(),
+        })
+    }
+
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: TrainingObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn config(&self) -> &CmaEsConfig {
+        &self.config
+    }
+
+    /// Searches around the parameters of `agent`, every restart starts from them again.
+    pub fn train<A, S>(
+        &mut self,
+        agent: A,
+        simulation: &mut S,
+    ) -> Result<TrainingResult<A>, TrainingError>
+    where
+        A: Agent + Parameters,
+        S: Simulation,
+    {
+        let config = &self.config;
+        let initial_mean = agent.to_flat_vec();
+        if initial_mean.is_empty() {
+            return Err(TrainingError::InvalidConfig(
+                "the agent has no parameter to optimise".to_string(),
+            ));
+        }
+
+        let mut run = RunLoop::new(&config.run, &mut self.observers, config.run.seed);
+        let seed = run.seed();
+        let default_lambda = config
+            .population_size
+            .unwrap_or(4 + (3. * (initial_mean.len() as f64).ln()) as usize);
+        let mut search = Search::new(initial_mean.clone(), config.sigma, default_lambda);
+
+        // Budgets of the two BIPOP regimes, the first run counts as a large one
+        let mut large_lambda = default_lambda;
+        let (mut large_evaluations, mut small_evaluations) = (0, 0);
+        let mut small_regime = false;
+        let mut nb_restarts = 0;
+
+        let mut hall_of_fame: Vec<(A, f64)> = Vec::new();
+        let mut history: Vec<GenerationStats> = Vec::new();
+        let mut last_generation: Vec<(A, f64)> = Vec::new();
+        let mut nb_simulations = 0;
+
+        for gen in 0..config.run.nb_generation {
+            run.start_generation(gen, search.sigma)?;
+
+            let candidates = search.sample(&mut stream_rng(seed, gen, 0, RngStream::Mutation));
+            let population: Vec<A> = candidates
+                .iter()
+                .map(|params| {
+                    let mut candidate = agent.clone();
+                    candidate.from_flat_vec(params);
+                    candidate.reset();
+                    candidate
+                })
+                .collect();
+            nb_simulations += population.len();
+            if small_regime {
+                small_evaluations += population.len();
+            } else {
+                large_evaluations += population.len();
+            }
+
+            let evaluated = run_generation(population, Arc::new(simulation.clone()), None);
+            update_hall_of_fame(&mut hall_of_fame, &evaluated, config.run.hall_of_fame_size);
+
+            // Non finite fitness values rank last
+            let fitness: Vec<f64> = evaluated
+                .iter()
+                .map(|res| {
+                    if res.1.is_finite() {
+                        res.1
+                    } else {
+                        f64::NEG_INFINITY
+                    }
+                })
+                .collect();
+            let nb_non_finite = evaluated.iter().filter(|res| !res.1.is_finite()).count();
+            let mut order: Vec<usize> = (0..fitness.len()).collect();
+            order.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+            let sorted_fitness: Vec<f64> = order.iter().map(|&i| fitness[i]).collect();
+
+            let stats = GenerationStats::new(
+                gen,
+                search.sigma,
+                (evaluated.len(), nb_non_finite),
+                &sorted_fitness,
+                sorted_fitness[..search.mu()].to_vec(),
+            );
+            search.update(&candidates, &order, &sorted_fitness);
+            last_generation = order.iter().map(|&i| evaluated[i].clone()).collect();
+
+            simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+
+            if run.end_generation(stats, &mut history, nb_simulations)? {
+                break;
+            }
+
+            if search.converged(config.tol_fun, config.tol_x) {
+                let max_restarts = match config.restart {
+                    Restart::Never => 0,
+                    Restart::Ipop { max_restarts } | Restart::Bipop { max_restarts } => {
+                        max_restarts
+                    }
+                };
+                if nb_restarts >= max_restarts {
+                    run.stop(StopReason::Converged);
+                    break;
+                }
+                nb_restarts += 1;
+
+                let mut rng = stream_rng(seed, gen, 0, RngStream::Restart);
+                small_regime = matches!(config.restart, Restart::Bipop { .. })
+                    && small_evaluations < large_evaluations;
+                search = if small_regime {
+                    let (u_lambda, u_sigma): (f64, f64) = (rng.gen(), rng.gen());
+                    let ratio = large_lambda as f64 / default_lambda as f64 / 2.;
+                    let lambda = (default_lambda as f64 * ratio.powf(u_lambda * u_lambda)) as usize;
+                    let sigma = config.sigma * 10f64.powf(-2. * u_sigma);
+                    Search::new(initial_mean.clone(), sigma, lambda.max(2))
+                } else {
+                    large_lambda *= 2;
+                    Search::new(initial_mean.clone(), config.sigma, large_lambda)
+                };
+            }
+        }
+
+        let summary = run.finish(default_lambda, history.len(), nb_simulations)?;
+        TrainingResult::new(hall_of_fame, last_generation, history, summary)
+    }
+}
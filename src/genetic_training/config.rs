@@ -0,0 +1,228 @@
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::run::{run_options_builders, RunOptions};
+use crate::genetic_training::speciation::Speciation;
+
+/// What to do with individuals whose fitness is NaN or infinite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NonFinitePolicy {
+    /// Rank them after every other individual.
+    #[default]
+    Worst,
+    /// Remove them from the generation before selection.
+    Drop,
+    /// Stop the training with `TrainingError::NonFiniteFitness`.
+    Fail,
+}
+
+/// Hyper-parameters of a genetic training run.
+///
+/// Every field has a default, so a config file only needs to list the values
+/// that differ from it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    /// Population size evaluated at every generation.
+    pub nb_individus: usize,
+    #[serde(flatten)]
+    pub run: RunOptions,
+    /// Fraction of the population selected as parents of the next generation.
+    pub survival_rate: f64,
+    /// Mutation rate of the first generation.
+    pub mutation_rate: f64,
+    /// Multiplicative decay applied to the mutation rate at every generation.
+    pub mutation_decay: f64,
+    /// Probability that a child is the crossover of two survivors before being mutated.
+    pub crossover_rate: f64,
+    /// The first generation is oversampled to `nb_individus * initial_population_multiplier`.
+    pub initial_population_multiplier: usize,
+    /// Number of survivors copied unchanged into the next generation.
+    /// `None` keeps every survivor.
+    pub elitism: Option<usize>,
+    pub non_finite_fitness: NonFinitePolicy,
+    /// Period, in generations, of the checkpoints written by `Trainer::train_with_checkpoints`.
+    pub checkpoint_every: usize,
+    /// Evaluates the population in stacks of this many agents with
+    /// `Simulation::evaluate_batch`. `None` evaluates the agents one by one.
+    pub batch_evaluation: Option<usize>,
+    /// Groups the population in species that share the parents, see
+    /// `Speciation`. `None` selects the parents in the whole population.
+    pub speciation: Option<Speciation>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        TrainingConfig {
+            nb_individus: 100,
+            run: RunOptions::default(),
+            survival_rate: 0.1,
+            mutation_rate: 0.1,
+            mutation_decay: 0.999,
+            crossover_rate: 0.,
+            initial_population_multiplier: 10,
+            elitism: None,
+            non_finite_fitness: NonFinitePolicy::Worst,
+            checkpoint_every: 10,
+            batch_evaluation: None,
+            speciation: None,
+        }
+    }
+}
+
+impl TrainingConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nb_individus(mut self, nb_individus: usize) -> Self {
+        self.nb_individus = nb_individus;
+        self
+    }
+
+    run_options_builders!();
+
+    pub fn survival_rate(mut self, survival_rate: f64) -> Self {
+        self.survival_rate = survival_rate;
+        self
+    }
+
+    pub fn mutation_rate(mut self, mutation_rate: f64) -> Self {
+        self.mutation_rate = mutation_rate;
+        self
+    }
+
+    pub fn mutation_decay(mut self, mutation_decay: f64) -> Self {
+        self.mutation_decay = mutation_decay;
+        self
+    }
+
+    pub fn crossover_rate(mut self, crossover_rate: f64) -> Self {
+        self.crossover_rate = crossover_rate;
+        self
+    }
+
+    pub fn initial_population_multiplier(mut self, multiplier: usize) -> Self {
+        self.initial_population_multiplier = multiplier;
+        self
+    }
+
+    pub fn elitism(mut self, elitism: usize) -> Self {
+        self.elitism = Some(elitism);
+        self
+    }
+
+    pub fn non_finite_fitness(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite_fitness = policy;
+        self
+    }
+
+    pub fn checkpoint_every(mut self, checkpoint_every: usize) -> Self {
+        self.checkpoint_every = checkpoint_every;
+        self
+    }
+
+    /// Opt-in population-batched evaluation, in stacks of `batch_size` agents
+    /// evaluated in parallel. Agents or simulations that don't support it fall
+    /// back to `Simulation::evaluate_agent`.
+    pub fn batch_evaluation(mut self, batch_size: usize) -> Self {
+        self.batch_evaluation = Some(batch_size);
+        self
+    }
+
+    /// Opt-in speciation, the agents must implement `Agent::distance`.
+    pub fn speciation(mut self, speciation: Speciation) -> Self {
+        self.speciation = Some(speciation);
+        self
+    }
+
+    /// Number of individuals kept as parents at every generation.
+    pub fn nb_keep(&self) -> usize {
+        (self.nb_individus as f64 * self.survival_rate) as usize
+    }
+
+    /// Number of survivors copied unchanged into the next generation.
+    pub fn nb_elites(&self) -> usize {
+        self.elitism.unwrap_or_else(|| self.nb_keep())
+    }
+
+    pub fn mutation_rate_at(&self, generation: usize) -> f64 {
+        self.mutation_rate * self.mutation_decay.powf(generation as f64)
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if self.nb_individus == 0 {
+            return invalid("nb_individus must be greater than 0".to_string());
+        }
+        if !(self.survival_rate > 0. && self.survival_rate <= 1.) {
+            return invalid(format!(
+                "survival_rate must be in ]0, 1], got {}",
+                self.survival_rate
+            ));
+        }
+        if self.nb_keep() == 0 {
+            return invalid(format!(
+                "survival_rate {} keeps no individual out of {}",
+                self.survival_rate, self.nb_individus
+            ));
+        }
+        if !(self.mutation_rate.is_finite() && self.mutation_rate >= 0.) {
+            return invalid(format!(
+                "mutation_rate must be a non-negative number, got {}",
+                self.mutation_rate
+            ));
+        }
+        if !(self.mutation_decay > 0. && self.mutation_decay <= 1.) {
+            return invalid(format!(
+                "mutation_decay must be in ]0, 1], got {}",
+                self.mutation_decay
+            ));
+        }
+        if !(0. ..=1.).contains(&self.crossover_rate) {
+            return invalid(format!(
+                "crossover_rate must be in [0, 1], got {}",
+                self.crossover_rate
+            ));
+        }
+        if self.initial_population_multiplier == 0 {
+            return invalid("initial_population_multiplier must be greater than 0".to_string());
+        }
+        if self.nb_elites() > self.nb_keep() {
+            return invalid(format!(
+                "elitism ({}) can not exceed the number of survivors ({})",
+                self.nb_elites(),
+                self.nb_keep()
+            ));
+        }
+        if self.checkpoint_every == 0 {
+            return invalid("checkpoint_every must be greater than 0".to_string());
+        }
+        if self.batch_evaluation == Some(0) {
+            return invalid("batch_evaluation must be greater than 0".to_string());
+        }
+        if let Some(speciation) = &self.speciation {
+            speciation.validate()?;
+        }
+
+        self.run.validate()
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self, TrainingError> {
+        let config: TrainingConfig = serde_json::from_str(json)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self, TrainingError> {
+        Self::from_json_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn save_json_file<P: AsRef<Path>>(&self, path: P) -> Result<(), TrainingError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TrainingError {
+    InvalidConfig(String),
+    EmptyPopulation,
+    NonFiniteFitness {
+        generation: usize,
+        nb_non_finite: usize,
+    },
+    Io(std::io::Error),
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for TrainingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrainingError::InvalidConfig(reason) => {
+                write!(f, "invalid training config: {}", reason)
+            }
+            TrainingError::EmptyPopulation => write!(f, "the initial population is empty"),
+            TrainingError::NonFiniteFitness {
+                generation,
+                nb_non_finite,
+            } => write!(
+                f,
+                "{} individuals had a non finite fitness at generation {}",
+                nb_non_finite, generation
+            ),
+            TrainingError::Io(err) => write!(f, "io error: {}", err),
+            TrainingError::Serialization(err) => write!(f, "serialization error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TrainingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TrainingError::Io(err) => Some(err),
+            TrainingError::Serialization(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TrainingError {
+    fn from(err: std::io::Error) -> Self {
+        TrainingError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for TrainingError {
+    fn from(err: serde_json::Error) -> Self {
+        TrainingError::Serialization(err)
+    }
+}
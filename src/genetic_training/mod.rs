@@ -1,3 +1,17 @@
 pub mod agent;
+pub mod checkpoint;
+pub mod cmaes;
+pub mod config;
+pub mod error;
+pub mod nes;
+pub mod nsga2;
+pub mod observer;
+pub mod result;
+pub mod rng;
+pub mod run;
+pub mod selection;
 pub mod simulation;
+pub mod speciation;
+pub mod stats;
+pub mod stop;
 pub mod training;
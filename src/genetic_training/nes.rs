@@ -0,0 +1,381 @@
+//! Natural Evolution Strategies in the style of "Evolution Strategies as a
+//! Scalable Alternative to Reinforcement Learning" (Salimans et al., 2017).
+//!
+//! A single centre agent is moved along a search gradient estimated from
+//! mirrored Gaussian perturbations of its parameters. Unlike the truncation
+//! selection, every evaluation contributes to the update, which scales to
+//! networks with many parameters.
+
+use rand::Rng;
+use rand_distr::StandardNormal;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::genetic_training::agent::Agent;
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::TrainingObserver;
+use crate::genetic_training::result::TrainingResult;
+use crate::genetic_training::rng::{stream_rng, RngStream};
+use crate::genetic_training::run::{run_options_builders, RunLoop, RunOptions};
+use crate::genetic_training::simulation::Simulation;
+use crate::genetic_training::stats::GenerationStats;
+use crate::genetic_training::training::{run_generation, update_hall_of_fame};
+use crate::neuralnetwork::parameters::Parameters;
+
+/// Update rule of the centre parameters, given the search gradient.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Optimizer {
+    /// Gradient ascent with an exponential moving average of the gradient,
+    /// `momentum` 0 is the plain gradient ascent.
+    Sgd { momentum: f64 },
+    Adam {
+        beta1: f64,
+        beta2: f64,
+        epsilon: f64,
+    },
+}
+
+impl Optimizer {
+    /// Adam with the usual `beta1 = 0.9`, `beta2 = 0.999` and `epsilon = 1e-8`.
+    pub fn adam() -> Self {
+        Optimizer::Adam {
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        }
+    }
+}
+
+/// Hyper-parameters of a `Nes` run.
+///
+/// The default step sizes and weight decay are those of Salimans et al., with
+/// fewer perturbations per generation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NesConfig {
+    /// Number of mirrored perturbations per generation, every pair costs two simulations.
+    pub nb_pairs: usize,
+    /// Standard deviation of the perturbations.
+    pub sigma: f64,
+    pub learning_rate: f64,
+    pub optimizer: Optimizer,
+    /// L2 penalty on the centre parameters, subtracted from the search gradient.
+    pub weight_decay: f64,
+    #[serde(flatten)]
+    pub run: RunOptions,
+}
+
+impl Default for NesConfig {
+    fn default() -> Self {
+        NesConfig {
+            nb_pairs: 50,
+            sigma: 0.02,
+            learning_rate: 0.01,
+            optimizer: Optimizer::adam(),
+            weight_decay: 0.005,
+            run: RunOptions::default(),
+        }
+    }
+}
+
+impl NesConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nb_pairs(mut self, nb_pairs: usize) -> Self {
+        self.nb_pairs = nb_pairs;
+        self
+    }
+
+    pub fn sigma(mut self, sigma: f64) -> Self {
+        self.sigma = sigma;
+        self
+    }
+
+    pub fn learning_rate(mut self, learning_rate: f64) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    pub fn weight_decay(mut self, weight_decay: f64) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    run_options_builders!();
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if self.nb_pairs == 0 {
+            return invalid("nb_pairs must be greater than 0".to_string());
+        }
+        if !(self.sigma.is_finite() && self.sigma > 0.) {
+            return invalid(format!("sigma must be greater than 0, got {}", self.sigma));
+        }
+        if !(self.learning_rate.is_finite() && self.learning_rate > 0.) {
+            return invalid(format!(
+                "learning_rate must be greater than 0, got {}",
+                self.learning_rate
+            ));
+        }
+        if !(self.weight_decay.is_finite() && self.weight_decay >= 0.) {
+            return invalid(format!(
+                "weight_decay must be a non-negative number, got {}",
+                self.weight_decay
+            ));
+        }
+        match self.optimizer {
+            Optimizer::Sgd { momentum } if !(0. ..1.).contains(&momentum) => {
+                return invalid(format!("momentum must be in [0, 1[, got {}", momentum));
+            }
+            Optimizer::Adam { beta1, beta2, .. }
+                if !((0. ..1.).contains(&beta1) && (0. ..1.).contains(&beta2)) =>
+            {
+                return invalid(format!(
+                    "Adam betas must be in [0, 1[, got {} and {}",
+                    beta1, beta2
+                ));
+            }
+            _ => {}
+        }
+
+        self.run.validate()
+    }
+}
+
+/// Moments of the optimizer, one value per parameter.
+struct OptimizerState {
+    first: Vec<f64>,
+    second: Vec<f64>,
+    step: i32,
+}
+
+impl OptimizerState {
+    fn new(nb_params: usize) -> Self {
+        OptimizerState {
+            first: vec![0.; nb_params],
+            second: vec![0.; nb_params],
+            step: 0,
+        }
+    }
+
+    /// Moves `params` along the ascent direction `gradient`.
+    fn apply(
+        &mut self,
+        optimizer: Optimizer,
+        learning_rate: f64,
+        gradient: &[f64],
+        params: &mut [f64],
+    ) {
+        self.step += 1;
+        match optimizer {
+            Optimizer::Sgd { momentum } => {
+                for ((param, velocity), g) in params.iter_mut().zip(&mut self.first).zip(gradient) {
+                    *velocity = momentum * *velocity + (1. - momentum) * g;
+                    *param += learning_rate * *velocity;
+                }
+            }
+            Optimizer::Adam {
+                beta1,
+                beta2,
+                epsilon,
+            } => {
+                let step_size = learning_rate * (1. - beta2.powi(self.step)).sqrt()
+                    / (1. - beta1.powi(self.step));
+                for (i, g) in gradient.iter().enumerate() {
+                    self.first[i] = beta1 * self.first[i] + (1. - beta1) * g;
+                    self.second[i] = beta2 * self.second[i] + (1. - beta2) * g * g;
+                    params[i] += step_size * self.first[i] / (self.second[i].sqrt() + epsilon);
+                }
+            }
+        }
+    }
+}
+
+/// Centred ranks in `[-0.5, 0.5]`, the best fitness gets 0.5 and non finite
+/// values rank last.
+fn centred_ranks(fitness: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..fitness.len()).collect();
+    let key = |i: usize| {
+        if fitness[i].is_finite() {
+            fitness[i]
+        } else {
+            f64::NEG_INFINITY
+        }
+    };
+    order.sort_by(|&a, &b| key(a).total_cmp(&key(b)));
+
+    let mut ranks = vec![0.; fitness.len()];
+    let denominator = (fitness.len() - 1).max(1) as f64;
+    for (rank, &i) in order.iter().enumerate() {
+        ranks[i] = rank as f64 / denominator - 0.5;
+    }
+    ranks
+}
+
+/// Evolution strategies trainer over the flat parameters of an agent.
+///
+/// Every generation evaluates the centre and `2 * nb_pairs` perturbations of
+/// it, `centre + sigma * eps` and `centre - sigma * eps`. The fitness values
+/// are replaced by their centred ranks, which makes the update invariant to
+/// the scale of the fitness, and the centre moves along
+/// `sum((rank+ - rank-) * eps) / (2 * nb_pairs * sigma) - weight_decay * centre`.
+///
+/// The centre left by the last update is evaluated once more when the
+/// training stops, it competes for the hall of fame and
+/// `TrainingResult::best_agent` with the evaluated generations.
+/// `GenerationStats::mutation_rate` holds `sigma` and `survivors_fitness` the
+/// fitness of the centre.
+pub struct Nes {
+    config: NesConfig,
+    observers: Vec<Box<dyn TrainingObserver>>,
+}
+
+impl Nes {
+    pub fn new(config: NesConfig) -> Result<Self, TrainingError> {
+        config.validate()?;
+        Ok(Nes {
+            config,
+            observers: Vec::new(),
+        })
+    }
+
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: TrainingObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn config(&self) -> &NesConfig {
+        &self.config
+    }
+
+    /// Starts the search from the parameters of `agent`.
+    pub fn train<A, S>(
+        &mut self,
+        agent: A,
+        simulation: &mut S,
+    ) -> Result<TrainingResult<A>, TrainingError>
+    where
+        A: Agent + Parameters,
+        S: Simulation,
+    {
+        let config = &self.config;
+        let mut centre = agent.to_flat_vec();
+        let nb_params = centre.len();
+        if nb_params == 0 {
+            return Err(TrainingError::InvalidConfig(
+                "the agent has no parameter to optimise".to_string(),
+            ));
+        }
+
+        let mut run = RunLoop::new(&config.run, &mut self.observers, config.run.seed);
+        let seed = run.seed();
+        let mut optimizer = OptimizerState::new(nb_params);
+        let to_agent = |params: &[f64]| {
+            let mut candidate = agent.clone();
+            candidate.from_flat_vec(params);
+            candidate.reset();
+            candidate
+        };
+
+        let mut hall_of_fame: Vec<(A, f64)> = Vec::new();
+        let mut history: Vec<GenerationStats> = Vec::new();
+        let mut last_generation: Vec<(A, f64)> = Vec::new();
+        let mut nb_simulations = 0;
+
+        for gen in 0..config.run.nb_generation {
+            run.start_generation(gen, config.sigma)?;
+
+            // One random stream per pair, independent of the rayon scheduling
+            let noise: Vec<Vec<f64>> = (0..config.nb_pairs)
+                .into_par_iter()
+                .map(|i| {
+                    let mut rng = stream_rng(seed, gen, i, RngStream::Mutation);
+                    (0..nb_params)
+                        .map(|_| rng.sample::<f64, _>(StandardNormal))
+                        .collect()
+                })
+                .collect();
+
+            // The centre comes first, then the positive and negative perturbation of every pair
+            let mut population = vec![to_agent(&centre)];
+            for eps in noise.iter() {
+                for sign in [1., -1.] {
+                    let params: Vec<f64> = centre
+                        .iter()
+                        .zip(eps)
+                        .map(|(c, e)| c + sign * config.sigma * e)
+                        .collect();
+                    population.push(to_agent(&params));
+                }
+            }
+            nb_simulations += population.len();
+
+            let evaluated = run_generation(population, Arc::new(simulation.clone()), None);
+            update_hall_of_fame(&mut hall_of_fame, &evaluated, config.run.hall_of_fame_size);
+
+            let centre_fitness = evaluated[0].1;
+            let fitness: Vec<f64> = evaluated[1..].iter().map(|res| res.1).collect();
+            let ranks = centred_ranks(&fitness);
+            let normalisation = 1. / (2. * config.nb_pairs as f64 * config.sigma);
+            let gradient: Vec<f64> = (0..nb_params)
+                .map(|j| {
+                    let estimate: f64 = noise
+                        .iter()
+                        .enumerate()
+                        .map(|(i, eps)| (ranks[2 * i] - ranks[2 * i + 1]) * eps[j])
+                        .sum();
+                    estimate * normalisation - config.weight_decay * centre[j]
+                })
+                .collect();
+            optimizer.apply(
+                config.optimizer,
+                config.learning_rate,
+                &gradient,
+                &mut centre,
+            );
+
+            let nb_non_finite = evaluated.iter().filter(|res| !res.1.is_finite()).count();
+            last_generation = evaluated;
+            last_generation.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let sorted_fitness: Vec<f64> = last_generation.iter().map(|res| res.1).collect();
+            let stats = GenerationStats::new(
+                gen,
+                config.sigma,
+                (sorted_fitness.len(), nb_non_finite),
+                &sorted_fitness,
+                vec![centre_fitness],
+            );
+
+            simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+
+            if run.end_generation(stats, &mut history, nb_simulations)? {
+                break;
+            }
+        }
+
+        // The last update of the centre hasn't been evaluated yet
+        let final_centre = to_agent(&centre);
+        let fitness = simulation.evaluate_agent(&mut final_centre.clone());
+        nb_simulations += 1;
+        update_hall_of_fame(
+            &mut hall_of_fame,
+            &[(final_centre, fitness)],
+            config.run.hall_of_fame_size,
+        );
+
+        let summary = run.finish(2 * config.nb_pairs + 1, history.len(), nb_simulations)?;
+        TrainingResult::new(hall_of_fame, last_generation, history, summary)
+    }
+}
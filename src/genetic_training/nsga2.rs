@@ -0,0 +1,507 @@
+//! Multi-objective optimisation with NSGA-II (Deb et al., "A fast and elitist
+//! multiobjective genetic algorithm: NSGA-II", 2002).
+//!
+//! The simulation returns one value per objective with
+//! `Simulation::evaluate_objectives`, every objective is maximised. Instead of
+//! a single best agent, the training gives the Pareto front: the agents that
+//! no other agent beats on every objective.
+
+use rand::Rng;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::genetic_training::agent::Agent;
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::TrainingObserver;
+use crate::genetic_training::result::ParetoResult;
+use crate::genetic_training::rng::{stream_rng, RngStream, TrainingRng};
+use crate::genetic_training::run::{run_options_builders, RunLoop, RunOptions};
+use crate::genetic_training::simulation::Simulation;
+use crate::genetic_training::stats::GenerationStats;
+use crate::genetic_training::training::reproduce;
+
+/// Hyper-parameters of a `Nsga2` run.
+///
+/// The fitness stop conditions of `run` apply to the first objective, and
+/// `run.hall_of_fame_size` is unused since the result keeps the Pareto front.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Nsga2Config {
+    /// Number of parents, and of children of every generation.
+    pub population_size: usize,
+    #[serde(flatten)]
+    pub run: RunOptions,
+    /// Mutation rate of the first generation.
+    pub mutation_rate: f64,
+    /// Multiplicative decay applied to the mutation rate at every generation.
+    pub mutation_decay: f64,
+    /// Probability that a child is the crossover of two parents before being mutated.
+    pub crossover_rate: f64,
+    /// Point the hypervolume is measured from, one value per objective below
+    /// the values of interest. `None` uses the worst value of every objective
+    /// in the first generation where every objective has a finite value, the
+    /// hypervolume isn't reported before it.
+    pub reference_point: Option<Vec<f64>>,
+}
+
+impl Default for Nsga2Config {
+    fn default() -> Self {
+        Nsga2Config {
+            population_size: 100,
+            run: RunOptions::default(),
+            mutation_rate: 0.1,
+            mutation_decay: 0.999,
+            crossover_rate: 0.,
+            reference_point: None,
+        }
+    }
+}
+
+impl Nsga2Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn population_size(mut self, population_size: usize) -> Self {
+        self.population_size = population_size;
+        self
+    }
+
+    run_options_builders!();
+
+    pub fn mutation_rate(mut self, mutation_rate: f64) -> Self {
+        self.mutation_rate = mutation_rate;
+        self
+    }
+
+    pub fn mutation_decay(mut self, mutation_decay: f64) -> Self {
+        self.mutation_decay = mutation_decay;
+        self
+    }
+
+    pub fn crossover_rate(mut self, crossover_rate: f64) -> Self {
+        self.crossover_rate = crossover_rate;
+        self
+    }
+
+    pub fn reference_point(mut self, reference_point: Vec<f64>) -> Self {
+        self.reference_point = Some(reference_point);
+        self
+    }
+
+    pub fn mutation_rate_at(&self, generation: usize) -> f64 {
+        self.mutation_rate * self.mutation_decay.powf(generation as f64)
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if self.population_size < 2 {
+            return invalid("population_size must be at least 2".to_string());
+        }
+        if !(self.mutation_rate.is_finite() && self.mutation_rate >= 0.) {
+            return invalid(format!(
+                "mutation_rate must be a non-negative number, got {}",
+                self.mutation_rate
+            ));
+        }
+        if !(self.mutation_decay > 0. && self.mutation_decay <= 1.) {
+            return invalid(format!(
+                "mutation_decay must be in ]0, 1], got {}",
+                self.mutation_decay
+            ));
+        }
+        if !(0. ..=1.).contains(&self.crossover_rate) {
+            return invalid(format!(
+                "crossover_rate must be in [0, 1], got {}",
+                self.crossover_rate
+            ));
+        }
+        if let Some(reference) = &self.reference_point {
+            if reference.is_empty() || reference.iter().any(|r| !r.is_finite()) {
+                return invalid(format!(
+                    "the reference point must have finite values, got {:?}",
+                    reference
+                ));
+            }
+        }
+
+        self.run.validate()
+    }
+}
+
+/// Whether `a` is at least as good as `b` on every objective and better on one.
+pub fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| x >= y) && a.iter().zip(b.iter()).any(|(x, y)| x > y)
+}
+
+/// Splits the points in successive fronts: the first front holds the points
+/// dominated by no other, the second one those only dominated by the first
+/// front, and so on. Returns the indices of the points of every front.
+pub fn non_dominated_sort(objectives: &[Vec<f64>]) -> Vec<Vec<usize>> {
+    let n = objectives.len();
+    let mut dominated: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut nb_dominating = vec![0; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            if dominates(&objectives[i], &objectives[j]) {
+                dominated[i].push(j);
+                nb_dominating[j] += 1;
+            } else if dominates(&objectives[j], &objectives[i]) {
+                dominated[j].push(i);
+                nb_dominating[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = Vec::new();
+    let mut front: Vec<usize> = (0..n).filter(|&i| nb_dominating[i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = Vec::new();
+        for &i in front.iter() {
+            for &j in dominated[i].iter() {
+                nb_dominating[j] -= 1;
+                if nb_dominating[j] == 0 {
+                    next.push(j);
+                }
+            }
+        }
+        next.sort_unstable();
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+/// Crowding distance of the points of `front`, in the same order: the sum
+/// over the objectives of the normalised distance between the two neighbours
+/// of a point. The extreme points of every objective get an infinite distance.
+pub fn crowding_distance(objectives: &[Vec<f64>], front: &[usize]) -> Vec<f64> {
+    let mut distance = vec![0.; front.len()];
+    let Some(&first) = front.first() else {
+        return distance;
+    };
+
+    let mut order: Vec<usize> = (0..front.len()).collect();
+    let columns = (0..objectives[first].len()).map(|k| {
+        front
+            .iter()
+            .map(|&i| objectives[i][k])
+            .collect::<Vec<f64>>()
+    });
+    for values in columns {
+        order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+        let (lowest, highest) = (order[0], order[front.len() - 1]);
+        let range = values[highest] - values[lowest];
+        distance[lowest] = f64::INFINITY;
+        distance[highest] = f64::INFINITY;
+        if !(range.is_finite() && range > 0.) {
+            continue;
+        }
+        for w in order.windows(3) {
+            distance[w[1]] += (values[w[2]] - values[w[0]]) / range;
+        }
+    }
+    distance
+}
+
+/// Volume of the objective space dominated by `points` and bounded by
+/// `reference`. The points that don't beat the reference on every objective
+/// add nothing.
+///
+/// The exact computation slices the space along every objective and costs
+/// O(n^d) for `n` points and `d` objectives: it's meant for 2 or 3 objectives,
+/// with more the hypervolume of a large front becomes the bulk of a generation.
+pub fn hypervolume(points: &[Vec<f64>], reference: &[f64]) -> f64 {
+    let points: Vec<&[f64]> = points
+        .iter()
+        .map(|p| p.as_slice())
+        .filter(|p| p.iter().zip(reference.iter()).all(|(x, r)| x > r))
+        .collect();
+    slice_volume(points, reference)
+}
+
+/// Sums the slices between the successive values of the last objective, every
+/// slice is the hypervolume of the points above it in one less dimension.
+fn slice_volume(mut points: Vec<&[f64]>, reference: &[f64]) -> f64 {
+    let d = reference.len();
+    if points.is_empty() || d == 0 {
+        return 0.;
+    }
+    if d == 1 {
+        return points
+            .iter()
+            .map(|p| p[0])
+            .fold(f64::NEG_INFINITY, f64::max)
+            - reference[0];
+    }
+
+    points.sort_by(|a, b| b[d - 1].total_cmp(&a[d - 1]));
+    let mut volume = 0.;
+    for i in 0..points.len() {
+        let bottom = points.get(i + 1).map_or(reference[d - 1], |p| p[d - 1]);
+        let height = points[i][d - 1] - bottom;
+        if height > 0. {
+            let above: Vec<&[f64]> = points[..=i].iter().map(|p| &p[..d - 1]).collect();
+            volume += height * slice_volume(above, &reference[..d - 1]);
+        }
+    }
+    volume
+}
+
+/// Parents of the next generation with their front rank and crowding distance.
+struct Ranked<A> {
+    agent: A,
+    objectives: Vec<f64>,
+    rank: usize,
+    crowding: f64,
+}
+
+/// Crowded comparison: the lower front wins, then the larger crowding distance.
+fn crowded_better<A>(a: &Ranked<A>, b: &Ranked<A>) -> bool {
+    a.rank < b.rank || (a.rank == b.rank && a.crowding > b.crowding)
+}
+
+/// Multi-objective trainer running NSGA-II.
+///
+/// At every generation, the current parents and their children are evaluated,
+/// the parents again since the simulation can change with
+/// `Simulation::on_generation`. The parents of the next generation are the
+/// best `population_size` agents of the parents and children together: whole
+/// fronts first, and the least crowded agents of the front that doesn't fit.
+/// The parents of every child are drawn by binary tournament on the same order.
+///
+/// `GenerationStats::hypervolume` gives the hypervolume of the first front,
+/// the fitness statistics are those of the first objective.
+pub struct Nsga2 {
+    config: Nsga2Config,
+    observers: Vec<Box<dyn TrainingObserver>>,
+}
+
+impl Nsga2 {
+    pub fn new(config: Nsga2Config) -> Result<Self, TrainingError> {
+        config.validate()?;
+        Ok(Nsga2 {
+            config,
+            observers: Vec::new(),
+        })
+    }
+
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: TrainingObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn config(&self) -> &Nsga2Config {
+        &self.config
+    }
+
+    pub fn train<A, S>(
+        &mut self,
+        population: Vec<A>,
+        simulation: &mut S,
+    ) -> Result<ParetoResult<A>, TrainingError>
+    where
+        A: Agent,
+        S: Simulation,
+    {
+        if population.is_empty() {
+            return Err(TrainingError::EmptyPopulation);
+        }
+        let config = &self.config;
+        let mut run = RunLoop::new(&config.run, &mut self.observers, config.run.seed);
+        let seed = run.seed();
+
+        let mut children = reproduce(
+            population.clone(),
+            std::slice::from_ref(&population),
+            config.population_size,
+            config.mutation_rate,
+            0.,
+            (seed, 0, RngStream::Initial),
+        );
+        let mut parents: Vec<Ranked<A>> = Vec::new();
+        let mut reference = config.reference_point.clone();
+
+        let mut history: Vec<GenerationStats> = Vec::new();
+        let mut nb_simulations = 0;
+
+        for gen in 0..config.run.nb_generation {
+            let gen_mutation_rate = config.mutation_rate_at(gen);
+            run.start_generation(gen, gen_mutation_rate)?;
+
+            if gen > 0 {
+                children = (0..config.population_size)
+                    .map(|i| {
+                        let mut rng = stream_rng(seed, gen, i, RngStream::Mutation);
+                        let tournament = |rng: &mut TrainingRng| {
+                            let a = &parents[rng.gen_range(0..parents.len())];
+                            let b = &parents[rng.gen_range(0..parents.len())];
+                            if crowded_better(b, a) {
+                                b
+                            } else {
+                                a
+                            }
+                        };
+                        let parent = tournament(&mut rng);
+                        if rng.gen::<f64>() < config.crossover_rate {
+                            let mate = tournament(&mut rng);
+                            parent
+                                .agent
+                                .crossover(&mate.agent, &mut rng)
+                                .mutate(gen_mutation_rate, &mut rng)
+                        } else {
+                            parent.agent.mutate(gen_mutation_rate, &mut rng)
+                        }
+                    })
+                    .collect();
+                children.iter_mut().for_each(|agent| agent.reset());
+            }
+
+            // The parents are evaluated again as the simulation may have changed
+            let sim = Arc::new(simulation.clone());
+            let evaluated: Vec<(A, Vec<f64>)> = parents
+                .drain(..)
+                .map(|ranked| ranked.agent)
+                .chain(std::mem::take(&mut children))
+                .collect::<Vec<A>>()
+                .into_par_iter()
+                .map(|agent| {
+                    let objectives = sim.evaluate_objectives(&mut agent.clone());
+                    (agent, objectives)
+                })
+                .collect();
+            nb_simulations += evaluated.len();
+
+            let nb_objectives = reference
+                .as_ref()
+                .map_or(evaluated[0].1.len(), |reference| reference.len());
+            if nb_objectives == 0 {
+                return Err(TrainingError::InvalidConfig(
+                    "evaluate_objectives returned no objective".to_string(),
+                ));
+            }
+            if let Some(res) = evaluated.iter().find(|res| res.1.len() != nb_objectives) {
+                return Err(TrainingError::InvalidConfig(format!(
+                    "evaluate_objectives returned {} objectives, expected {}",
+                    res.1.len(),
+                    nb_objectives
+                )));
+            }
+            let nb_non_finite = evaluated
+                .iter()
+                .filter(|res| res.1.iter().any(|x| !x.is_finite()))
+                .count();
+            let mut first_objective: Vec<f64> = evaluated.iter().map(|res| res.1[0]).collect();
+            first_objective.sort_by(|a, b| b.total_cmp(a));
+
+            // Non finite objectives are the worst possible values
+            let mut candidates: Vec<(A, Vec<f64>)> = evaluated
+                .into_iter()
+                .map(|(agent, objectives)| {
+                    let objectives = objectives
+                        .into_iter()
+                        .map(|x| if x.is_finite() { x } else { f64::NEG_INFINITY })
+                        .collect();
+                    (agent, objectives)
+                })
+                .collect();
+            if reference.is_none() {
+                let worst: Vec<f64> = (0..nb_objectives)
+                    .map(|k| {
+                        candidates
+                            .iter()
+                            .map(|res| res.1[k])
+                            .filter(|x| x.is_finite())
+                            .fold(f64::INFINITY, f64::min)
+                    })
+                    .collect();
+                // Until every objective has a finite value
+                if worst.iter().all(|x| x.is_finite()) {
+                    reference = Some(worst);
+                }
+            }
+
+            let objectives: Vec<Vec<f64>> = candidates.iter().map(|res| res.1.clone()).collect();
+            let mut selected: Vec<(usize, usize, f64)> = Vec::with_capacity(config.population_size);
+            for (rank, front) in non_dominated_sort(&objectives).into_iter().enumerate() {
+                let remaining = config.population_size - selected.len();
+                if remaining == 0 {
+                    break;
+                }
+                let crowding = crowding_distance(&objectives, &front);
+                let mut front: Vec<(usize, usize, f64)> = front
+                    .into_iter()
+                    .zip(crowding)
+                    .map(|(i, crowding)| (i, rank, crowding))
+                    .collect();
+                front.sort_by(|a, b| b.2.total_cmp(&a.2));
+                front.truncate(remaining);
+                selected.extend(front);
+            }
+
+            let mut slots: Vec<Option<(A, Vec<f64>)>> = candidates.drain(..).map(Some).collect();
+            parents = selected
+                .into_iter()
+                .filter_map(|(i, rank, crowding)| {
+                    slots[i].take().map(|(agent, objectives)| Ranked {
+                        agent,
+                        objectives,
+                        rank,
+                        crowding,
+                    })
+                })
+                .collect();
+
+            let front: Vec<Vec<f64>> = parents
+                .iter()
+                .filter(|ranked| ranked.rank == 0)
+                .map(|ranked| ranked.objectives.clone())
+                .collect();
+            let mut survivors_fitness: Vec<f64> =
+                parents.iter().map(|ranked| ranked.objectives[0]).collect();
+            survivors_fitness.sort_by(|a, b| b.total_cmp(a));
+            let mut stats = GenerationStats::new(
+                gen,
+                gen_mutation_rate,
+                (first_objective.len(), nb_non_finite),
+                &first_objective,
+                survivors_fitness,
+            );
+            stats.hypervolume = reference
+                .as_ref()
+                .map(|reference| hypervolume(&front, reference));
+
+            simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+
+            if run.end_generation(stats, &mut history, nb_simulations)? {
+                break;
+            }
+        }
+
+        let summary = run.finish(config.population_size, history.len(), nb_simulations)?;
+
+        // The parents are sorted by front, the least crowded first
+        let population: Vec<(A, Vec<f64>)> = parents
+            .iter()
+            .map(|ranked| (ranked.agent.clone(), ranked.objectives.clone()))
+            .collect();
+        let mut pareto_front: Vec<(A, Vec<f64>)> = parents
+            .into_iter()
+            .filter(|ranked| ranked.rank == 0)
+            .map(|ranked| (ranked.agent, ranked.objectives))
+            .collect();
+        pareto_front.sort_by(|a, b| b.1[0].total_cmp(&a.1[0]));
+
+        Ok(ParetoResult {
+            pareto_front,
+            population,
+            history,
+            summary,
+        })
+    }
+}
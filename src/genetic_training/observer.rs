@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::stats::{GenerationStats, TrainingSummary};
+
+/// Hooks called by the `Trainer` during the training loop.
+///
+/// Every hook has a no-op default, implementors only override what they need.
+pub trait TrainingObserver {
+    fn on_generation_start(
+        &mut self,
+        _generation: usize,
+        _mutation_rate: f64,
+    ) -> Result<(), TrainingError> {
+        Ok(())
+    }
+
+    fn on_generation_end(&mut self, _stats: &GenerationStats) -> Result<(), TrainingError> {
+        Ok(())
+    }
+
+    fn on_training_end(&mut self, _summary: &TrainingSummary) -> Result<(), TrainingError> {
+        Ok(())
+    }
+}
+
+pub struct SilentObserver;
+
+impl TrainingObserver for SilentObserver {}
+
+/// Prints the progress of the training on stdout.
+pub struct ConsoleObserver;
+
+impl TrainingObserver for ConsoleObserver {
+    fn on_generation_start(
+        &mut self,
+        generation: usize,
+        mutation_rate: f64,
+    ) -> Result<(), TrainingError> {
+        println!(
+            "Generation: {}     Mutation rate: {}",
+            generation,
+            (mutation_rate * 10000.0).round() / 10000.0
+        );
+        Ok(())
+    }
+
+    fn on_generation_end(&mut self, stats: &GenerationStats) -> Result<(), TrainingError> {
+        let scores: Vec<f64> = stats
+            .survivors_fitness
+            .iter()
+            .map(|fitness| (fitness * 10000.0).round() / 10000.0)
+            .collect();
+        println!("Best individuals fitness: {:?}", scores);
+        if stats.nb_non_finite > 0 {
+            println!("Non finite fitness: {} individuals", stats.nb_non_finite);
+        }
+        if !stats.species.is_empty() {
+            let sizes: Vec<usize> = stats.species.iter().map(|s| s.nb_members).collect();
+            println!("Species sizes: {:?}", sizes);
+        }
+        if let Some(hypervolume) = stats.hypervolume {
+            println!("Hypervolume: {}", hypervolume);
+        }
+        println!("{} ms\n", stats.elapsed_ms);
+        Ok(())
+    }
+
+    fn on_training_end(&mut self, summary: &TrainingSummary) -> Result<(), TrainingError> {
+        println!(
+            "Total time: {} ms\nFor {} individuals for {} generations.\nFor a total of {} simulations",
+            summary.elapsed_ms, summary.nb_individus, summary.nb_generation, summary.nb_simulations
+        );
+        println!("Stopped: {}", summary.stop_reason);
+        Ok(())
+    }
+}
+
+/// Writes the statistics of every generation as one json object per line.
+pub struct JsonLinesObserver {
+    writer: BufWriter<File>,
+}
+
+impl JsonLinesObserver {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, TrainingError> {
+        Ok(JsonLinesObserver {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl TrainingObserver for JsonLinesObserver {
+    fn on_generation_end(&mut self, stats: &GenerationStats) -> Result<(), TrainingError> {
+        serde_json::to_writer(&mut self.writer, stats)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn on_training_end(&mut self, _summary: &TrainingSummary) -> Result<(), TrainingError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::stats::{GenerationStats, TrainingSummary};
+
+/// Outcome of a training run.
+#[derive(Clone, Debug)]
+pub struct TrainingResult<A> {
+    /// Best individual evaluated during the whole run.
+    pub best_agent: A,
+    pub best_fitness: f64,
+    /// Best individuals of the whole run with their fitness, best first.
+    pub hall_of_fame: Vec<(A, f64)>,
+    /// Last evaluated generation with its fitness, best first.
+    pub population: Vec<(A, f64)>,
+    pub history: Vec<GenerationStats>,
+    pub summary: TrainingSummary,
+}
+
+impl<A> TrainingResult<A>
+where
+    A: Clone,
+{
+    /// The best agent is the head of the `hall_of_fame`, which is empty when
+    /// no fitness of the run was finite.
+    pub(crate) fn new(
+        hall_of_fame: Vec<(A, f64)>,
+        population: Vec<(A, f64)>,
+        history: Vec<GenerationStats>,
+        summary: TrainingSummary,
+    ) -> Result<Self, TrainingError> {
+        let Some((best_agent, best_fitness)) = hall_of_fame.first().cloned() else {
+            return Err(match history.last() {
+                Some(stats) => TrainingError::NonFiniteFitness {
+                    generation: stats.generation,
+                    nb_non_finite: summary.nb_simulations,
+                },
+                None => TrainingError::InvalidConfig("no individual was evaluated".to_string()),
+            });
+        };
+
+        Ok(TrainingResult {
+            best_agent,
+            best_fitness,
+            hall_of_fame,
+            population,
+            history,
+            summary,
+        })
+    }
+
+    /// Agents of the last evaluated generation, best first.
+    pub fn agents(self) -> Vec<A> {
+        self.population
+            .into_iter()
+            .map(|(agent, _)| agent)
+            .collect()
+    }
+}
+
+/// Outcome of a multi-objective training run.
+#[derive(Clone, Debug)]
+pub struct ParetoResult<A> {
+    /// Agents of the last generation that no other agent beats on every
+    /// objective, with their objectives, best first objective first.
+    pub pareto_front: Vec<(A, Vec<f64>)>,
+    /// Last generation with its objectives, by front.
+    pub population: Vec<(A, Vec<f64>)>,
+    pub history: Vec<GenerationStats>,
+    pub summary: TrainingSummary,
+}
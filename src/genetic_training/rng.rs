@@ -0,0 +1,30 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Random number generator handed to the agents and the simulation during training.
+pub type TrainingRng = ChaCha8Rng;
+
+/// Independent random streams used by the training loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngStream {
+    Initial,
+    Mutation,
+    Simulation,
+    Selection,
+    /// Draws of the restart strategies of `CmaEs`.
+    Restart,
+}
+
+/// Derives the generator of one individual at one generation.
+///
+/// The stream only depends on its coordinates, not on the order in which
+/// rayon schedules the individuals, so a seed always replays the same run.
+pub fn stream_rng(seed: u64, generation: usize, index: usize, stream: RngStream) -> TrainingRng {
+    let mut key = [0u8; 32];
+    key[0..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&(generation as u64).to_le_bytes());
+    key[16..24].copy_from_slice(&(index as u64).to_le_bytes());
+    key[24..32].copy_from_slice(&(stream as u64).to_le_bytes());
+
+    TrainingRng::from_seed(key)
+}
@@ -0,0 +1,209 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::TrainingObserver;
+use crate::genetic_training::stats::{GenerationStats, TrainingSummary};
+use crate::genetic_training::stop::{StopCondition, StopReason};
+
+/// Options of a run shared by every trainer config. The configs flatten
+/// them, a config file lists them next to the other fields.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunOptions {
+    /// Maximum number of generations.
+    pub nb_generation: usize,
+    /// The training stops before `nb_generation` as soon as one of them is met.
+    pub stop_conditions: Vec<StopCondition>,
+    /// Seed of the run, a random one is drawn when `None`.
+    pub seed: Option<u64>,
+    /// Number of best individuals of the whole run kept in the `TrainingResult`.
+    pub hall_of_fame_size: usize,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            nb_generation: 100,
+            stop_conditions: Vec::new(),
+            seed: None,
+            hall_of_fame_size: 10,
+        }
+    }
+}
+
+impl RunOptions {
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        if self.nb_generation == 0 {
+            return Err(TrainingError::InvalidConfig(
+                "nb_generation must be greater than 0".to_string(),
+            ));
+        }
+        if self.hall_of_fame_size == 0 {
+            return Err(TrainingError::InvalidConfig(
+                "hall_of_fame_size must be greater than 0".to_string(),
+            ));
+        }
+        for condition in self.stop_conditions.iter() {
+            condition.validate().map_err(TrainingError::InvalidConfig)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builders of the `run: RunOptions` field of a trainer config.
+macro_rules! run_options_builders {
+    () => {
+        pub fn nb_generation(mut self, nb_generation: usize) -> Self {
+            self.run.nb_generation = nb_generation;
+            self
+        }
+
+        pub fn stop_condition(
+            mut self,
+            condition: $crate::genetic_training::stop::StopCondition,
+        ) -> Self {
+            self.run.stop_conditions.push(condition);
+            self
+        }
+
+        /// Stop as soon as the best fitness of a generation reaches this value.
+        pub fn target_fitness(self, target_fitness: f64) -> Self {
+            self.stop_condition(
+                $crate::genetic_training::stop::StopCondition::TargetFitness(target_fitness),
+            )
+        }
+
+        /// Stop when the best fitness hasn't improved for `generations` generations.
+        pub fn patience(self, generations: usize) -> Self {
+            self.stop_condition(
+                $crate::genetic_training::stop::StopCondition::NoImprovement { generations },
+            )
+        }
+
+        pub fn time_budget(self, budget: std::time::Duration) -> Self {
+            self.stop_condition($crate::genetic_training::stop::StopCondition::TimeBudget(
+                budget,
+            ))
+        }
+
+        pub fn evaluation_budget(self, nb_simulations: usize) -> Self {
+            self.stop_condition(
+                $crate::genetic_training::stop::StopCondition::EvaluationBudget(nb_simulations),
+            )
+        }
+
+        pub fn seed(mut self, seed: u64) -> Self {
+            self.run.seed = Some(seed);
+            self
+        }
+
+        pub fn hall_of_fame_size(mut self, hall_of_fame_size: usize) -> Self {
+            self.run.hall_of_fame_size = hall_of_fame_size;
+            self
+        }
+    };
+}
+
+pub(crate) use run_options_builders;
+
+/// Bookkeeping shared by the training loops: notifies the observers, checks
+/// the stop conditions and builds the summary of the run.
+pub(crate) struct RunLoop<'a> {
+    options: &'a RunOptions,
+    observers: &'a mut [Box<dyn TrainingObserver>],
+    seed: u64,
+    stop_reason: StopReason,
+    start_time: Instant,
+    generation_start_time: Instant,
+}
+
+impl<'a> RunLoop<'a> {
+    /// Draws a random seed when `seed` is `None`.
+    pub(crate) fn new(
+        options: &'a RunOptions,
+        observers: &'a mut [Box<dyn TrainingObserver>],
+        seed: Option<u64>,
+    ) -> Self {
+        RunLoop {
+            options,
+            observers,
+            seed: seed.unwrap_or_else(|| rand::thread_rng().gen()),
+            stop_reason: StopReason::MaxGenerations,
+            start_time: Instant::now(),
+            generation_start_time: Instant::now(),
+        }
+    }
+
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub(crate) fn start_generation(
+        &mut self,
+        generation: usize,
+        mutation_rate: f64,
+    ) -> Result<(), TrainingError> {
+        self.generation_start_time = Instant::now();
+        for observer in self.observers.iter_mut() {
+            observer.on_generation_start(generation, mutation_rate)?;
+        }
+        Ok(())
+    }
+
+    /// Times the generation, appends its `stats` to `history` and notifies the
+    /// observers. Returns whether one of the stop conditions is met.
+    pub(crate) fn end_generation(
+        &mut self,
+        mut stats: GenerationStats,
+        history: &mut Vec<GenerationStats>,
+        nb_simulations: usize,
+    ) -> Result<bool, TrainingError> {
+        stats.elapsed_ms = self.generation_start_time.elapsed().as_millis();
+        history.push(stats);
+        for observer in self.observers.iter_mut() {
+            observer.on_generation_end(&history[history.len() - 1])?;
+        }
+
+        let elapsed = self.start_time.elapsed();
+        match self
+            .options
+            .stop_conditions
+            .iter()
+            .find(|condition| condition.is_met(history, nb_simulations, elapsed))
+        {
+            Some(condition) => {
+                self.stop(StopReason::Condition(*condition));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Records why the training stops before `nb_generation`.
+    pub(crate) fn stop(&mut self, reason: StopReason) {
+        self.stop_reason = reason;
+    }
+
+    pub(crate) fn finish(
+        self,
+        nb_individus: usize,
+        nb_generation: usize,
+        nb_simulations: usize,
+    ) -> Result<TrainingSummary, TrainingError> {
+        let summary = TrainingSummary {
+            seed: self.seed,
+            nb_individus,
+            nb_generation,
+            nb_simulations,
+            elapsed_ms: self.start_time.elapsed().as_millis(),
+            stop_reason: self.stop_reason,
+        };
+        for observer in self.observers.iter_mut() {
+            observer.on_training_end(&summary)?;
+        }
+        Ok(summary)
+    }
+}
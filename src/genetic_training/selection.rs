@@ -0,0 +1,157 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::genetic_training::rng::TrainingRng;
+
+/// Chooses the parents of the next generation.
+pub trait SelectionStrategy {
+    /// `fitness` is sorted from best to worst, the returned values are indices
+    /// into it. An index can be returned several times.
+    fn select(&self, fitness: &[f64], nb_parents: usize, rng: &mut TrainingRng) -> Vec<usize>;
+}
+
+/// Keeps the `nb_parents` best individuals.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Truncation;
+
+impl SelectionStrategy for Truncation {
+    fn select(&self, fitness: &[f64], nb_parents: usize, _rng: &mut TrainingRng) -> Vec<usize> {
+        (0..nb_parents.min(fitness.len())).collect()
+    }
+}
+
+/// Every parent is the best of `size` individuals drawn uniformly.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tournament {
+    pub size: usize,
+}
+
+impl SelectionStrategy for Tournament {
+    fn select(&self, fitness: &[f64], nb_parents: usize, rng: &mut TrainingRng) -> Vec<usize> {
+        if fitness.is_empty() {
+            return Vec::new();
+        }
+        (0..nb_parents)
+            .map(|_| {
+                // The population is sorted, so the best contestant has the lowest index
+                (0..self.size.max(1))
+                    .map(|_| rng.gen_range(0..fitness.len()))
+                    .min()
+                    .unwrap()
+            })
+            .collect()
+    }
+}
+
+/// Fitness proportional selection.
+///
+/// The fitness is shifted by the worst finite value so negative fitness (e.g. `-mse`)
+/// is supported, the worst individual therefore can't be selected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Roulette;
+
+impl SelectionStrategy for Roulette {
+    fn select(&self, fitness: &[f64], nb_parents: usize, rng: &mut TrainingRng) -> Vec<usize> {
+        let cumulative = cumulative_weights(&shifted_fitness(fitness));
+        (0..nb_parents)
+            .filter_map(|_| sample_cumulative(&cumulative, rng.gen::<f64>()))
+            .collect()
+    }
+}
+
+/// Linear ranking, `pressure` in [1, 2] is the expected number of copies of the best individual.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinearRank {
+    pub pressure: f64,
+}
+
+impl Default for LinearRank {
+    fn default() -> Self {
+        LinearRank { pressure: 1.5 }
+    }
+}
+
+impl SelectionStrategy for LinearRank {
+    fn select(&self, fitness: &[f64], nb_parents: usize, rng: &mut TrainingRng) -> Vec<usize> {
+        let n = fitness.len();
+        if n == 0 {
+            return Vec::new();
+        }
+        if n == 1 {
+            return vec![0; nb_parents];
+        }
+        let pressure = self.pressure.clamp(1., 2.);
+        let weights: Vec<f64> = (0..n)
+            .map(|rank| {
+                let reversed = (n - 1 - rank) as f64;
+                (2. - pressure) / n as f64 + 2. * reversed * (pressure - 1.) / (n * (n - 1)) as f64
+            })
+            .collect();
+        let cumulative = cumulative_weights(&weights);
+        (0..nb_parents)
+            .filter_map(|_| sample_cumulative(&cumulative, rng.gen::<f64>()))
+            .collect()
+    }
+}
+
+/// Fitness proportional selection with a single spin and evenly spaced pointers,
+/// which has less variance than the roulette.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StochasticUniversalSampling;
+
+impl SelectionStrategy for StochasticUniversalSampling {
+    fn select(&self, fitness: &[f64], nb_parents: usize, rng: &mut TrainingRng) -> Vec<usize> {
+        if nb_parents == 0 {
+            return Vec::new();
+        }
+        let cumulative = cumulative_weights(&shifted_fitness(fitness));
+        let step = 1. / nb_parents as f64;
+        let start = rng.gen::<f64>() * step;
+        (0..nb_parents)
+            .filter_map(|i| sample_cumulative(&cumulative, start + i as f64 * step))
+            .collect()
+    }
+}
+
+/// Non finite fitness values get a zero weight.
+fn shifted_fitness(fitness: &[f64]) -> Vec<f64> {
+    let worst = fitness
+        .iter()
+        .cloned()
+        .filter(|f| f.is_finite())
+        .fold(f64::INFINITY, f64::min);
+    fitness
+        .iter()
+        .map(|f| if f.is_finite() { f - worst } else { 0. })
+        .collect()
+}
+
+/// Normalised cumulative distribution, uniform when every weight is zero.
+fn cumulative_weights(weights: &[f64]) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    let mut acc = 0.;
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            acc += if total > 0. {
+                w / total
+            } else {
+                1. / weights.len() as f64
+            };
+            if i == weights.len() - 1 {
+                1.
+            } else {
+                acc
+            }
+        })
+        .collect()
+}
+
+fn sample_cumulative(cumulative: &[f64], u: f64) -> Option<usize> {
+    if cumulative.is_empty() {
+        return None;
+    }
+    let index = cumulative.partition_point(|c| *c <= u);
+    Some(index.min(cumulative.len() - 1))
+}
@@ -1,9 +1,29 @@
-use crate::genetic_training::agent::Agent;
+use rand::Rng;
+
+use crate::genetic_training::agent::{Agent, AgentBatch};
 
 pub trait Simulation: Clone + Send + Sync + 'static {
     fn evaluate_agent<A>(&self, agent: &mut A) -> f64
     where
         A: Agent;
 
-    fn on_generation(&mut self, generation_number: usize);
+    fn on_generation<R: Rng + ?Sized>(&mut self, generation_number: usize, rng: &mut R);
+
+    /// Objectives of `agent`, all maximised, for the multi-objective `Nsga2`
+    /// trainer. Every call must return the same number of objectives. The
+    /// default implementation has the fitness as single objective.
+    fn evaluate_objectives<A>(&self, agent: &mut A) -> Vec<f64>
+    where
+        A: Agent,
+    {
+        vec![self.evaluate_agent(agent)]
+    }
+
+    /// Fitness of every agent of `batch`, in order, when the training runs with
+    /// `TrainingConfig::batch_evaluation`. It must match what `evaluate_agent`
+    /// gives for each agent. The default implementation returns `None` and the
+    /// agents are evaluated one by one.
+    fn evaluate_batch(&self, _batch: &mut dyn AgentBatch) -> Option<Vec<f64>> {
+        None
+    }
 }
@@ -0,0 +1,228 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::genetic_training::error::TrainingError;
+
+/// Opt-in speciation of the `Trainer` population, see `TrainingConfig::speciation`.
+///
+/// The agents are grouped in species by `Agent::distance`. The parents are
+/// shared between the species in proportion to the mean fitness of their
+/// members, shifted so that the worst agent of the generation has 0, and are
+/// selected within every species. The children are only crossed over with
+/// parents of their own species.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Speciation {
+    /// Two agents closer than this belong to the same species.
+    pub threshold: f64,
+    /// A species whose best fitness hasn't improved for this number of
+    /// generations is removed, unless it holds the best agent.
+    pub stagnation: usize,
+}
+
+impl Default for Speciation {
+    fn default() -> Self {
+        Speciation {
+            threshold: 1.,
+            stagnation: 15,
+        }
+    }
+}
+
+impl Speciation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn stagnation(mut self, stagnation: usize) -> Self {
+        self.stagnation = stagnation;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if !(self.threshold.is_finite() && self.threshold > 0.) {
+            return invalid(format!(
+                "the speciation threshold must be greater than 0, got {}",
+                self.threshold
+            ));
+        }
+        if self.stagnation == 0 {
+            return invalid("the speciation stagnation must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Agents whose distance to the representative is below the threshold.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Species<A> {
+    pub id: usize,
+    pub representative: A,
+    /// Index of the members in the current generation, best first.
+    #[serde(skip)]
+    pub members: Vec<usize>,
+    /// Best finite fitness of the species over the run.
+    pub best_fitness: Option<f64>,
+    /// Generation of the last improvement of `best_fitness`.
+    pub last_improvement: usize,
+}
+
+/// Statistics of one species in `GenerationStats::species`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeciesStats {
+    pub id: usize,
+    pub nb_members: usize,
+    /// Fitness statistics of the finite fitness values of the members, `None`
+    /// when none is finite.
+    pub best_fitness: Option<f64>,
+    pub mean_fitness: Option<f64>,
+    /// Number of parents, or of children for `Neat`, given to the species.
+    pub nb_offspring: usize,
+    pub generations_without_improvement: usize,
+}
+
+impl SpeciesStats {
+    pub(crate) fn new<A>(
+        species: &Species<A>,
+        fitness: &[f64],
+        nb_offspring: usize,
+        generation: usize,
+    ) -> Self {
+        let finite: Vec<f64> = species
+            .members
+            .iter()
+            .map(|&i| fitness[i])
+            .filter(|f| f.is_finite())
+            .collect();
+        let (best_fitness, mean_fitness) = if finite.is_empty() {
+            (None, None)
+        } else {
+            (
+                Some(finite.iter().cloned().fold(f64::NEG_INFINITY, f64::max)),
+                Some(finite.iter().sum::<f64>() / finite.len() as f64),
+            )
+        };
+
+        SpeciesStats {
+            id: species.id,
+            nb_members: species.members.len(),
+            best_fitness,
+            mean_fitness,
+            nb_offspring,
+            generations_without_improvement: generation - species.last_improvement,
+        }
+    }
+}
+
+/// Assigns every agent to the first species, in order, whose representative
+/// is closer than `threshold`, or to a new species. The species left without
+/// member are removed.
+pub(crate) fn speciate<'a, A, I, F>(
+    agents: I,
+    species: &mut Vec<Species<A>>,
+    threshold: f64,
+    distance: F,
+    next_id: &mut usize,
+    generation: usize,
+) where
+    A: Clone + 'a,
+    I: Iterator<Item = &'a A>,
+    F: Fn(&A, &A) -> f64,
+{
+    species.iter_mut().for_each(|s| s.members.clear());
+
+    for (i, agent) in agents.enumerate() {
+        match species
+            .iter_mut()
+            .find(|s| distance(&s.representative, agent) < threshold)
+        {
+            Some(s) => s.members.push(i),
+            None => {
+                species.push(Species {
+                    id: *next_id,
+                    representative: agent.clone(),
+                    members: vec![i],
+                    best_fitness: None,
+                    last_improvement: generation,
+                });
+                *next_id += 1;
+            }
+        }
+    }
+
+    species.retain(|s| !s.members.is_empty());
+}
+
+/// Sorts the members of every species from best to worst, records the
+/// improvements and removes the species stagnant for `stagnation`
+/// generations, except the one holding `best_index`.
+pub(crate) fn update_species<A>(
+    species: &mut Vec<Species<A>>,
+    fitness: &[f64],
+    best_index: usize,
+    stagnation: usize,
+    generation: usize,
+) {
+    for s in species.iter_mut() {
+        s.members
+            .sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+        let best = fitness[s.members[0]];
+        if best.is_finite() && s.best_fitness.is_none_or(|previous| best > previous) {
+            s.best_fitness = Some(best);
+            s.last_improvement = generation;
+        }
+    }
+    species.retain(|s| {
+        generation - s.last_improvement < stagnation || s.members.contains(&best_index)
+    });
+}
+
+/// Explicit fitness sharing: every member gets its fitness shifted by the
+/// worst finite value of the generation and divided by the size of its
+/// species, the non finite values get 0. Returns the sum for every species.
+pub(crate) fn shared_fitness<A>(species: &[Species<A>], fitness: &[f64]) -> Vec<f64> {
+    let worst = fitness
+        .iter()
+        .cloned()
+        .filter(|f| f.is_finite())
+        .fold(f64::INFINITY, f64::min);
+    species
+        .iter()
+        .map(|s| {
+            s.members
+                .iter()
+                .filter(|&&i| fitness[i].is_finite())
+                .map(|&i| fitness[i] - worst)
+                .sum::<f64>()
+                / s.members.len() as f64
+        })
+        .collect()
+}
+
+/// Splits `total` proportionally to `shares`, the remainder goes to the
+/// largest fractional parts. Equal split when every share is 0.
+pub(crate) fn offspring_quotas(shares: &[f64], total: usize) -> Vec<usize> {
+    let sum: f64 = shares.iter().sum();
+    let exact: Vec<f64> = if sum > 0. && sum.is_finite() {
+        shares.iter().map(|s| s / sum * total as f64).collect()
+    } else {
+        vec![total as f64 / shares.len() as f64; shares.len()]
+    };
+
+    let mut quotas: Vec<usize> = exact.iter().map(|q| q.floor() as usize).collect();
+    let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+    by_remainder
+        .sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    let missing = total - quotas.iter().sum::<usize>();
+    for &i in by_remainder.iter().take(missing) {
+        quotas[i] += 1;
+    }
+    quotas
+}
@@ -0,0 +1,137 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::genetic_training::speciation::SpeciesStats;
+use crate::genetic_training::stop::StopReason;
+
+/// Fitness statistics of one generation, computed before reproduction.
+///
+/// The fitness statistics only account for the finite fitness values, they
+/// are `None` when no fitness of the generation is finite.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GenerationStats {
+    pub generation: usize,
+    pub mutation_rate: f64,
+    pub nb_evaluated: usize,
+    /// Number of individuals whose fitness was NaN or infinite.
+    pub nb_non_finite: usize,
+    pub best_fitness: Option<f64>,
+    pub mean_fitness: Option<f64>,
+    pub median_fitness: Option<f64>,
+    pub std_fitness: Option<f64>,
+    pub worst_fitness: Option<f64>,
+    /// Fitness of the selected survivors, best first.
+    #[serde(with = "non_finite")]
+    pub survivors_fitness: Vec<f64>,
+    pub elapsed_ms: u128,
+    /// Statistics of every species, empty without speciation.
+    #[serde(default)]
+    pub species: Vec<SpeciesStats>,
+    /// Hypervolume of the Pareto front, only with the multi-objective `Nsga2`.
+    #[serde(default)]
+    pub hypervolume: Option<f64>,
+}
+
+impl GenerationStats {
+    /// `fitness` must be sorted from best to worst.
+    pub(crate) fn new(
+        generation: usize,
+        mutation_rate: f64,
+        (nb_evaluated, nb_non_finite): (usize, usize),
+        fitness: &[f64],
+        survivors_fitness: Vec<f64>,
+    ) -> Self {
+        let fitness: Vec<f64> = fitness.iter().cloned().filter(|f| f.is_finite()).collect();
+        let n = fitness.len();
+        let (best_fitness, worst_fitness, mean_fitness, median_fitness, std_fitness) = if n == 0 {
+            (None, None, None, None, None)
+        } else {
+            let mean = fitness.iter().sum::<f64>() / n as f64;
+            let variance = fitness.iter().map(|f| (f - mean).powi(2)).sum::<f64>() / n as f64;
+            let median = if n % 2 == 1 {
+                fitness[n / 2]
+            } else {
+                (fitness[n / 2 - 1] + fitness[n / 2]) / 2.
+            };
+            (
+                Some(fitness[0]),
+                Some(fitness[n - 1]),
+                Some(mean),
+                Some(median),
+                Some(variance.sqrt()),
+            )
+        };
+
+        GenerationStats {
+            generation,
+            mutation_rate,
+            nb_evaluated,
+            nb_non_finite,
+            best_fitness,
+            mean_fitness,
+            median_fitness,
+            std_fitness,
+            worst_fitness,
+            survivors_fitness,
+            elapsed_ms: 0,
+            species: Vec::new(),
+            hypervolume: None,
+        }
+    }
+}
+
+/// JSON has no NaN or infinity, the non finite values are written as the
+/// strings `"NaN"`, `"inf"` and `"-inf"`.
+mod non_finite {
+    use serde::de::{Deserializer, Error};
+    use serde::ser::Serializer;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Value<'a> {
+        Finite(f64),
+        NonFinite(&'a str),
+    }
+
+    pub fn serialize<S: Serializer>(values: &[f64], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|&value| {
+            if value.is_nan() {
+                Value::NonFinite("NaN")
+            } else if value == f64::INFINITY {
+                Value::NonFinite("inf")
+            } else if value == f64::NEG_INFINITY {
+                Value::NonFinite("-inf")
+            } else {
+                Value::Finite(value)
+            }
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+        let values: Vec<Value> = serde::de::Deserialize::deserialize(deserializer)?;
+        values
+            .into_iter()
+            .map(|value| match value {
+                Value::Finite(value) => Ok(value),
+                Value::NonFinite("NaN") => Ok(f64::NAN),
+                Value::NonFinite("inf") => Ok(f64::INFINITY),
+                Value::NonFinite("-inf") => Ok(f64::NEG_INFINITY),
+                Value::NonFinite(other) => Err(D::Error::custom(format!(
+                    "invalid fitness value: {}",
+                    other
+                ))),
+            })
+            .collect()
+    }
+}
+
+/// Summary handed to the observers once the training loop is over.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrainingSummary {
+    pub seed: u64,
+    pub nb_individus: usize,
+    pub nb_generation: usize,
+    pub nb_simulations: usize,
+    pub elapsed_ms: u128,
+    pub stop_reason: StopReason,
+}
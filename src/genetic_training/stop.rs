@@ -0,0 +1,98 @@
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::genetic_training::stats::GenerationStats;
+
+/// Condition checked at the end of every generation, the training stops as
+/// soon as one of the configured conditions is met.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StopCondition {
+    /// The best fitness of the generation reaches the value.
+    TargetFitness(f64),
+    /// The best fitness of the run hasn't improved for this number of generations.
+    NoImprovement { generations: usize },
+    /// Wall time of the current training session.
+    TimeBudget(Duration),
+    /// Total number of simulations of the run, a generation is never interrupted
+    /// so the budget can be exceeded by less than one generation.
+    EvaluationBudget(usize),
+}
+
+impl StopCondition {
+    /// `history` ends with the generation that just ran.
+    pub(crate) fn is_met(
+        &self,
+        history: &[GenerationStats],
+        nb_simulations: usize,
+        elapsed: Duration,
+    ) -> bool {
+        match *self {
+            StopCondition::TargetFitness(target) => history
+                .last()
+                .and_then(|stats| stats.best_fitness)
+                .is_some_and(|best| best >= target),
+            StopCondition::NoImprovement { generations } => {
+                let mut best = f64::NEG_INFINITY;
+                let mut last_improvement = 0;
+                for (i, stats) in history.iter().enumerate() {
+                    if let Some(fitness) = stats.best_fitness.filter(|&f| f > best) {
+                        best = fitness;
+                        last_improvement = i;
+                    }
+                }
+                history.len() - 1 - last_improvement >= generations
+            }
+            StopCondition::TimeBudget(budget) => elapsed >= budget,
+            StopCondition::EvaluationBudget(budget) => nb_simulations >= budget,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            StopCondition::TargetFitness(target) if !target.is_finite() => {
+                Err(format!("target fitness must be finite, got {}", target))
+            }
+            StopCondition::NoImprovement { generations: 0 } => {
+                Err("no improvement stop condition needs at least 1 generation".to_string())
+            }
+            StopCondition::EvaluationBudget(0) => {
+                Err("evaluation budget must be greater than 0".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Why a training run ended.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StopReason {
+    MaxGenerations,
+    Condition(StopCondition),
+    /// Name of the user predicate, see `Trainer::with_stop_predicate`.
+    Predicate(String),
+    /// The search converged and had no restart left, see `CmaEs`.
+    Converged,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::MaxGenerations => write!(f, "maximum number of generations reached"),
+            StopReason::Condition(StopCondition::TargetFitness(target)) => {
+                write!(f, "target fitness {} reached", target)
+            }
+            StopReason::Condition(StopCondition::NoImprovement { generations }) => {
+                write!(f, "no improvement for {} generations", generations)
+            }
+            StopReason::Condition(StopCondition::TimeBudget(budget)) => {
+                write!(f, "time budget of {:?} exhausted", budget)
+            }
+            StopReason::Condition(StopCondition::EvaluationBudget(budget)) => {
+                write!(f, "evaluation budget of {} simulations exhausted", budget)
+            }
+            StopReason::Predicate(name) => write!(f, "stop predicate \"{}\" met", name),
+            StopReason::Converged => write!(f, "converged with no restart left"),
+        }
+    }
+}
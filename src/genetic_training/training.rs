@@ -1,107 +1,568 @@
 use crate::genetic_training::agent::Agent;
+use crate::genetic_training::checkpoint::Checkpoint;
+use crate::genetic_training::config::{NonFinitePolicy, TrainingConfig};
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::{ConsoleObserver, TrainingObserver};
+use crate::genetic_training::result::TrainingResult;
+use crate::genetic_training::rng::{stream_rng, RngStream, TrainingRng};
+use crate::genetic_training::run::RunLoop;
+use crate::genetic_training::selection::{SelectionStrategy, Truncation};
 use crate::genetic_training::simulation::Simulation;
+use crate::genetic_training::speciation::{
+    offspring_quotas, shared_fitness, speciate, update_species, Speciation, Species, SpeciesStats,
+};
+use crate::genetic_training::stats::{GenerationStats, TrainingSummary};
+use crate::genetic_training::stop::StopReason;
 
+use rand::Rng;
 use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
 
-fn run_generation<A, S>(population: Vec<A>, simulation: Arc<S>) -> Vec<(A, f64)>
+pub(crate) fn run_generation<A, S>(
+    population: Vec<A>,
+    simulation: Arc<S>,
+    batch_size: Option<usize>,
+) -> Vec<(A, f64)>
 where
     A: Agent,
     S: Simulation,
 {
-    let results: Vec<(A, f64)> = population
-        .par_iter()
-        .map(|agent| {
-            let fitness = simulation.evaluate_agent(&mut agent.clone());
-            (agent.clone(), fitness) // Clone agent for the result tuple
+    let Some(batch_size) = batch_size else {
+        return population
+            .par_iter()
+            .map(|agent| {
+                let fitness = simulation.evaluate_agent(&mut agent.clone());
+                (agent.clone(), fitness) // Clone agent for the result tuple
+            })
+            .collect();
+    };
+
+    population
+        .par_chunks(batch_size)
+        .flat_map_iter(|agents| {
+            let fitness = A::stack(agents)
+                .and_then(|mut batch| simulation.evaluate_batch(batch.as_mut()))
+                .unwrap_or_else(|| {
+                    agents
+                        .iter()
+                        .map(|agent| simulation.evaluate_agent(&mut agent.clone()))
+                        .collect()
+                });
+            assert_eq!(
+                fitness.len(),
+                agents.len(),
+                "evaluate_batch must return one fitness per agent"
+            );
+            agents.iter().cloned().zip(fitness)
         })
-        .collect();
+        .collect()
+}
 
-    let mut results = results; // Move results to sort them
+/// Applies the non finite fitness `policy` and sorts the results from best to worst.
+/// Also returns the number of non finite fitness values.
+fn rank_results<A>(
+    mut results: Vec<(A, f64)>,
+    policy: NonFinitePolicy,
+    generation: usize,
+) -> Result<(Vec<(A, f64)>, usize), TrainingError> {
+    let nb_non_finite = results.iter().filter(|res| !res.1.is_finite()).count();
 
-    results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    if nb_non_finite > 0 {
+        match policy {
+            NonFinitePolicy::Worst => results
+                .iter_mut()
+                .filter(|res| !res.1.is_finite())
+                .for_each(|res| res.1 = f64::NEG_INFINITY),
+            NonFinitePolicy::Drop => results.retain(|res| res.1.is_finite()),
+            NonFinitePolicy::Fail => {
+                return Err(TrainingError::NonFiniteFitness {
+                    generation,
+                    nb_non_finite,
+                })
+            }
+        }
+    }
+    if results.is_empty() {
+        return Err(TrainingError::NonFiniteFitness {
+            generation,
+            nb_non_finite,
+        });
+    }
+
+    results.sort_by(|a, b| b.1.total_cmp(&a.1));
 
-    results
+    Ok((results, nb_non_finite))
 }
 
-fn reproduce<A>(mut population: Vec<A>, nb_individus: usize, mutation_rate: f64) -> Vec<A>
-where
+/// Keeps the `size` best finite fitness individuals seen so far, the oldest
+/// one comes first on ties.
+pub(crate) fn update_hall_of_fame<A>(
+    hall_of_fame: &mut Vec<(A, f64)>,
+    candidates: &[(A, f64)],
+    size: usize,
+) where
     A: Agent,
 {
-    let pop = population.len();
-    (0..(nb_individus - pop))
-        .for_each(|i| population.push(population[i % pop].mutate(mutation_rate)));
-    for agent in &mut population {
-        agent.reset();
-    }
-    population
+    let mut best: Vec<&(A, f64)> = candidates.iter().filter(|c| c.1.is_finite()).collect();
+    best.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    hall_of_fame.extend(best.into_iter().take(size).cloned());
+    hall_of_fame.sort_by(|a, b| b.1.total_cmp(&a.1));
+    hall_of_fame.truncate(size);
 }
 
-pub fn training_from_checkpoint<A, S>(
-    population: Vec<A>,
-    simulation: &mut S,
+/// Builds the next generation from the `elites`, copied unchanged, and the
+/// children of the selected `parents`. With several groups of parents, one per
+/// species, every group gets a share of the children proportional to its size
+/// and its children are only crossed over within the group.
+pub(crate) fn reproduce<A>(
+    mut elites: Vec<A>,
+    parents: &[Vec<A>],
     nb_individus: usize,
-    nb_generation: usize,
-    survivial_rate: f64,
     mutation_rate: f64,
-    mutation_decay: f64,
+    crossover_rate: f64,
+    (seed, generation, stream): (u64, usize, RngStream),
 ) -> Vec<A>
 where
     A: Agent,
-    S: Simulation,
 {
-    let nb_keep: usize = (nb_individus as f64 * survivial_rate) as usize;
+    let sizes: Vec<f64> = parents.iter().map(|group| group.len() as f64).collect();
+    let quotas = offspring_quotas(&sizes, nb_individus.saturating_sub(elites.len()));
+    let children: Vec<A> = parents
+        .iter()
+        .zip(quotas)
+        .flat_map(|(group, quota)| (0..quota).map(move |j| (group, j)))
+        .enumerate()
+        .map(|(i, (group, j))| {
+            let mut rng = stream_rng(seed, generation, i, stream);
+            let nb_parents = group.len();
+            let parent = &group[j % nb_parents];
+            if nb_parents > 1 && rng.gen::<f64>() < crossover_rate {
+                // Second parent drawn among the other selected parents
+                let offset = rng.gen_range(1..nb_parents);
+                let mate = &group[(j + offset) % nb_parents];
+                parent
+                    .crossover(mate, &mut rng)
+                    .mutate(mutation_rate, &mut rng)
+            } else {
+                parent.mutate(mutation_rate, &mut rng)
+            }
+        })
+        .collect();
+
+    elites.extend(children);
+    for agent in &mut elites {
+        agent.reset();
+    }
+    elites
+}
+
+/// Speciated selection: the `nb_keep` parents are shared between the species
+/// in proportion to their shared fitness, and every species selects its own
+/// among its members. Returns the parents of every species, as indices into
+/// the sorted `results`, and the species statistics.
+fn select_by_species<A>(
+    results: &[(A, f64)],
+    speciation: &Speciation,
+    (species, next_id): (&mut Vec<Species<A>>, &mut usize),
+    selection: &dyn SelectionStrategy,
+    nb_keep: usize,
+    generation: usize,
+    rng: &mut TrainingRng,
+) -> Result<(Vec<Vec<usize>>, Vec<SpeciesStats>), TrainingError>
+where
+    A: Agent,
+{
+    if results[0].0.distance(&results[0].0).is_none() {
+        return Err(TrainingError::InvalidConfig(
+            "speciation needs agents implementing Agent::distance".to_string(),
+        ));
+    }
 
-    let mut population: Vec<A> = reproduce(population, nb_individus * 10, mutation_rate);
+    let fitness: Vec<f64> = results.iter().map(|res| res.1).collect();
+    speciate(
+        results.iter().map(|res| &res.0),
+        species,
+        speciation.threshold,
+        |a, b| a.distance(b).unwrap_or(f64::INFINITY),
+        next_id,
+        generation,
+    );
+    // The results are sorted, the best agent is the first one
+    update_species(species, &fitness, 0, speciation.stagnation, generation);
+    let quotas = offspring_quotas(&shared_fitness(species, &fitness), nb_keep);
 
-    let s_time = Instant::now();
+    let mut groups = Vec::with_capacity(species.len());
+    let mut stats = Vec::with_capacity(species.len());
+    for (s, quota) in species.iter_mut().zip(quotas) {
+        let members_fitness: Vec<f64> = s.members.iter().map(|&i| fitness[i]).collect();
+        let selected: Vec<usize> = selection
+            .select(&members_fitness, quota, rng)
+            .into_iter()
+            .map(|k| s.members[k])
+            .collect();
+        groups.push(selected);
+        stats.push(SpeciesStats::new(s, &fitness, quota, generation));
+        // The next generation is compared to the best member of this one
+        s.representative = results[s.members[0]].0.clone();
+    }
 
-    for gen in 0..nb_generation {
-        let start_time = Instant::now();
-        let gen_mutation_rate = mutation_rate * mutation_decay.powf(gen as f64);
-        println!(
-            "Generation: {}     Mutation rate: {}",
-            gen,
-            (gen_mutation_rate * 10000.0).round() / 10000.0
-        );
-        let sim = Arc::new(simulation.clone());
+    Ok((groups, stats))
+}
 
-        let results = run_generation(population, sim);
+type StopPredicate = Box<dyn FnMut(&GenerationStats) -> bool>;
 
-        let surviviors: Vec<(A, f64)> = results.into_iter().take(nb_keep).collect();
+pub struct Trainer {
+    config: TrainingConfig,
+    observers: Vec<Box<dyn TrainingObserver>>,
+    selection: Box<dyn SelectionStrategy>,
+    stop_predicates: Vec<(String, StopPredicate)>,
+}
 
-        let scores: Vec<f64> = surviviors
-            .iter()
-            .map(|res| (res.1 * 10000.0).round() / 10000.0)
-            .collect();
-        println!("Best individuals fitness: {:?}", scores);
+impl Trainer {
+    /// Builds a silent trainer using truncation selection.
+    pub fn new(config: TrainingConfig) -> Result<Self, TrainingError> {
+        config.validate()?;
+        Ok(Trainer {
+            config,
+            observers: Vec::new(),
+            selection: Box::new(Truncation),
+            stop_predicates: Vec::new(),
+        })
+    }
+
+    /// The `nb_keep` parents of every generation are chosen by `selection`,
+    /// the elites are always the best individuals, or the best parents with
+    /// speciation.
+    pub fn with_selection<T>(mut self, selection: T) -> Self
+    where
+        T: SelectionStrategy + 'static,
+    {
+        self.selection = Box::new(selection);
+        self
+    }
+
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: TrainingObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Stops the training after the first generation whose stats satisfy
+    /// `predicate`, the result reports it with `StopReason::Predicate(name)`.
+    pub fn with_stop_predicate<F>(mut self, name: &str, predicate: F) -> Self
+    where
+        F: FnMut(&GenerationStats) -> bool + 'static,
+    {
+        self.stop_predicates
+            .push((name.to_string(), Box::new(predicate)));
+        self
+    }
+
+    pub fn config(&self) -> &TrainingConfig {
+        &self.config
+    }
+
+    pub fn train<A, S>(
+        &mut self,
+        population: Vec<A>,
+        simulation: &mut S,
+    ) -> Result<TrainingResult<A>, TrainingError>
+    where
+        A: Agent,
+        S: Simulation,
+    {
+        self.resume(Checkpoint::new(population), simulation)
+    }
+
+    /// Continues a training from `checkpoint`, the mutation rate schedule
+    /// picks up at the checkpoint generation and from its saved rate.
+    pub fn resume<A, S>(
+        &mut self,
+        checkpoint: Checkpoint<A>,
+        simulation: &mut S,
+    ) -> Result<TrainingResult<A>, TrainingError>
+    where
+        A: Agent,
+        S: Simulation,
+    {
+        self.run(checkpoint, simulation, &mut |_| Ok(()))
+            .and_then(|(state, result)| result.finish(state))
+    }
+
+    /// Same as `resume`, and writes the checkpoint to `path` every
+    /// `checkpoint_every` generations and at the end of the training.
+    pub fn train_with_checkpoints<A, S, P>(
+        &mut self,
+        checkpoint: Checkpoint<A>,
+        simulation: &mut S,
+        path: P,
+    ) -> Result<TrainingResult<A>, TrainingError>
+    where
+        A: Agent + Serialize,
+        S: Simulation,
+        P: AsRef<Path>,
+    {
+        let checkpoint_every = self.config.checkpoint_every;
+        self.run(checkpoint, simulation, &mut |state: &Checkpoint<A>| {
+            if state.generation.is_multiple_of(checkpoint_every) {
+                state.save(&path)?;
+            }
+            Ok(())
+        })
+        .and_then(|(state, result)| {
+            state.save(&path)?;
+            result.finish(state)
+        })
+    }
 
-        population = reproduce(
-            surviviors.into_iter().map(|sur| sur.0).collect(),
-            nb_individus,
-            gen_mutation_rate,
+    fn run<A, S>(
+        &mut self,
+        checkpoint: Checkpoint<A>,
+        simulation: &mut S,
+        on_checkpoint: &mut dyn FnMut(&Checkpoint<A>) -> Result<(), TrainingError>,
+    ) -> Result<(Checkpoint<A>, PartialResult<A>), TrainingError>
+    where
+        A: Agent,
+        S: Simulation,
+    {
+        if checkpoint.population.is_empty() {
+            return Err(TrainingError::EmptyPopulation);
+        }
+
+        let config = &self.config;
+        let nb_keep = config.nb_keep();
+        let nb_elites = config.nb_elites();
+
+        let mut run = RunLoop::new(
+            &config.run,
+            &mut self.observers,
+            checkpoint.seed.or(config.run.seed),
         );
+        let seed = run.seed();
+        // The decay schedule continues from the rate saved in the checkpoint,
+        // even if the config has changed since
+        let mut mutation_rate = match checkpoint.mutation_rate {
+            Some(rate) if checkpoint.generation > 0 => rate,
+            _ => config.mutation_rate_at(checkpoint.generation),
+        };
+        let mut state = Checkpoint {
+            seed: Some(seed),
+            ..checkpoint
+        };
+
+        // Number of elites at the head of the population, they were already
+        // considered for the hall of fame when they were first evaluated
+        let mut nb_carried = 0;
+        let mut last_generation: Vec<(A, f64)> = Vec::new();
 
-        simulation.on_generation(gen);
+        if state.generation == 0 {
+            let initial_size = config.nb_individus * config.initial_population_multiplier;
+            let population = std::mem::take(&mut state.population);
+            state.population = reproduce(
+                population.clone(),
+                std::slice::from_ref(&population),
+                initial_size,
+                config.mutation_rate,
+                0.,
+                (seed, 0, RngStream::Initial),
+            );
+        } else {
+            // Brings the simulation back to the state it had at the checkpoint
+            for gen in 0..state.generation {
+                simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+            }
+            nb_carried = nb_elites.min(state.population.len());
+        }
 
-        let end_time = Instant::now();
-        let elapsed_time = end_time.duration_since(start_time);
-        let elapsed_seconds = elapsed_time.as_millis();
+        for gen in state.generation..config.run.nb_generation {
+            run.start_generation(gen, mutation_rate)?;
+            let sim = Arc::new(simulation.clone());
 
-        println!("{} ms\n", elapsed_seconds);
+            let population = std::mem::take(&mut state.population);
+            let nb_evaluated = population.len();
+            state.nb_simulations += nb_evaluated;
+            let evaluated = run_generation(population, sim, config.batch_evaluation);
+            update_hall_of_fame(
+                &mut state.hall_of_fame,
+                &evaluated[nb_carried..],
+                config.run.hall_of_fame_size,
+            );
+            let (results, nb_non_finite) = rank_results(evaluated, config.non_finite_fitness, gen)?;
+
+            let fitness: Vec<f64> = results.iter().map(|res| res.1).collect();
+            let mut rng = stream_rng(seed, gen, 0, RngStream::Selection);
+            let (groups, species_stats) = match &config.speciation {
+                Some(speciation) => select_by_species(
+                    &results,
+                    speciation,
+                    (&mut state.species, &mut state.next_species_id),
+                    self.selection.as_ref(),
+                    nb_keep,
+                    gen,
+                    &mut rng,
+                )?,
+                None => (
+                    vec![self.selection.select(&fitness, nb_keep, &mut rng)],
+                    Vec::new(),
+                ),
+            };
+            let mut selected: Vec<usize> = groups.iter().flatten().cloned().collect();
+            selected.sort_unstable();
+            if selected.is_empty() {
+                return Err(TrainingError::InvalidConfig(
+                    "the selection strategy returned no parent".to_string(),
+                ));
+            }
+
+            let mut stats = GenerationStats::new(
+                gen,
+                mutation_rate,
+                (nb_evaluated, nb_non_finite),
+                &fitness,
+                selected.iter().map(|&i| fitness[i]).collect(),
+            );
+            stats.species = species_stats;
+
+            let surviviors: Vec<Vec<A>> = groups
+                .into_iter()
+                .filter(|group| !group.is_empty())
+                .map(|mut group| {
+                    group.sort_unstable();
+                    group.iter().map(|&i| results[i].0.clone()).collect()
+                })
+                .collect();
+            // With speciation, the elites are the best parents rather than
+            // the best individuals, which may all belong to one species
+            let elite_indices: Vec<usize> = if config.speciation.is_some() {
+                selected.dedup();
+                selected
+            } else {
+                (0..results.len()).collect()
+            };
+            let elites: Vec<A> = elite_indices
+                .iter()
+                .take(nb_elites)
+                .map(|&i| results[i].0.clone())
+                .collect();
+            nb_carried = elites.len();
+            last_generation = results;
+
+            state.population = reproduce(
+                elites,
+                &surviviors,
+                config.nb_individus,
+                mutation_rate,
+                config.crossover_rate,
+                (seed, gen, RngStream::Mutation),
+            );
+
+            simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+
+            let stop = run.end_generation(stats, &mut state.history, state.nb_simulations)?;
+            state.generation = gen + 1;
+            mutation_rate *= config.mutation_decay;
+            state.mutation_rate = Some(mutation_rate);
+            on_checkpoint(&state)?;
+
+            if stop {
+                break;
+            }
+            let stats = &state.history[state.history.len() - 1];
+            if let Some(reason) = self
+                .stop_predicates
+                .iter_mut()
+                .find_map(|(name, predicate)| {
+                    predicate(stats).then(|| StopReason::Predicate(name.clone()))
+                })
+            {
+                run.stop(reason);
+                break;
+            }
+        }
+
+        let summary = run.finish(config.nb_individus, state.generation, state.nb_simulations)?;
+
+        Ok((
+            state,
+            PartialResult {
+                population: last_generation,
+                summary,
+            },
+        ))
     }
+}
 
-    let end_time = Instant::now();
-    let elapsed_time = end_time.duration_since(s_time);
-    let elapsed_seconds = elapsed_time.as_millis();
-    println!(
-        "Total time: {} ms\nFor {} individuals for {} generations.\nFor a total of {} simulations",
-        elapsed_seconds,
-        nb_individus,
-        nb_generation,
-        nb_individus * nb_generation
-    );
+/// What the training loop produces besides the final checkpoint.
+struct PartialResult<A> {
+    population: Vec<(A, f64)>,
+    summary: TrainingSummary,
+}
 
-    population
+impl<A> PartialResult<A>
+where
+    A: Agent,
+{
+    fn finish(self, state: Checkpoint<A>) -> Result<TrainingResult<A>, TrainingError> {
+        TrainingResult::new(
+            state.hall_of_fame,
+            self.population,
+            state.history,
+            self.summary,
+        )
+    }
+}
+
+/// Positional shortcut kept for existing callers, prefer building a `Trainer`
+/// from a `TrainingConfig`.
+///
+/// Returns the population reproduced from the survivors of the last
+/// generation, or the initial population when `nb_generation` is 0.
+///
+/// # Panics
+///
+/// When the parameters are rejected by `TrainingConfig::validate`.
+pub fn training_from_checkpoint<A, S>(
+    population: Vec<A>,
+    simulation: &mut S,
+    nb_individus: usize,
+    nb_generation: usize,
+    survivial_rate: f64,
+    mutation_rate: f64,
+    mutation_decay: f64,
+) -> Vec<A>
+where
+    A: Agent,
+    S: Simulation,
+{
+    let config = TrainingConfig::new()
+        .nb_individus(nb_individus)
+        .nb_generation(nb_generation.max(1))
+        .survival_rate(survivial_rate)
+        .mutation_rate(mutation_rate)
+        .mutation_decay(mutation_decay);
+    let mut trainer = Trainer::new(config)
+        .unwrap_or_else(|err| panic!("{}", err))
+        .with_observer(ConsoleObserver);
+
+    if nb_generation == 0 {
+        let initial_size = nb_individus * trainer.config.initial_population_multiplier;
+        return reproduce(
+            population.clone(),
+            std::slice::from_ref(&population),
+            initial_size,
+            mutation_rate,
+            0.,
+            (rand::thread_rng().gen(), 0, RngStream::Initial),
+        );
+    }
+
+    trainer
+        .run(Checkpoint::new(population), simulation, &mut |_| Ok(()))
+        .map(|(state, _)| state.population)
+        .unwrap_or_else(|err| panic!("{}", err))
 }
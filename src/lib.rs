@@ -1,4 +1,5 @@
 pub mod genetic_training;
+pub mod neat;
 pub mod neuralnetwork;
 
 pub use genetic_training::*;
@@ -6,18 +7,1842 @@ pub use neuralnetwork::*;
 
 #[cfg(test)]
 mod tests {
-    use crate::layer::LinearLayer;
+    use crate::activation::Activation;
+    use crate::agent::{Agent, AgentBatch};
+    use crate::batch::{LayerRef, SequentialBatch};
+    use crate::checkpoint::Checkpoint;
+    use crate::cmaes::{CmaEs, CmaEsConfig, Restart};
+    use crate::config::{NonFinitePolicy, TrainingConfig};
+    use crate::crossover::Crossover;
+    use crate::error::TrainingError;
+    use crate::float::Float;
+    use crate::initializer::Initializer;
+    use crate::layer::{GRULayer, GRUMutation, LSTMLayer, LinearLayer};
+    use crate::matrix::Matrix;
+    use crate::mutation::{Bound, LayerMutation, MutationOperator, Noise};
+    use crate::neat::evolution::{Neat, NeatConfig};
+    use crate::neat::genome::{Compatibility, ConnectionGene, Genome, InnovationTracker};
+    use crate::nes::{Nes, NesConfig, Optimizer};
+    use crate::nsga2::{
+        crowding_distance, dominates, hypervolume, non_dominated_sort, Nsga2, Nsga2Config,
+    };
+    use crate::observer::TrainingObserver;
+    use crate::parameters::{parameter_distance, Parameters};
+    use crate::result::TrainingResult;
+    use crate::rng::TrainingRng;
+    use crate::selection::{
+        LinearRank, Roulette, SelectionStrategy, StochasticUniversalSampling, Tournament,
+        Truncation,
+    };
+    use crate::sequential::{Layer, Sequential};
+    use crate::simd::{Kernels, SimdLevel};
+    use crate::simulation::Simulation;
+    use crate::speciation::{Speciation, Species, SpeciesStats};
+    use crate::stats::GenerationStats;
+    use crate::stop::{StopCondition, StopReason};
+    use crate::training::{training_from_checkpoint, Trainer};
+    use rand::{Rng, SeedableRng};
+    use serde_derive::{Deserialize, Serialize};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct GuessAgent {
+        guess: f64,
+    }
+
+    impl Agent for GuessAgent {
+        fn step(&mut self, _input: &Matrix) -> Matrix {
+            Matrix::from_vec(1, 1, vec![self.guess])
+        }
+
+        fn reset(&mut self) {}
+
+        fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+            GuessAgent {
+                guess: self.guess + rng.gen_range(-mutation_rate..=mutation_rate),
+            }
+        }
+
+        fn crossover<R: Rng + ?Sized>(&self, other: &Self, _rng: &mut R) -> Self {
+            GuessAgent {
+                guess: (self.guess + other.guess) / 2.,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct GuessSimulation {
+        target: f64,
+        noise: f64,
+    }
+
+    impl GuessSimulation {
+        fn new(target: f64) -> Self {
+            GuessSimulation { target, noise: 0. }
+        }
+    }
+
+    impl Simulation for GuessSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            -(agent.step(&Matrix::default())[0][0] - self.target - self.noise).abs()
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, rng: &mut R) {
+            self.noise = rng.gen_range(-0.1..=0.1);
+        }
+    }
+
+    /// Diverges for negative guesses.
+    #[derive(Clone)]
+    struct UnstableSimulation;
+
+    impl Simulation for UnstableSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            let guess = agent.step(&Matrix::default())[0][0];
+            if guess < 0. {
+                f64::NAN
+            } else {
+                -guess
+            }
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+    }
+
+    /// Two steps of a sequence, the fitness is minus the error on the last one.
+    #[derive(Clone)]
+    struct SequenceSimulation {
+        nb_batches: Arc<AtomicUsize>,
+    }
+
+    impl SequenceSimulation {
+        const INPUTS: [[f64; 2]; 2] = [[1., -1.], [0.5, 0.]];
+
+        fn input(step: usize) -> Matrix {
+            Matrix::from_vec(1, 2, Self::INPUTS[step].to_vec())
+        }
+    }
+
+    impl Simulation for SequenceSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            agent.step(&Self::input(0));
+            -(agent.step(&Self::input(1))[0][0] - 0.25).abs()
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+
+        fn evaluate_batch(&self, batch: &mut dyn AgentBatch) -> Option<Vec<f64>> {
+            self.nb_batches.fetch_add(1, Ordering::Relaxed);
+            batch.step(&Self::input(0));
+            let output = batch.step(&Self::input(1));
+            Some(
+                output
+                    .iter_rows()
+                    .map(|row| -(row[0] - 0.25).abs())
+                    .collect(),
+            )
+        }
+    }
+
+    /// Agent whose output is its parameters, for the parameter space optimisers.
+    #[derive(Clone)]
+    struct PointAgent {
+        coords: Vec<f64>,
+    }
+
+    impl Agent for PointAgent {
+        fn step(&mut self, _input: &Matrix) -> Matrix {
+            Matrix::from_vec(1, self.coords.len(), self.coords.clone())
+        }
+
+        fn reset(&mut self) {}
+
+        fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+            PointAgent {
+                coords: self
+                    .coords
+                    .iter()
+                    .map(|x| x + rng.gen_range(-mutation_rate..=mutation_rate))
+                    .collect(),
+            }
+        }
+
+        fn distance(&self, other: &Self) -> Option<f64> {
+            Some(parameter_distance(self, other))
+        }
+    }
+
+    impl Parameters for PointAgent {
+        fn params(&self) -> Box<dyn Iterator<Item = &f64> + '_> {
+            Box::new(self.coords.iter())
+        }
+
+        fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut f64> + '_> {
+            Box::new(self.coords.iter_mut())
+        }
+    }
+
+    /// Fitness of minus a benchmark function of the agent output.
+    #[derive(Clone)]
+    struct FunctionSimulation {
+        function: fn(&[f64]) -> f64,
+    }
+
+    impl FunctionSimulation {
+        /// Minimum 0 at (1, ..., 1).
+        fn sphere() -> Self {
+            FunctionSimulation {
+                function: |x| x.iter().map(|x| (x - 1.).powi(2)).sum(),
+            }
+        }
+
+        /// Minimum 0 at 0, with a local minimum at every integer point.
+        fn rastrigin() -> Self {
+            FunctionSimulation {
+                function: |x| {
+                    x.iter()
+                        .map(|x| x * x - 10. * (2. * std::f64::consts::PI * x).cos() + 10.)
+                        .sum()
+                },
+            }
+        }
+    }
+
+    impl Simulation for FunctionSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            -(self.function)(agent.step(&Matrix::default()).row(0))
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+    }
+
+    /// Two conflicting objectives, the Pareto optimal agents are in [0, 2].
+    #[derive(Clone)]
+    struct SchafferSimulation;
+
+    impl Simulation for SchafferSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            self.evaluate_objectives(agent).iter().sum()
+        }
+
+        fn evaluate_objectives<A>(&self, agent: &mut A) -> Vec<f64>
+        where
+            A: Agent,
+        {
+            let x = agent.step(&Matrix::default())[(0, 0)];
+            vec![-x * x, -(x - 2.).powi(2)]
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+    }
+
+    /// XOR of the two inputs, evaluated on the 4 cases at once.
+    #[derive(Clone)]
+    struct XorSimulation;
+
+    impl Simulation for XorSimulation {
+        fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+        where
+            A: Agent,
+        {
+            let input = Matrix::from(vec![vec![0., 0.], vec![0., 1.], vec![1., 0.], vec![1., 1.]]);
+            let output = agent.step(&input);
+            let expected = [0., 1., 1., 0.];
+            4. - (0..4)
+                .map(|i| (output[(i, 0)] - expected[i]).powi(2))
+                .sum::<f64>()
+        }
+
+        fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+    }
+
+    struct RecurrentNet {
+        gru: GRULayer,
+        output: LinearLayer,
+    }
+
+    crate::impl_parameters!(RecurrentNet { gru, output });
+
+    #[derive(Clone, Default)]
+    struct HistoryObserver {
+        history: Rc<RefCell<Vec<GenerationStats>>>,
+    }
+
+    impl TrainingObserver for HistoryObserver {
+        fn on_generation_end(&mut self, stats: &GenerationStats) -> Result<(), TrainingError> {
+            self.history.borrow_mut().push(stats.clone());
+            Ok(())
+        }
+    }
 
     #[test]
     fn test_linear_layer() {
         let mut layer = LinearLayer::new(3, 2);
         layer.set_weights(vec![vec![1., 2., 3.], vec![2., 3., 4.]], vec![0., 0.]);
 
-        let input = vec![vec![1., 1., 1.], vec![0.5, 1., 1.5]];
+        let input = Matrix::from(vec![vec![1., 1., 1.], vec![0.5, 1., 1.5]]);
 
         let output = layer.forward(&input);
-        let target_output = vec![vec![6., 9.], vec![7., 10.]];
+        let target_output = Matrix::from(vec![vec![6., 9.], vec![7., 10.]]);
 
         assert_eq!(output, target_output);
     }
+
+    #[test]
+    fn test_training_config() {
+        assert!(TrainingConfig::new().validate().is_ok());
+        assert!(TrainingConfig::new().survival_rate(0.).validate().is_err());
+        assert!(TrainingConfig::new()
+            .nb_individus(10)
+            .survival_rate(0.05)
+            .validate()
+            .is_err());
+        assert!(TrainingConfig::new().elitism(11).validate().is_err());
+
+        let config =
+            TrainingConfig::from_json_str(r#"{"nb_individus": 20, "elitism": 1, "seed": 4}"#)
+                .unwrap();
+        assert_eq!(config.nb_individus, 20);
+        assert_eq!(config.nb_elites(), 1);
+        assert_eq!(config.run.seed, Some(4));
+        assert_eq!(
+            config.run.nb_generation,
+            TrainingConfig::default().run.nb_generation
+        );
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(TrainingConfig::from_json_str(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(5)
+            .survival_rate(0.2)
+            .mutation_rate(1.)
+            .initial_population_multiplier(2)
+            .elitism(1);
+        let mut trainer = Trainer::new(config).unwrap();
+        let mut simulation = GuessSimulation::new(3.);
+
+        let result = trainer
+            .train(vec![GuessAgent { guess: 0. }], &mut simulation)
+            .unwrap();
+        assert_eq!(result.population.len(), 20);
+        assert_eq!(result.history.len(), 5);
+        assert_eq!(result.hall_of_fame.len(), 10);
+        assert!(result.hall_of_fame.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert_eq!(result.best_fitness, result.hall_of_fame[0].1);
+        // Elites re-evaluated on a noisier generation are not candidates of
+        // the hall of fame, only the first generation is fully comparable
+        assert!(result.history[0].best_fitness.unwrap() <= result.best_fitness);
+        assert!(result.population.windows(2).all(|w| w[0].1 >= w[1].1));
+
+        assert!(trainer
+            .train(Vec::<GuessAgent>::new(), &mut simulation)
+            .is_err());
+    }
+
+    #[test]
+    fn test_training_observer() {
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(4)
+            .survival_rate(0.2)
+            .initial_population_multiplier(1);
+        let observer = HistoryObserver::default();
+        let mut trainer = Trainer::new(config)
+            .unwrap()
+            .with_observer(observer.clone());
+
+        trainer
+            .train(
+                vec![GuessAgent { guess: 0. }],
+                &mut GuessSimulation::new(1.),
+            )
+            .unwrap();
+
+        let history = observer.history.borrow();
+        assert_eq!(history.len(), 4);
+        for (gen, stats) in history.iter().enumerate() {
+            assert_eq!(stats.generation, gen);
+            assert_eq!(stats.nb_evaluated, 20);
+            assert_eq!(stats.survivors_fitness.len(), 4);
+            assert!(stats.best_fitness >= stats.median_fitness);
+            assert!(stats.median_fitness >= stats.worst_fitness);
+        }
+    }
+
+    #[test]
+    fn test_seeded_training_is_reproducible() {
+        let run = |seed: u64| {
+            let config = TrainingConfig::new()
+                .nb_individus(30)
+                .nb_generation(5)
+                .survival_rate(0.1)
+                .mutation_rate(2.)
+                .seed(seed);
+            let observer = HistoryObserver::default();
+            let mut trainer = Trainer::new(config)
+                .unwrap()
+                .with_observer(observer.clone());
+            let agents = trainer
+                .train(
+                    vec![GuessAgent { guess: 0. }],
+                    &mut GuessSimulation::new(5.),
+                )
+                .unwrap()
+                .agents();
+
+            let guesses: Vec<u64> = agents.iter().map(|a| a.guess.to_bits()).collect();
+            let history: Vec<Vec<u64>> = observer
+                .history
+                .borrow()
+                .iter()
+                .map(|stats| {
+                    stats
+                        .survivors_fitness
+                        .iter()
+                        .map(|f| f.to_bits())
+                        .collect()
+                })
+                .collect();
+            (guesses, history)
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_layer_crossover() {
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let mut a = LinearLayer::new(4, 3);
+        a.set_weights(vec![vec![1.; 4]; 3], vec![1.; 3]);
+        let mut b = LinearLayer::new(4, 3);
+        b.set_weights(vec![vec![-1.; 4]; 3], vec![-1.; 3]);
+
+        let uniform = a.crossover(&b, Crossover::Uniform, &mut rng);
+        assert!(uniform
+            .weights
+            .as_slice()
+            .iter()
+            .all(|w| *w == 1. || *w == -1.));
+
+        let single_point = a.crossover(&b, Crossover::SinglePoint, &mut rng);
+        let flat: Vec<f64> = single_point.weights.into_vec();
+        assert!(flat.windows(2).all(|w| w[0] >= w[1]));
+
+        let arithmetic = a.crossover(&b, Crossover::Arithmetic, &mut rng);
+        let blend = arithmetic.weights[0][0];
+        assert!(arithmetic.weights.as_slice().iter().all(|w| *w == blend));
+        assert!((-1. ..=1.).contains(&blend));
+
+        let gru_a: GRULayer = GRULayer::new_with_rng(2, 3, 1, &mut rng);
+        let gru_b = GRULayer::new_with_rng(2, 3, 1, &mut rng);
+        let child = gru_a.crossover(&gru_b, Crossover::Uniform, &mut rng);
+        for (i, u) in child.u_update.as_slice().iter().enumerate() {
+            let (x, y) = (gru_a.u_update[i / 3][i % 3], gru_b.u_update[i / 3][i % 3]);
+            assert!(*u == x || *u == y);
+        }
+    }
+
+    #[test]
+    fn test_training_with_crossover() {
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(5)
+            .survival_rate(0.2)
+            .crossover_rate(1.)
+            .seed(1);
+        let result = Trainer::new(config)
+            .unwrap()
+            .train(
+                vec![GuessAgent { guess: -4. }, GuessAgent { guess: 4. }],
+                &mut GuessSimulation::new(0.),
+            )
+            .unwrap();
+        assert_eq!(result.population.len(), 20);
+        assert!(TrainingConfig::new()
+            .crossover_rate(1.5)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_selection_strategies() {
+        let mut rng = TrainingRng::seed_from_u64(3);
+        let fitness = vec![4., 3., 2., 1., 0.];
+
+        assert_eq!(Truncation.select(&fitness, 2, &mut rng), vec![0, 1]);
+
+        let strategies: Vec<Box<dyn SelectionStrategy>> = vec![
+            Box::new(Tournament { size: 3 }),
+            Box::new(Roulette),
+            Box::new(LinearRank::default()),
+            Box::new(StochasticUniversalSampling),
+        ];
+        for strategy in strategies.iter() {
+            let selected = strategy.select(&fitness, 1000, &mut rng);
+            assert_eq!(selected.len(), 1000);
+            let count = |i: usize| selected.iter().filter(|s| **s == i).count();
+            assert!(count(0) > count(3));
+            assert!(selected.iter().all(|s| *s < fitness.len()));
+        }
+
+        // The worst individual has no weight once the fitness is shifted
+        let sus = StochasticUniversalSampling.select(&fitness, 10, &mut rng);
+        assert_eq!(sus.iter().filter(|s| **s == 0).count(), 4);
+        assert!(!sus.contains(&4));
+
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(3)
+            .survival_rate(0.2)
+            .elitism(1);
+        let result = Trainer::new(config)
+            .unwrap()
+            .with_selection(Tournament { size: 2 })
+            .train(
+                vec![GuessAgent { guess: 0. }],
+                &mut GuessSimulation::new(2.),
+            )
+            .unwrap();
+        assert_eq!(result.population.len(), 20);
+    }
+
+    #[test]
+    fn test_non_finite_fitness() {
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(3)
+            .survival_rate(0.2)
+            .mutation_rate(2.)
+            .seed(0);
+        let population = vec![GuessAgent { guess: 0. }];
+
+        for policy in [NonFinitePolicy::Worst, NonFinitePolicy::Drop] {
+            let observer = HistoryObserver::default();
+            let trained = Trainer::new(config.clone().non_finite_fitness(policy))
+                .unwrap()
+                .with_observer(observer.clone())
+                .train(population.clone(), &mut UnstableSimulation)
+                .unwrap();
+            assert!(trained.best_fitness.is_finite());
+
+            let history = observer.history.borrow();
+            assert!(history[0].nb_non_finite > 0);
+            assert!(history.iter().all(|stats| stats.best_fitness.is_some()));
+            assert!(history[0].survivors_fitness.iter().all(|f| f.is_finite()));
+        }
+
+        let result = Trainer::new(config.non_finite_fitness(NonFinitePolicy::Fail))
+            .unwrap()
+            .train(population, &mut UnstableSimulation);
+        assert!(matches!(
+            result,
+            Err(TrainingError::NonFiniteFitness { generation: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let config = TrainingConfig::new()
+            .nb_individus(20)
+            .nb_generation(6)
+            .survival_rate(0.2)
+            .mutation_rate(1.)
+            .mutation_decay(0.9)
+            .checkpoint_every(3)
+            .seed(11);
+        let population = vec![GuessAgent { guess: 0. }];
+
+        let uninterrupted = Trainer::new(config.clone())
+            .unwrap()
+            .train(population.clone(), &mut GuessSimulation::new(4.))
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("genetic_rl_{}.json", std::process::id()));
+        Trainer::new(config.clone().nb_generation(3))
+            .unwrap()
+            .train_with_checkpoints(
+                Checkpoint::new(population),
+                &mut GuessSimulation::new(4.),
+                &path,
+            )
+            .unwrap();
+
+        let checkpoint: Checkpoint<GuessAgent> = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.generation, 3);
+        assert_eq!(checkpoint.seed, Some(11));
+        assert_eq!(checkpoint.mutation_rate, Some(1. * 0.9 * 0.9 * 0.9));
+
+        let resumed = Trainer::new(config)
+            .unwrap()
+            .resume(checkpoint, &mut GuessSimulation::new(4.))
+            .unwrap();
+
+        let guesses = |population: &[(GuessAgent, f64)]| -> Vec<u64> {
+            population.iter().map(|a| a.0.guess.to_bits()).collect()
+        };
+        assert_eq!(
+            guesses(&uninterrupted.population),
+            guesses(&resumed.population)
+        );
+        assert_eq!(
+            guesses(&uninterrupted.hall_of_fame),
+            guesses(&resumed.hall_of_fame)
+        );
+        assert_eq!(resumed.history.len(), 6);
+
+        // The schedule continues from the saved rate, not from the new config
+        let mut checkpoint = Checkpoint::new(vec![GuessAgent { guess: 0. }]);
+        checkpoint.generation = 3;
+        checkpoint.mutation_rate = Some(0.5);
+        checkpoint.seed = Some(11);
+        let resumed = Trainer::new(
+            TrainingConfig::new()
+                .nb_individus(20)
+                .nb_generation(5)
+                .mutation_decay(0.9),
+        )
+        .unwrap()
+        .resume(checkpoint, &mut GuessSimulation::new(4.))
+        .unwrap();
+        let rates: Vec<f64> = resumed.history.iter().map(|s| s.mutation_rate).collect();
+        assert_eq!(rates, vec![0.5, 0.5 * 0.9]);
+    }
+
+    #[test]
+    fn test_training_from_checkpoint() {
+        let population = vec![GuessAgent { guess: 0. }];
+        let initial = training_from_checkpoint(
+            population.clone(),
+            &mut GuessSimulation::new(4.),
+            10,
+            0,
+            0.2,
+            1.,
+            1.,
+        );
+        assert_eq!(initial.len(), 100);
+        assert_eq!(initial[0].guess, 0.);
+
+        let trained = training_from_checkpoint(
+            population,
+            &mut GuessSimulation::new(4.),
+            10,
+            2,
+            0.2,
+            1.,
+            1.,
+        );
+        assert_eq!(trained.len(), 10);
+    }
+
+    #[test]
+    fn test_checkpoint_reload() {
+        let path =
+            std::env::temp_dir().join(format!("genetic_rl_reload_{}.json", std::process::id()));
+        let population = vec![GuessAgent { guess: -1. }];
+
+        Checkpoint::new(population.clone()).save(&path).unwrap();
+        let checkpoint: Checkpoint<GuessAgent> = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.mutation_rate, None);
+        assert_eq!(checkpoint.population.len(), 1);
+
+        // Every fitness is NaN, the statistics have no finite value
+        let config = TrainingConfig::new()
+            .nb_individus(10)
+            .nb_generation(1)
+            .mutation_rate(0.)
+            .seed(3);
+        let result = Trainer::new(config).unwrap().train_with_checkpoints(
+            Checkpoint::new(population),
+            &mut UnstableSimulation,
+            &path,
+        );
+        assert!(result.is_err());
+
+        let checkpoint: Checkpoint<GuessAgent> = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let stats = &checkpoint.history[0];
+        assert_eq!(stats.nb_non_finite, stats.nb_evaluated);
+        assert_eq!(stats.best_fitness, None);
+        assert!(stats
+            .survivors_fitness
+            .iter()
+            .all(|&f| f == f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn test_stop_conditions() {
+        let config = TrainingConfig::new()
+            .nb_individus(10)
+            .nb_generation(20)
+            .survival_rate(0.2)
+            .mutation_rate(0.)
+            .initial_population_multiplier(2);
+        let population = vec![GuessAgent { guess: 1. }];
+        let run = |config: TrainingConfig| {
+            Trainer::new(config)
+                .unwrap()
+                .train(population.clone(), &mut UnstableSimulation)
+                .unwrap()
+        };
+
+        let result = run(config.clone());
+        assert_eq!(result.summary.stop_reason, StopReason::MaxGenerations);
+        assert_eq!(result.history.len(), 20);
+
+        // The fitness never changes without mutation
+        let result = run(config.clone().patience(3));
+        assert_eq!(
+            result.summary.stop_reason,
+            StopReason::Condition(StopCondition::NoImprovement { generations: 3 })
+        );
+        assert_eq!(result.summary.nb_generation, 4);
+
+        let result = run(config.clone().target_fitness(-1.));
+        assert_eq!(
+            result.summary.stop_reason,
+            StopReason::Condition(StopCondition::TargetFitness(-1.))
+        );
+        assert_eq!(result.history.len(), 1);
+
+        // 20 simulations for the oversampled first generation, then 10 per generation
+        let result = run(config.clone().evaluation_budget(45));
+        assert_eq!(result.summary.nb_simulations, 50);
+        assert_eq!(result.history.len(), 4);
+
+        let result = run(config.clone().time_budget(Duration::ZERO));
+        assert_eq!(
+            result.summary.stop_reason,
+            StopReason::Condition(StopCondition::TimeBudget(Duration::ZERO))
+        );
+
+        let result = Trainer::new(config.clone())
+            .unwrap()
+            .with_stop_predicate("third generation", |stats| stats.generation == 2)
+            .train(population.clone(), &mut UnstableSimulation)
+            .unwrap();
+        assert_eq!(
+            result.summary.stop_reason,
+            StopReason::Predicate("third generation".to_string())
+        );
+        assert_eq!(result.history.len(), 3);
+
+        assert!(Trainer::new(config.patience(0)).is_err());
+    }
+
+    #[test]
+    fn test_parameters() {
+        let mut rng = TrainingRng::seed_from_u64(3);
+        let mut layer = LinearLayer::new_with_rng(3, 2, &mut rng);
+        assert_eq!(layer.num_params(), 8);
+        assert_eq!(
+            layer.to_flat_vec(),
+            [layer.weights.as_slice().to_vec(), layer.bias.clone()].concat()
+        );
+
+        let mut net = RecurrentNet {
+            gru: GRULayer::new_with_rng(2, 3, 1, &mut rng),
+            output: LinearLayer::new_with_rng(3, 1, &mut rng),
+        };
+        assert_eq!(net.num_params(), 3 * (6 + 9 + 3) + 4);
+        assert_eq!(net.params().count(), net.num_params());
+
+        let flat: Vec<f64> = (0..net.num_params()).map(|i| i as f64).collect();
+        net.from_flat_vec(&flat);
+        assert_eq!(net.to_flat_vec(), flat);
+        assert_eq!(net.gru.w_reset[1], vec![2., 3.]);
+        assert_eq!(net.gru.u_reset[0], vec![6., 7., 8.]);
+        assert_eq!(net.output.bias, vec![57.]);
+
+        layer.params_mut().for_each(|param| *param = 0.5);
+        assert_eq!(
+            layer.forward(&Matrix::from_vec(1, 3, vec![1.; 3])),
+            Matrix::from_vec(1, 2, vec![2.; 2])
+        );
+    }
+
+    #[test]
+    fn test_gru_mutation() {
+        let mut rng = TrainingRng::seed_from_u64(11);
+        let layer = GRULayer::new_with_rng(3, 8, 1, &mut rng);
+        let groups = |layer: &GRULayer| {
+            vec![
+                layer.w_reset.as_slice().to_vec(),
+                layer.u_reset.as_slice().to_vec(),
+                layer.b_reset.clone(),
+                layer.w_update.as_slice().to_vec(),
+                layer.u_update.as_slice().to_vec(),
+                layer.b_update.clone(),
+                layer.w_candidate.as_slice().to_vec(),
+                layer.u_candidate.as_slice().to_vec(),
+                layer.b_candidate.clone(),
+            ]
+        };
+
+        let mutated = layer.mutate(1., &mut rng);
+        for (before, after) in groups(&layer).iter().zip(groups(&mutated).iter()) {
+            assert_ne!(before, after);
+        }
+
+        // Only the recurrence evolves
+        let frozen = layer.clone().with_mutation(GRUMutation {
+            input_weights: false,
+            biases: false,
+            ..GRUMutation::default()
+        });
+        let mutated = frozen.mutate(1., &mut rng);
+        for (i, (before, after)) in groups(&layer)
+            .iter()
+            .zip(groups(&mutated).iter())
+            .enumerate()
+        {
+            assert_eq!(i % 3 == 1, before != after);
+        }
+        assert_eq!(mutated.mutation, frozen.mutation);
+
+        let still = layer
+            .clone()
+            .with_mutation(GRUMutation {
+                recurrent_coef: 0.,
+                ..GRUMutation::default()
+            })
+            .mutate(1., &mut rng);
+        assert_eq!(still.u_update, layer.u_update);
+    }
+
+    #[test]
+    fn test_lstm_layer() {
+        let mut rng = TrainingRng::seed_from_u64(5);
+        let mut layer = LSTMLayer::new_with_rng(2, 6, 3, &mut rng);
+        let input = Matrix::from(vec![vec![1., -1.], vec![0.5, 0.], vec![0., 0.]]);
+
+        let first = layer.forward(&input);
+        assert_eq!(first.shape(), (3, 6));
+        assert_eq!(first[2], vec![0.; 6]);
+        // The memory changes the output of the same input
+        assert_ne!(layer.forward(&input), first);
+        layer.reset();
+        assert_eq!(layer.cell_state, Matrix::zeros(3, 6));
+        assert_eq!(layer.forward(&input), first);
+        assert_eq!(layer.num_params(), 4 * (12 + 36 + 6));
+
+        let groups = |layer: &LSTMLayer| {
+            let peepholes = layer.peepholes.as_ref().unwrap();
+            vec![
+                layer.w_input.as_slice().to_vec(),
+                layer.u_input.as_slice().to_vec(),
+                layer.b_input.clone(),
+                layer.w_forget.as_slice().to_vec(),
+                layer.u_forget.as_slice().to_vec(),
+                layer.b_forget.clone(),
+                layer.w_output.as_slice().to_vec(),
+                layer.u_output.as_slice().to_vec(),
+                layer.b_output.clone(),
+                layer.w_candidate.as_slice().to_vec(),
+                layer.u_candidate.as_slice().to_vec(),
+                layer.b_candidate.clone(),
+                peepholes.input.clone(),
+                peepholes.forget.clone(),
+                peepholes.output.clone(),
+            ]
+        };
+        let peephole = LSTMLayer::new_with_rng(2, 6, 3, &mut rng).with_peepholes();
+        assert_eq!(peephole.num_params(), 4 * (12 + 36 + 6) + 18);
+        let mutated = peephole.mutate(1., &mut rng).mutate(1., &mut rng);
+        for (before, after) in groups(&peephole).iter().zip(groups(&mutated).iter()) {
+            assert_ne!(before, after);
+        }
+
+        let mut peephole = mutated;
+        let json = serde_json::to_string(&peephole).unwrap();
+        let mut loaded: LSTMLayer = serde_json::from_str(&json).unwrap();
+        assert!(loaded.peepholes.is_some());
+        assert!(loaded
+            .forward(&input)
+            .as_slice()
+            .iter()
+            .zip(peephole.forward(&input).as_slice().iter())
+            .all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_sequential() {
+        let model = Sequential::new()
+            .linear(2, 8)
+            .relu()
+            .gru(8, 4)
+            .linear(4, 1)
+            .sigmoid();
+        assert_eq!(model.layers().len(), 5);
+        assert_eq!(
+            (model.input_size(), model.output_size()),
+            (Some(2), Some(1))
+        );
+        assert_eq!(model.num_params(), 24 + 3 * (32 + 16 + 4) + 5);
+        assert!(model.summary().ends_with("Total params: 185"));
+
+        let mut rng = TrainingRng::seed_from_u64(1);
+        let mut mutated = model.mutate(1., &mut rng);
+        let output = mutated.step(&Matrix::from_vec(1, 2, vec![1., 0.]));
+        assert_eq!(output.shape(), (1, 1));
+        assert!(output[0][0] > 0. && output[0][0] < 1.);
+        mutated.reset();
+        assert_eq!(mutated.step(&Matrix::from_vec(1, 2, vec![1., 0.])), output);
+
+        let child = model.crossover(&mutated, &mut rng);
+        assert_eq!(child.num_params(), model.num_params());
+
+        let json = serde_json::to_string(&child).unwrap();
+        let loaded: Sequential = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.summary(), child.summary());
+
+        let seeded = || {
+            let mut rng = TrainingRng::seed_from_u64(2);
+            Sequential::new()
+                .linear_with_initializer(2, 4, &Initializer::HeUniform, &mut rng)
+                .lstm_with_initializers(
+                    4,
+                    3,
+                    &Initializer::LeCun,
+                    &Initializer::Orthogonal(1.),
+                    &mut rng,
+                )
+                .gru_with_initializers(
+                    3,
+                    2,
+                    &Initializer::XavierNormal,
+                    &Initializer::Zeros,
+                    &mut rng,
+                )
+        };
+        assert_eq!(seeded().to_flat_vec(), seeded().to_flat_vec());
+        assert_eq!(seeded().output_size(), Some(2));
+
+        let mismatch = std::panic::catch_unwind(|| Sequential::new().linear(2, 8).gru(4, 4));
+        assert!(mismatch.is_err());
+        assert!(Sequential::new()
+            .linear(2, 8)
+            .try_layer(Layer::Linear(LinearLayer::new(4, 1)))
+            .is_err());
+
+        // The second linear layer of a file edited by hand takes 3 inputs
+        let mut json: serde_json::Value =
+            serde_json::to_value(Sequential::new().linear(2, 2).linear(2, 1)).unwrap();
+        json["layers"][1]["Linear"]["weights"] = serde_json::json!([[1., 2., 3.]]);
+        let err = serde_json::from_value::<Sequential>(json).err().unwrap();
+        assert!(err.to_string().contains("layer 1 takes 3 inputs"));
+    }
+
+    #[test]
+    fn test_activations() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+        let cases = [
+            (Activation::Identity, -2., -2.),
+            (Activation::ReLU, -2., 0.),
+            (Activation::LeakyReLU(0.1), -2., -0.2),
+            (Activation::ELU(1.), -1., -0.632121),
+            (Activation::SELU, 1., 1.050701),
+            (Activation::GELU, 1., 0.841192),
+            (Activation::Sigmoid, 0., 0.5),
+            (Activation::Tanh, 1., 0.761594),
+            (Activation::Swish, 1., 0.731059),
+            (Activation::Softplus, 0., 2f64.ln()),
+            (Activation::Softplus, 1000., 1000.),
+            (Activation::Softsign, 1., 0.5),
+            (Activation::HardTanh, 3., 1.),
+            (Activation::HardSigmoid, 1.5, 0.75),
+            (Activation::Gaussian, 1., (-1f64).exp()),
+            (Activation::Sine, 0.5, 0.5f64.sin()),
+            (Activation::Step, 0., 1.),
+        ];
+        for (activation, x, expected) in cases {
+            assert!(
+                close(activation.activate(x), expected),
+                "{:?}({})",
+                activation,
+                x
+            );
+        }
+
+        let mut values = Matrix::from(vec![vec![1., 2., 3.], vec![1000., 1000., 1000.]]);
+        Activation::Softmax.apply_in_place(&mut values);
+        assert!(close(values[0].iter().sum(), 1.));
+        assert!(values[0][0] < values[0][1] && values[0][1] < values[0][2]);
+        assert!(values[1].iter().all(|&x| close(x, 1. / 3.)));
+
+        let json = serde_json::to_string(&Activation::LeakyReLU(0.2)).unwrap();
+        let activation: Activation = serde_json::from_str(&json).unwrap();
+        assert_eq!(activation, Activation::LeakyReLU(0.2));
+        let swish: Activation = serde_json::from_str("\"SiLU\"").unwrap();
+        assert_eq!(swish, Activation::Swish);
+
+        let mut rng = TrainingRng::seed_from_u64(0);
+        assert!(Activation::ALL.contains(&Activation::random(&mut rng)));
+    }
+
+    #[test]
+    fn test_matrix() {
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let (m, k, n) = (70, 90, 65);
+        let a = Matrix::from_vec(m, k, (0..m * k).map(|_| rng.gen::<f64>()).collect());
+        let b = Matrix::from_vec(k, n, (0..k * n).map(|_| rng.gen::<f64>()).collect());
+
+        let product = a.matmul(&b);
+        assert_eq!(product.shape(), (m, n));
+        for i in 0..m {
+            for j in 0..n {
+                let expected: f64 = (0..k).map(|p| a[(i, p)] * b[(p, j)]).sum();
+                assert!((product[(i, j)] - expected).abs() < 1e-9);
+            }
+        }
+
+        let b_transposed =
+            Matrix::from_vec(n, k, (0..n * k).map(|idx| b[(idx % k, idx / k)]).collect());
+        let mut out = Matrix::default();
+        a.matmul_transposed_into(&b_transposed, &mut out);
+        assert!(out
+            .as_slice()
+            .iter()
+            .zip(product.as_slice())
+            .all(|(x, y)| (x - y).abs() < 1e-9));
+
+        let mut bias = Matrix::from(vec![vec![1., 2.], vec![3., 4.]]);
+        bias.add_row(&[1., -1.]);
+        assert_eq!(bias.to_rows(), vec![vec![2., 1.], vec![4., 3.]]);
+
+        let json = serde_json::to_string(&bias).unwrap();
+        assert_eq!(json, r#"{"rows":2,"cols":2,"data":[2.0,1.0,4.0,3.0]}"#);
+        assert_eq!(serde_json::from_str::<Matrix>(&json).unwrap(), bias);
+        let empty = Matrix::<f64>::zeros(0, 3);
+        let json = serde_json::to_string(&empty).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Matrix>(&json).unwrap().shape(),
+            (0, 3)
+        );
+        assert!(serde_json::from_str::<Matrix>(r#"{"rows":2,"cols":2,"data":[1.0]}"#).is_err());
+        // Matrices saved as lists of rows
+        assert_eq!(
+            serde_json::from_str::<Matrix>("[[2.0,1.0],[4.0,3.0]]").unwrap(),
+            bias
+        );
+        assert!(serde_json::from_str::<Matrix>("[[1.0],[1.0,2.0]]").is_err());
+        let column_overflow = std::panic::catch_unwind(|| bias[(0, 2)]);
+        assert!(column_overflow.is_err());
+
+        let shape_mismatch = std::panic::catch_unwind(|| a.matmul(&a));
+        assert!(shape_mismatch.is_err());
+    }
+
+    #[test]
+    fn test_f32_precision() {
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let layer: GRULayer = GRULayer::new_with_rng(2, 3, 1, &mut rng);
+        let mut layer_f64 = layer.clone();
+        let mut layer_f32: GRULayer<f32> =
+            serde_json::from_str(&serde_json::to_string(&layer).unwrap()).unwrap();
+        assert_eq!(layer_f32.num_params(), layer.num_params());
+
+        let input = Matrix::from_vec(1, 2, vec![0.5, -1.]);
+        for _ in 0..3 {
+            let output_f64 = layer_f64.forward(&input);
+            let output_f32 = layer_f32.forward(&input.cast());
+            assert!(output_f64
+                .as_slice()
+                .iter()
+                .zip(output_f32.as_slice())
+                .all(|(x, y)| (x - *y as f64).abs() < 1e-5));
+        }
+
+        let mutated = layer_f32.mutate(1., &mut rng);
+        assert_ne!(mutated.to_flat_vec(), layer_f32.to_flat_vec());
+        let json = serde_json::to_string(&mutated).unwrap();
+        let loaded: GRULayer<f32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.to_flat_vec(), mutated.to_flat_vec());
+
+        struct Net {
+            gru: GRULayer<f32>,
+            output: LinearLayer<f32>,
+        }
+        crate::impl_parameters!(Net: f32 { gru, output });
+        let net = Net {
+            gru: mutated,
+            output: LinearLayer::new_with_rng(3, 1, &mut rng),
+        };
+        assert_eq!(net.to_flat_vec().len(), layer.num_params() + 4);
+
+        let mut model = Sequential::<f32>::with_batch_size(2)
+            .lstm(2, 4)
+            .linear(4, 1)
+            .sigmoid();
+        let output = model.step(&Matrix::from(vec![vec![1., 0.], vec![0., 1.]]));
+        assert_eq!(output.shape(), (2, 1));
+        assert!(output.as_slice().iter().all(|&x| (0. ..=1.).contains(&x)));
+        let child = model.mutate(0.5, &mut rng).crossover(&model, &mut rng);
+        assert_eq!(child.num_params(), model.num_params());
+    }
+
+    #[test]
+    fn test_initializers() {
+        fn gram(m: &Matrix) -> Matrix {
+            m.matmul_transposed(m)
+        }
+        fn is_identity(m: &Matrix) -> bool {
+            let (rows, cols) = m.shape();
+            (0..rows).all(|i| (0..cols).all(|j| (m[(i, j)] - (i == j) as u8 as f64).abs() < 1e-9))
+        }
+
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let wide: Matrix = Initializer::Orthogonal(1.).init(7, 4, &mut rng);
+        assert_eq!(wide.shape(), (4, 7));
+        assert!(is_identity(&gram(&wide)));
+        let tall: Matrix = Initializer::Orthogonal(1.).init(3, 6, &mut rng);
+        let columns = Matrix::from_vec(
+            3,
+            3,
+            (0..9)
+                .map(|k| (0..6).map(|r| tall[(r, k / 3)] * tall[(r, k % 3)]).sum())
+                .collect(),
+        );
+        assert!(is_identity(&columns));
+
+        let he: Matrix = Initializer::HeNormal.init(50, 200, &mut rng);
+        let variance = he.as_slice().iter().map(|w| w * w).sum::<f64>() / 10000.;
+        assert!((variance - 2. / 50.).abs() < 0.004);
+        let xavier: Matrix = Initializer::XavierUniform.init(10, 20, &mut rng);
+        assert!(xavier
+            .as_slice()
+            .iter()
+            .all(|w| w.abs() < (6f64 / 30.).sqrt()));
+        // The default constructors keep the amplitude of the first versions
+        let default: GRULayer = GRULayer::new_with_rng(3, 4, 1, &mut TrainingRng::seed_from_u64(5));
+        let scaled: GRULayer = GRULayer::new_with_initializers(
+            3,
+            4,
+            1,
+            &Initializer::ScaledUniform,
+            &Initializer::ScaledUniform,
+            &mut TrainingRng::seed_from_u64(5),
+        );
+        assert_eq!(default.to_flat_vec(), scaled.to_flat_vec());
+        let linear: LinearLayer = LinearLayer::new_with_rng(10, 20, &mut rng);
+        assert!(linear
+            .weights
+            .as_slice()
+            .iter()
+            .all(|w| w.abs() < (2f64 / 30.).sqrt()));
+
+        let zeros: Matrix<f32> = Initializer::Zeros.init(2, 3, &mut rng);
+        assert!(zeros.as_slice().iter().all(|&w| w == 0.));
+        let constant: Matrix = Initializer::Constant(0.5).init(2, 3, &mut rng);
+        assert!(constant.as_slice().iter().all(|&w| w == 0.5));
+        let custom = Initializer::custom(|fan_in, fan_out, rng| {
+            (fan_in * fan_out) as f64 + rng.gen_range(0. ..0.5)
+        });
+        let custom: Matrix = custom.init(2, 3, &mut rng);
+        assert!(custom.as_slice().iter().all(|&w| (6. ..6.5).contains(&w)));
+
+        let build = |seed| {
+            let mut rng = TrainingRng::seed_from_u64(seed);
+            let gru: GRULayer = GRULayer::new_with_initializers(
+                2,
+                5,
+                1,
+                &Initializer::HeUniform,
+                &Initializer::Orthogonal(1.),
+                &mut rng,
+            );
+            gru
+        };
+        let gru = build(3);
+        assert_eq!(gru.to_flat_vec(), build(3).to_flat_vec());
+        assert_eq!(gru.w_reset.shape(), (5, 2));
+        assert!(is_identity(&gram(&gru.u_candidate)));
+
+        let lstm: LSTMLayer<f32> = LSTMLayer::new_with_initializers(
+            2,
+            3,
+            1,
+            &Initializer::LeCun,
+            &Initializer::Zeros,
+            &mut rng,
+        );
+        assert!(lstm.b_forget.iter().all(|&b| b == 1.));
+        let linear: LinearLayer =
+            LinearLayer::new_with_initializer(4, 2, &Initializer::Constant(1.), &mut rng);
+        assert_eq!(linear.weights.as_slice(), &[1.; 8]);
+    }
+
+    #[test]
+    fn test_mutation_operators() {
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let zeros = vec![0f64; 1000];
+
+        let mut weights = zeros.clone();
+        MutationOperator::gaussian(1.)
+            .sparse(0.1)
+            .mutate(&mut weights, 1., &mut rng);
+        let mutated = weights.iter().filter(|&&w| w != 0.).count();
+        assert!((50..150).contains(&mutated));
+
+        let mut weights = zeros.clone();
+        MutationOperator::cauchy(10.)
+            .bounded(Bound::Clamp(0.5))
+            .mutate(&mut weights, 1., &mut rng);
+        assert!(weights.iter().all(|w| w.abs() <= 0.5));
+        assert!(weights.iter().any(|w| w.abs() == 0.5));
+
+        let mut weights = vec![5f64; 100];
+        MutationOperator::reset(0.2).mutate(&mut weights, 1e-3, &mut rng);
+        assert!(weights.iter().all(|w| w.abs() < 0.2));
+
+        let mut weights = vec![3.; 100];
+        MutationOperator::weights().mutate(&mut weights, 10., &mut rng);
+        assert!(weights.iter().all(|&w| w <= 3.));
+        let mut weights = zeros.clone();
+        MutationOperator::gaussian(1.).mutate(&mut weights, 0., &mut rng);
+        assert_eq!(weights, zeros);
+
+        let mutation = LayerMutation {
+            weights: MutationOperator::gaussian(0.5).bounded(Bound::Clamp(1.)),
+            biases: MutationOperator::uniform(0.1).sparse(0.),
+        };
+        let layer: LSTMLayer = LSTMLayer::new_with_rng(2, 3, 1, &mut rng)
+            .with_peepholes()
+            .with_mutation(mutation);
+        let child = layer.mutate(1., &mut rng);
+        assert_eq!(child.b_forget, layer.b_forget);
+        assert_eq!(child.mutation, mutation);
+        assert!(child.w_input.as_slice().iter().all(|w| w.abs() <= 1.));
+        assert_ne!(child.w_input, layer.w_input);
+
+        let linear: LinearLayer<f32> =
+            LinearLayer::new_with_rng(2, 2, &mut rng).with_mutation(LayerMutation {
+                weights: MutationOperator::cauchy(0.1),
+                ..LayerMutation::default()
+            });
+        let json = serde_json::to_string(&linear).unwrap();
+        let loaded: LinearLayer<f32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.mutation.weights.noise, Noise::Cauchy);
+        let legacy = r#"{"weights":[[1.0]],"bias":[0.0]}"#;
+        let loaded: LinearLayer = serde_json::from_str(legacy).unwrap();
+        assert_eq!(loaded.mutation, LayerMutation::default());
+
+        let invalid = [
+            MutationOperator {
+                scale: -1.,
+                ..MutationOperator::gaussian(1.)
+            },
+            MutationOperator {
+                probability: 1.5,
+                ..MutationOperator::uniform(1.)
+            },
+            MutationOperator {
+                bound: Bound::Clamp(f64::NAN),
+                ..MutationOperator::cauchy(1.)
+            },
+            MutationOperator {
+                bound: Bound::Soft(0.),
+                ..MutationOperator::reset(1.)
+            },
+        ];
+        for operator in invalid {
+            assert!(operator.validate().is_err());
+            let mutation = LayerMutation {
+                weights: operator,
+                ..LayerMutation::default()
+            };
+            let layer: LinearLayer = LinearLayer::new_with_rng(1, 1, &mut rng);
+            assert!(layer.clone().try_with_mutation(mutation).is_err());
+            assert!(std::panic::catch_unwind(|| layer.with_mutation(mutation)).is_err());
+        }
+        let gru: GRULayer = GRULayer::new_with_rng(1, 1, 1, &mut rng);
+        let negative_coef = GRUMutation {
+            recurrent_coef: -1.,
+            ..GRUMutation::default()
+        };
+        assert!(gru.try_with_mutation(negative_coef).is_err());
+        assert!(serde_json::from_str::<GRUMutation>(r#"{"recurrent_coef": -1.0}"#).is_err());
+        let loaded: GRUMutation = serde_json::from_str(r#"{"biases": false}"#).unwrap();
+        assert_eq!(
+            loaded,
+            GRUMutation {
+                biases: false,
+                ..GRUMutation::default()
+            }
+        );
+        assert!(std::panic::catch_unwind(|| MutationOperator::gaussian(-1.)).is_err());
+        assert!(
+            std::panic::catch_unwind(|| MutationOperator::uniform(1.).bounded(Bound::Soft(0.)))
+                .is_err()
+        );
+        let negative_scale =
+            r#"{"noise":"Gaussian","scale":-1.0,"probability":1.0,"bound":"Unbounded"}"#;
+        assert!(serde_json::from_str::<MutationOperator>(negative_scale).is_err());
+    }
+
+    #[test]
+    fn test_cmaes() {
+        let start = PointAgent {
+            coords: vec![-2., 3., 0.5, 4., -1.],
+        };
+        let config = CmaEsConfig::new()
+            .sigma(1.)
+            .nb_generation(1000)
+            .seed(7)
+            .target_fitness(-1e-10);
+        let train = |config: CmaEsConfig| {
+            CmaEs::new(config)
+                .unwrap()
+                .train(start.clone(), &mut FunctionSimulation::sphere())
+                .unwrap()
+        };
+        let result = train(config.clone());
+        assert!(result.best_fitness >= -1e-10);
+        assert!(result
+            .best_agent
+            .coords
+            .iter()
+            .all(|x| (x - 1.).abs() < 1e-4));
+        assert!(result.summary.nb_generation < 400);
+        assert_eq!(result.history[0].nb_evaluated, 8);
+        assert_eq!(result.best_fitness, train(config).best_fitness);
+
+        // A local optimum of Rastrigin, the restarts double the population
+        let rastrigin = |restart| {
+            let config = CmaEsConfig::new()
+                .sigma(2.)
+                .nb_generation(2000)
+                .tol_fun(1e-8)
+                .seed(1)
+                .restart(restart);
+            CmaEs::new(config)
+                .unwrap()
+                .train(start.clone(), &mut FunctionSimulation::rastrigin())
+                .unwrap()
+        };
+        let single = rastrigin(Restart::Never);
+        assert_eq!(single.summary.stop_reason, StopReason::Converged);
+        let ipop = rastrigin(Restart::Ipop { max_restarts: 4 });
+        assert_eq!(ipop.summary.stop_reason, StopReason::Converged);
+        let sizes: Vec<usize> = ipop
+            .history
+            .iter()
+            .map(|stats| stats.nb_evaluated)
+            .collect();
+        assert_eq!(sizes.last(), Some(&(8 << 4)));
+        assert!(ipop.best_fitness >= single.best_fitness);
+        let bipop = rastrigin(Restart::Bipop { max_restarts: 6 });
+        assert!(bipop.history.iter().any(|stats| stats.nb_evaluated < 8));
+        assert!(bipop.best_fitness >= single.best_fitness);
+
+        assert!(CmaEs::new(CmaEsConfig::new().sigma(0.)).is_err());
+        assert!(CmaEs::new(CmaEsConfig::new().population_size(1)).is_err());
+        let no_params = CmaEs::new(CmaEsConfig::new()).unwrap().train(
+            PointAgent { coords: vec![] },
+            &mut FunctionSimulation::sphere(),
+        );
+        assert!(no_params.is_err());
+    }
+
+    #[test]
+    fn test_nes() {
+        let start = PointAgent {
+            coords: vec![0.; 20],
+        };
+        let config = NesConfig::new()
+            .nb_pairs(20)
+            .sigma(0.1)
+            .learning_rate(0.05)
+            .weight_decay(0.)
+            .nb_generation(150)
+            .seed(3);
+        let train = |config: NesConfig| {
+            Nes::new(config)
+                .unwrap()
+                .train(start.clone(), &mut FunctionSimulation::sphere())
+                .unwrap()
+        };
+        let result = train(config.clone());
+        assert_eq!(result.history[0].nb_evaluated, 41);
+        assert_eq!(result.summary.nb_simulations, 150 * 41 + 1);
+        assert!(result.history[0].survivors_fitness[0] == -20.);
+        assert!(result.best_fitness > -0.1);
+        assert_eq!(result.best_fitness, train(config.clone()).best_fitness);
+
+        let sgd = train(
+            config
+                .clone()
+                .optimizer(Optimizer::Sgd { momentum: 0.9 })
+                .learning_rate(0.02),
+        );
+        assert!(sgd.best_fitness > -1.);
+
+        // The decay pulls the centre towards 0, away from the optimum at 1
+        let decayed = train(config.clone().weight_decay(1.));
+        let centre_mean = |result: &TrainingResult<PointAgent>| {
+            result.best_agent.coords.iter().sum::<f64>() / 20.
+        };
+        assert!(centre_mean(&decayed) < 0.8 * centre_mean(&result));
+
+        // The centre moved by the last update is evaluated after the last generation
+        let one_step = train(config.nb_generation(1).learning_rate(0.2));
+        assert_eq!(one_step.summary.nb_simulations, 42);
+        assert!(one_step.best_fitness > one_step.population[0].1);
+
+        assert!(Nes::new(NesConfig::new().nb_pairs(0)).is_err());
+        assert!(Nes::new(NesConfig::new().sigma(-1.)).is_err());
+        assert!(Nes::new(NesConfig::new().optimizer(Optimizer::Sgd { momentum: 1. })).is_err());
+    }
+
+    #[test]
+    fn test_neat() {
+        let config = NeatConfig::new()
+            .population_size(150)
+            .nb_generation(300)
+            .target_fitness(3.9)
+            .seed(0);
+        let result = Neat::new(config.clone())
+            .unwrap()
+            .train(2, 1, &mut XorSimulation)
+            .unwrap();
+        assert!(result.best_fitness > 3.9);
+        assert!(result.best_agent.genome.nb_hidden() >= 1);
+        assert_eq!(result.history[0].nb_evaluated, 150);
+        let replay = Neat::new(config)
+            .unwrap()
+            .train(2, 1, &mut XorSimulation)
+            .unwrap();
+        assert_eq!(replay.best_fitness, result.best_fitness);
+        assert_eq!(replay.best_agent.genome, result.best_agent.genome);
+
+        // An output looping on itself keeps a state between the steps
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let mut tracker = InnovationTracker::new(1, 1);
+        let mut genome = Genome::new(1, 1, Activation::Tanh, &mut tracker, &mut rng);
+        genome.connections.push(ConnectionGene {
+            innovation: tracker.connection(2, 2),
+            from: 2,
+            to: 2,
+            weight: 1.,
+            enabled: true,
+        });
+        let mut network = genome.network();
+        let input = Matrix::from(vec![vec![1.]]);
+        let first = network.step(&input)[(0, 0)];
+        assert_ne!(network.step(&input)[(0, 0)], first);
+        network.reset();
+        assert_eq!(network.step(&input)[(0, 0)], first);
+
+        // Structural mutations share their innovation numbers
+        let mut other = Genome::new(1, 1, Activation::Tanh, &mut tracker, &mut rng);
+        let compatibility = Compatibility::default();
+        assert!(genome.distance(&other, &compatibility) > 0.);
+        assert!(other.add_node(&mut tracker, Activation::Tanh, &mut rng));
+        assert_eq!(other.nb_hidden(), 1);
+        assert_eq!(other.nb_enabled(), 3);
+        let child = other.crossover(&genome, &mut rng);
+        assert_eq!(child.nodes, other.nodes);
+        assert_eq!(child.connections.len(), other.connections.len());
+        assert_eq!(child.distance(&child, &compatibility), 0.);
+
+        assert!(Neat::new(NeatConfig::new().population_size(1)).is_err());
+        assert!(Neat::new(NeatConfig::new().add_node_rate(2.)).is_err());
+        assert!(
+            Neat::new(NeatConfig::new().activations(Activation::Softmax, Activation::Sigmoid))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_speciation() {
+        let a = Sequential::new().linear(2, 3).linear(3, 1);
+        let b = a.mutate(0.5, &mut TrainingRng::seed_from_u64(0));
+        assert_eq!(a.distance(&a), Some(0.));
+        assert!(a.distance(&b).unwrap() > 0.);
+        assert_eq!(a.distance(&b), b.distance(&a));
+        assert_eq!(
+            a.distance(&Sequential::new().linear(2, 1)),
+            Some(f64::INFINITY)
+        );
+
+        let config = TrainingConfig::new()
+            .nb_individus(60)
+            .nb_generation(40)
+            .survival_rate(0.2)
+            .mutation_rate(0.3)
+            .mutation_decay(0.95)
+            .initial_population_multiplier(1)
+            .speciation(Speciation::new().threshold(1.).stagnation(5))
+            .seed(4);
+        let mut rng = TrainingRng::seed_from_u64(1);
+        let population: Vec<PointAgent> = (0..60)
+            .map(|_| PointAgent {
+                coords: (0..2).map(|_| rng.gen_range(-4. ..4.)).collect(),
+            })
+            .collect();
+        let train = |config: TrainingConfig| {
+            Trainer::new(config)
+                .unwrap()
+                .train(population.clone(), &mut FunctionSimulation::rastrigin())
+        };
+        let result = train(config.clone()).unwrap();
+        for stats in result.history.iter() {
+            assert!(!stats.species.is_empty());
+            let nb_members: usize = stats.species.iter().map(|s| s.nb_members).sum();
+            assert!(nb_members <= stats.nb_evaluated);
+            let nb_parents: usize = stats.species.iter().map(|s| s.nb_offspring).sum();
+            assert_eq!(nb_parents, 12);
+            assert_eq!(stats.survivors_fitness.len(), 12);
+            assert!(stats
+                .species
+                .iter()
+                .all(|s| s.generations_without_improvement < 5
+                    || s.best_fitness == stats.best_fitness));
+        }
+        assert!(result.history.iter().any(|stats| stats.species.len() > 1));
+        let json = serde_json::to_string(&result.history).unwrap();
+        let loaded: Vec<GenerationStats> = serde_json::from_str(&json).unwrap();
+        let species_ids = |history: &[GenerationStats]| -> Vec<Vec<usize>> {
+            history
+                .iter()
+                .map(|stats| stats.species.iter().map(|s| s.id).collect())
+                .collect()
+        };
+        assert_eq!(species_ids(&loaded), species_ids(&result.history));
+
+        // A species without any finite fitness
+        let diverged = Species {
+            id: 0,
+            representative: GuessAgent { guess: -1. },
+            members: vec![0, 1],
+            best_fitness: None,
+            last_improvement: 0,
+        };
+        let stats = SpeciesStats::new(&diverged, &[f64::NAN, f64::NEG_INFINITY], 0, 2);
+        assert_eq!((stats.best_fitness, stats.mean_fitness), (None, None));
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(serde_json::from_str::<SpeciesStats>(&json).unwrap(), stats);
+        assert!(result.best_fitness > -1.);
+        assert_eq!(
+            result.best_fitness,
+            train(config.clone()).unwrap().best_fitness
+        );
+
+        // Agents without a distance can't be speciated
+        let guess = Trainer::new(config.clone()).unwrap().train(
+            vec![GuessAgent { guess: 0. }],
+            &mut GuessSimulation::new(0.5),
+        );
+        assert!(matches!(guess, Err(TrainingError::InvalidConfig(_))));
+        assert!(config
+            .speciation(Speciation::new().threshold(0.))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_nsga2() {
+        assert!(dominates(&[1., 2.], &[1., 1.]));
+        assert!(!dominates(&[1., 1.], &[1., 1.]));
+        assert!(!dominates(&[2., 0.], &[1., 1.]));
+        let points = vec![
+            vec![1., 3.],
+            vec![2., 2.],
+            vec![3., 1.],
+            vec![1., 1.],
+            vec![0., 0.],
+        ];
+        assert_eq!(
+            non_dominated_sort(&points),
+            vec![vec![0, 1, 2], vec![3], vec![4]]
+        );
+        assert_eq!(
+            crowding_distance(&points, &[0, 1, 2]),
+            vec![f64::INFINITY, 2., f64::INFINITY]
+        );
+        assert_eq!(hypervolume(&points, &[0., 0.]), 6.);
+        assert_eq!(hypervolume(&points, &[1., 1.]), 1.);
+        let cubes = vec![vec![2., 1., 1.], vec![1., 2., 1.]];
+        assert_eq!(hypervolume(&cubes, &[0., 0., 0.]), 3.);
+
+        let config = Nsga2Config::new()
+            .population_size(40)
+            .nb_generation(50)
+            .mutation_rate(0.5)
+            .mutation_decay(0.95)
+            .reference_point(vec![-10., -10.])
+            .seed(2);
+        let population = vec![PointAgent { coords: vec![5.] }];
+        let train = |config: Nsga2Config| {
+            Nsga2::new(config)
+                .unwrap()
+                .train(population.clone(), &mut SchafferSimulation)
+        };
+        let result = train(config.clone()).unwrap();
+        assert_eq!(result.population.len(), 40);
+        assert_eq!(result.summary.nb_simulations, 40 + 49 * 80);
+        assert!(result.pareto_front.len() >= 20);
+        assert!(result
+            .pareto_front
+            .iter()
+            .all(|(agent, _)| (-0.05..=2.05).contains(&agent.coords[0])));
+        assert!(result
+            .pareto_front
+            .windows(2)
+            .all(|w| w[0].1[0] >= w[1].1[0]));
+        let hypervolume = |stats: &GenerationStats| stats.hypervolume.unwrap();
+        assert!(hypervolume(&result.history[49]) > hypervolume(&result.history[0]));
+        // 100 minus the area of 8/3 between the true front and the axes
+        assert!(hypervolume(&result.history[49]) > 96.);
+        assert_eq!(
+            result.pareto_front[0].1,
+            train(config.clone()).unwrap().pareto_front[0].1
+        );
+
+        let mismatch = train(config.clone().reference_point(vec![0.; 3]));
+        assert!(matches!(mismatch, Err(TrainingError::InvalidConfig(_))));
+        assert!(Nsga2::new(Nsga2Config::new().population_size(1)).is_err());
+
+        /// No objective, or the second one only defined below 3.
+        #[derive(Clone)]
+        struct PartialSchaffer {
+            nb_objectives: usize,
+        }
+
+        impl Simulation for PartialSchaffer {
+            fn evaluate_agent<A>(&self, agent: &mut A) -> f64
+            where
+                A: Agent,
+            {
+                self.evaluate_objectives(agent).iter().sum()
+            }
+
+            fn evaluate_objectives<A>(&self, agent: &mut A) -> Vec<f64>
+            where
+                A: Agent,
+            {
+                let x = agent.step(&Matrix::default())[(0, 0)];
+                let second = if x < 3. { -(x - 2.).powi(2) } else { f64::NAN };
+                vec![-x * x, second][..self.nb_objectives].to_vec()
+            }
+
+            fn on_generation<R: Rng + ?Sized>(&mut self, _generation_number: usize, _rng: &mut R) {}
+        }
+
+        let mut config = config;
+        config.reference_point = None;
+        let train = |nb_objectives| {
+            Nsga2::new(config.clone())
+                .unwrap()
+                .train(population.clone(), &mut PartialSchaffer { nb_objectives })
+        };
+        assert!(matches!(train(0), Err(TrainingError::InvalidConfig(_))));
+        // The reference waits for a generation where the second objective is finite
+        let result = train(2).unwrap();
+        assert_eq!(result.history[0].hypervolume, None);
+        assert!(result.history[49].hypervolume.unwrap() > 0.);
+    }
+
+    #[test]
+    fn test_simd_kernels() {
+        fn check<T: Float>(rng: &mut TrainingRng) {
+            for n in [0, 1, 3, 8, 17, 64, 1001] {
+                let a: Vec<T> = (0..n)
+                    .map(|_| T::from_f64(rng.gen_range(-1. ..1.)))
+                    .collect();
+                let b: Vec<T> = (0..n)
+                    .map(|_| T::from_f64(rng.gen_range(-1. ..1.)))
+                    .collect();
+                let alpha = T::from_f64(0.3);
+                let expected_dot = T::dot(SimdLevel::Scalar, &a, &b);
+                let bound = T::from_f64(n as f64)
+                    * T::epsilon()
+                    * a.iter()
+                        .zip(b.iter())
+                        .map(|(x, y)| (*x * *y).abs())
+                        .sum::<T>();
+                let mut expected_axpy = b.clone();
+                T::axpy(SimdLevel::Scalar, alpha, &a, &mut expected_axpy);
+                let mut expected_mul = a.clone();
+                <T as Kernels>::mul_assign(SimdLevel::Scalar, &mut expected_mul, &b);
+
+                for level in SimdLevel::supported() {
+                    assert!((T::dot(level, &a, &b) - expected_dot).abs() <= bound);
+                    assert_eq!(
+                        T::dot4(level, &a, [&b, &a, &b, &b]),
+                        [&b, &a, &b, &b].map(|x| T::dot(level, &a, x))
+                    );
+
+                    let mut axpy = b.clone();
+                    T::axpy(level, alpha, &a, &mut axpy);
+                    for ((y, expected), (x, y0)) in axpy
+                        .iter()
+                        .zip(expected_axpy.iter())
+                        .zip(a.iter().zip(b.iter()))
+                    {
+                        let bound = T::epsilon() * ((alpha * *x).abs() + y0.abs());
+                        assert!((*y - *expected).abs() <= bound);
+                    }
+
+                    let mut mul = a.clone();
+                    <T as Kernels>::mul_assign(level, &mut mul, &b);
+                    assert_eq!(mul, expected_mul);
+                    let mut add = a.clone();
+                    <T as Kernels>::add_assign(level, &mut add, &b);
+                    <T as Kernels>::sub_assign(level, &mut add, &b);
+                    assert!(add
+                        .iter()
+                        .zip(a.iter())
+                        .all(|(x, y)| (*x - *y).abs() <= T::epsilon() * T::from_f64(2.)));
+                }
+            }
+        }
+
+        let mut rng = TrainingRng::seed_from_u64(0);
+        check::<f64>(&mut rng);
+        check::<f32>(&mut rng);
+        assert!(SimdLevel::supported().contains(&SimdLevel::detect()));
+    }
+
+    #[test]
+    fn test_population_batch() {
+        let mut rng = TrainingRng::seed_from_u64(0);
+        let model = |rng: &mut TrainingRng| {
+            Sequential::with_batch_size(2)
+                .layer(Layer::Gru(GRULayer::new_with_rng(2, 4, 2, rng)))
+                .layer(Layer::Lstm(
+                    LSTMLayer::new_with_rng(4, 3, 2, rng).with_peepholes(),
+                ))
+                .tanh()
+                .layer(Layer::Linear(LinearLayer::new_with_rng(3, 1, rng)))
+                .mutate(1., rng)
+        };
+        let mut models: Vec<Sequential> = (0..5).map(|_| model(&mut rng)).collect();
+
+        let mut batch = SequentialBatch::stack(&models).unwrap();
+        assert_eq!(batch.nb_agents(), 5);
+        let input = Matrix::from(vec![vec![1., -1.], vec![0.5, 0.]]);
+        for _ in 0..3 {
+            let output = AgentBatch::step(&mut batch, &input);
+            let expected = Matrix::concat_rows(
+                models
+                    .iter_mut()
+                    .map(|model| model.step(&input))
+                    .collect::<Vec<_>>()
+                    .iter(),
+            );
+            assert_eq!(*output, expected);
+        }
+
+        AgentBatch::reset(&mut batch);
+        models.iter_mut().for_each(|model| model.reset());
+        let inputs = Matrix::from_vec(10, 2, (0..20).map(|i| i as f64 / 10. - 1.).collect());
+        let output = batch.step_each(&inputs);
+        for (i, model) in models.iter_mut().enumerate() {
+            let input = Matrix::from_vec(2, 2, inputs.as_slice()[i * 4..(i + 1) * 4].to_vec());
+            assert_eq!(
+                model.step(&input).as_slice(),
+                &output.as_slice()[i * 2..(i + 1) * 2]
+            );
+        }
+
+        let mismatch = [
+            Sequential::new().linear(2, 1),
+            Sequential::new().linear(2, 2),
+        ];
+        assert!(SequentialBatch::stack(&mismatch).is_none());
+        assert!(<GuessAgent as Agent>::stack(&[GuessAgent { guess: 0. }]).is_none());
+
+        // Agents holding their layers, wide enough for the blocked sums
+        let layers: Vec<(LinearLayer<f32>, LinearLayer<f32>)> = (0..3)
+            .map(|_| {
+                (
+                    LinearLayer::new_with_rng(70, 9, &mut rng),
+                    LinearLayer::new_with_rng(9, 2, &mut rng),
+                )
+            })
+            .collect();
+        let models: Vec<Vec<LayerRef<f32>>> = layers
+            .iter()
+            .map(|(hidden, output)| vec![hidden.into(), Activation::Tanh.into(), output.into()])
+            .collect();
+        let mut batch = SequentialBatch::stack_layers(&models).unwrap();
+        let input = Matrix::from_vec(1, 70, (0..70).map(|i| i as f64 / 35. - 1.).collect());
+        let expected: Vec<f64> = layers
+            .iter()
+            .flat_map(|(hidden, output)| {
+                let hidden = Activation::Tanh.apply(hidden.clone().forward(&input.cast()));
+                output.clone().forward(&hidden).cast::<f64>().into_vec()
+            })
+            .collect();
+        assert_eq!(
+            AgentBatch::step(&mut batch, &input).as_slice(),
+            &expected[..]
+        );
+
+        // Batched and one by one evaluations give the same training
+        let config = TrainingConfig::new()
+            .nb_individus(12)
+            .nb_generation(3)
+            .survival_rate(0.25)
+            .initial_population_multiplier(1)
+            .seed(1);
+        let population: Vec<Sequential> = (0..12)
+            .map(|_| Sequential::new().gru(2, 3).linear(3, 1))
+            .collect();
+        let mut simulation = SequenceSimulation {
+            nb_batches: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let one_by_one = Trainer::new(config.clone())
+            .unwrap()
+            .train(population.clone(), &mut simulation)
+            .unwrap();
+        assert_eq!(simulation.nb_batches.load(Ordering::Relaxed), 0);
+        let batched = Trainer::new(config.batch_evaluation(5))
+            .unwrap()
+            .train(population, &mut simulation)
+            .unwrap();
+        assert_eq!(simulation.nb_batches.load(Ordering::Relaxed), 3 * 3);
+        assert_eq!(batched.best_fitness, one_by_one.best_fitness);
+        let fitness = |result: &TrainingResult<Sequential>| -> Vec<Vec<f64>> {
+            result
+                .history
+                .iter()
+                .map(|stats| stats.survivors_fitness.clone())
+                .collect()
+        };
+        assert_eq!(fitness(&batched), fitness(&one_by_one));
+
+        assert!(TrainingConfig::new()
+            .batch_evaluation(0)
+            .validate()
+            .is_err());
+    }
 }
@@ -1,23 +1,25 @@
 pub mod genetic_training;
+pub mod neat;
 pub mod neuralnetwork;
 
 use genetic_rl::neuralnetwork::layer::LinearLayer;
+use genetic_rl::neuralnetwork::matrix::Matrix;
 
 fn main() {
     let mut layer = LinearLayer::new(3, 2);
     let w = vec![vec![1., 2., 3.], vec![3., 4., 5.]];
 
-    println!("{:?}", layer.weights);
+    println!("{:?}", layer.weights.to_rows());
 
     layer.set_weights(w, vec![0., 0.]);
 
-    println!("{:?}", layer.weights);
+    println!("{:?}", layer.weights.to_rows());
 
-    let input = vec![vec![1., 2., 3.], vec![1., 1., 1.]];
+    let input = Matrix::from(vec![vec![1., 2., 3.], vec![1., 1., 1.]]);
 
-    println!("{:?}", layer.forward(&input));
+    println!("{:?}", layer.forward(&input).to_rows());
 
-    let layer_mut = layer.mutate(0.99);
-    println!("{:?}", layer_mut.weights);
+    let layer_mut = layer.mutate(0.99, &mut rand::thread_rng());
+    println!("{:?}", layer_mut.weights.to_rows());
     println!("{:?}", layer_mut.bias);
 }
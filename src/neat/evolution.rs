@@ -0,0 +1,383 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::genetic_training::error::TrainingError;
+use crate::genetic_training::observer::TrainingObserver;
+use crate::genetic_training::result::TrainingResult;
+use crate::genetic_training::rng::{stream_rng, RngStream};
+use crate::genetic_training::run::{run_options_builders, RunLoop, RunOptions};
+use crate::genetic_training::simulation::Simulation;
+use crate::genetic_training::speciation::{
+    offspring_quotas, shared_fitness, speciate, update_species, Species, SpeciesStats,
+};
+use crate::genetic_training::stats::GenerationStats;
+use crate::genetic_training::training::{run_generation, update_hall_of_fame};
+use crate::neat::genome::{Compatibility, Genome, InnovationTracker};
+use crate::neat::network::NeatNetwork;
+use crate::neuralnetwork::activation::Activation;
+
+/// Hyper-parameters of a `Neat` run.
+///
+/// The defaults are those of the original NEAT experiments (Stanley and
+/// Miikkulainen, 2002), except for the weight mutation power and the activations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NeatConfig {
+    pub population_size: usize,
+    #[serde(flatten)]
+    pub run: RunOptions,
+    /// Probability of every weight of a child to be mutated.
+    pub weight_mutation_rate: f64,
+    /// Standard deviation of the weight perturbations.
+    pub weight_mutation_power: f64,
+    /// Probability for a mutated weight to be replaced instead of perturbed.
+    pub weight_replace_rate: f64,
+    /// Probability for a child to get a new connection.
+    pub add_connection_rate: f64,
+    /// Probability for a child to get a new node, splitting a connection.
+    pub add_node_rate: f64,
+    /// Whether the new connections can close cycles.
+    pub allow_recurrent: bool,
+    /// Probability for a child to be the crossover of two parents of its species.
+    pub crossover_rate: f64,
+    pub compatibility: Compatibility,
+    /// Fraction of every species kept as parents, at least one.
+    pub survival_rate: f64,
+    /// Best members of every species copied unchanged into the next generation.
+    pub elitism: usize,
+    /// A species whose best fitness hasn't improved for this number of
+    /// generations gets no offspring, unless it holds the best genome.
+    pub stagnation: usize,
+    /// Activation of the nodes added by the mutations. A node only sees its
+    /// own sum, `Softmax` which normalises a whole layer is rejected.
+    pub hidden_activation: Activation,
+    /// Activation of the output nodes, `Softmax` is rejected as well.
+    pub output_activation: Activation,
+}
+
+impl Default for NeatConfig {
+    fn default() -> Self {
+        NeatConfig {
+            population_size: 150,
+            run: RunOptions::default(),
+            weight_mutation_rate: 0.8,
+            weight_mutation_power: 0.5,
+            weight_replace_rate: 0.1,
+            add_connection_rate: 0.05,
+            add_node_rate: 0.03,
+            allow_recurrent: true,
+            crossover_rate: 0.75,
+            compatibility: Compatibility::default(),
+            survival_rate: 0.2,
+            elitism: 1,
+            stagnation: 15,
+            hidden_activation: Activation::Tanh,
+            output_activation: Activation::Sigmoid,
+        }
+    }
+}
+
+impl NeatConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn population_size(mut self, population_size: usize) -> Self {
+        self.population_size = population_size;
+        self
+    }
+
+    run_options_builders!();
+
+    pub fn weight_mutation(mut self, rate: f64, power: f64, replace_rate: f64) -> Self {
+        self.weight_mutation_rate = rate;
+        self.weight_mutation_power = power;
+        self.weight_replace_rate = replace_rate;
+        self
+    }
+
+    pub fn add_connection_rate(mut self, add_connection_rate: f64) -> Self {
+        self.add_connection_rate = add_connection_rate;
+        self
+    }
+
+    pub fn add_node_rate(mut self, add_node_rate: f64) -> Self {
+        self.add_node_rate = add_node_rate;
+        self
+    }
+
+    pub fn allow_recurrent(mut self, allow_recurrent: bool) -> Self {
+        self.allow_recurrent = allow_recurrent;
+        self
+    }
+
+    pub fn crossover_rate(mut self, crossover_rate: f64) -> Self {
+        self.crossover_rate = crossover_rate;
+        self
+    }
+
+    pub fn compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    pub fn survival_rate(mut self, survival_rate: f64) -> Self {
+        self.survival_rate = survival_rate;
+        self
+    }
+
+    pub fn elitism(mut self, elitism: usize) -> Self {
+        self.elitism = elitism;
+        self
+    }
+
+    pub fn stagnation(mut self, stagnation: usize) -> Self {
+        self.stagnation = stagnation;
+        self
+    }
+
+    pub fn activations(mut self, hidden: Activation, output: Activation) -> Self {
+        self.hidden_activation = hidden;
+        self.output_activation = output;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if self.population_size < 2 {
+            return invalid("population_size must be at least 2".to_string());
+        }
+        for (name, rate) in [
+            ("weight_mutation_rate", self.weight_mutation_rate),
+            ("weight_replace_rate", self.weight_replace_rate),
+            ("add_connection_rate", self.add_connection_rate),
+            ("add_node_rate", self.add_node_rate),
+            ("crossover_rate", self.crossover_rate),
+        ] {
+            if !(0. ..=1.).contains(&rate) {
+                return invalid(format!("{} must be in [0, 1], got {}", name, rate));
+            }
+        }
+        if !(self.weight_mutation_power.is_finite() && self.weight_mutation_power >= 0.) {
+            return invalid(format!(
+                "weight_mutation_power must be a non-negative number, got {}",
+                self.weight_mutation_power
+            ));
+        }
+        if !(self.survival_rate > 0. && self.survival_rate <= 1.) {
+            return invalid(format!(
+                "survival_rate must be in ]0, 1], got {}",
+                self.survival_rate
+            ));
+        }
+        if self.compatibility.threshold.is_nan() || self.compatibility.threshold <= 0. {
+            return invalid("the compatibility threshold must be greater than 0".to_string());
+        }
+        if self.stagnation == 0 {
+            return invalid("stagnation must be greater than 0".to_string());
+        }
+        if self.hidden_activation == Activation::Softmax
+            || self.output_activation == Activation::Softmax
+        {
+            return invalid("Softmax can't be the activation of a single node".to_string());
+        }
+
+        self.run.validate()
+    }
+}
+
+/// NEAT trainer: evolves the topology and the weights of `NeatNetwork`s.
+///
+/// The population starts from minimal genomes connecting the inputs to the
+/// outputs and grows them with structural mutations. The genomes are grouped
+/// in species by compatibility distance, and the fitness is shared within a
+/// species: the offspring of a species is proportional to the mean fitness of
+/// its members, shifted so that the worst genome of the generation has 0.
+///
+/// `GenerationStats::mutation_rate` holds the weight mutation power and
+/// `GenerationStats::species` the statistics of the species.
+pub struct Neat {
+    config: NeatConfig,
+    observers: Vec<Box<dyn TrainingObserver>>,
+}
+
+impl Neat {
+    pub fn new(config: NeatConfig) -> Result<Self, TrainingError> {
+        config.validate()?;
+        Ok(Neat {
+            config,
+            observers: Vec::new(),
+        })
+    }
+
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: TrainingObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn config(&self) -> &NeatConfig {
+        &self.config
+    }
+
+    pub fn train<S>(
+        &mut self,
+        nb_inputs: usize,
+        nb_outputs: usize,
+        simulation: &mut S,
+    ) -> Result<TrainingResult<NeatNetwork>, TrainingError>
+    where
+        S: Simulation,
+    {
+        let config = &self.config;
+        if nb_outputs == 0 {
+            return Err(TrainingError::InvalidConfig(
+                "the networks need at least one output".to_string(),
+            ));
+        }
+
+        let mut run = RunLoop::new(&config.run, &mut self.observers, config.run.seed);
+        let seed = run.seed();
+        let mut tracker = InnovationTracker::new(nb_inputs, nb_outputs);
+        let mut rng = stream_rng(seed, 0, 0, RngStream::Initial);
+        let mut genomes: Vec<Genome> = (0..config.population_size)
+            .map(|_| {
+                Genome::new(
+                    nb_inputs,
+                    nb_outputs,
+                    config.output_activation,
+                    &mut tracker,
+                    &mut rng,
+                )
+            })
+            .collect();
+        let mut species: Vec<Species<Genome>> = Vec::new();
+        let mut next_species_id = 0;
+
+        let mut hall_of_fame: Vec<(NeatNetwork, f64)> = Vec::new();
+        let mut history: Vec<GenerationStats> = Vec::new();
+        let mut last_generation: Vec<(NeatNetwork, f64)> = Vec::new();
+        let mut nb_simulations = 0;
+
+        for gen in 0..config.run.nb_generation {
+            run.start_generation(gen, config.weight_mutation_power)?;
+
+            let population: Vec<NeatNetwork> = genomes.iter().map(Genome::network).collect();
+            nb_simulations += population.len();
+            let evaluated = run_generation(population, Arc::new(simulation.clone()), None);
+            update_hall_of_fame(&mut hall_of_fame, &evaluated, config.run.hall_of_fame_size);
+
+            // Non finite fitness values rank last and get no share
+            let nb_non_finite = evaluated.iter().filter(|res| !res.1.is_finite()).count();
+            let fitness: Vec<f64> = evaluated
+                .iter()
+                .map(|res| {
+                    if res.1.is_finite() {
+                        res.1
+                    } else {
+                        f64::NEG_INFINITY
+                    }
+                })
+                .collect();
+            let best_index = (0..fitness.len())
+                .max_by(|&a, &b| fitness[a].total_cmp(&fitness[b]))
+                .unwrap_or(0);
+
+            speciate(
+                genomes.iter(),
+                &mut species,
+                config.compatibility.threshold,
+                |a, b| a.distance(b, &config.compatibility),
+                &mut next_species_id,
+                gen,
+            );
+            update_species(&mut species, &fitness, best_index, config.stagnation, gen);
+            let shares = shared_fitness(&species, &fitness);
+            let quotas = offspring_quotas(&shares, config.population_size);
+
+            let mut survivors_fitness = Vec::new();
+            let mut species_stats = Vec::with_capacity(species.len());
+            let mut next_genomes = Vec::with_capacity(config.population_size);
+            for (s, &quota) in species.iter().zip(quotas.iter()) {
+                let nb_parents =
+                    ((s.members.len() as f64 * config.survival_rate).ceil() as usize).max(1);
+                let parents = &s.members[..nb_parents.min(s.members.len())];
+                survivors_fitness.extend(parents.iter().map(|&i| fitness[i]));
+                species_stats.push(SpeciesStats::new(s, &fitness, quota, gen));
+
+                let nb_elites = config.elitism.min(quota);
+                next_genomes.extend(
+                    s.members
+                        .iter()
+                        .take(nb_elites)
+                        .map(|&i| genomes[i].clone()),
+                );
+                for _ in nb_elites..quota {
+                    let mut rng = stream_rng(seed, gen, next_genomes.len(), RngStream::Mutation);
+                    let first = parents[rng.gen_range(0..parents.len())];
+                    let mut child = if parents.len() > 1 && rng.gen::<f64>() < config.crossover_rate
+                    {
+                        let second = parents[rng.gen_range(0..parents.len())];
+                        // The members are sorted, the fitter parent has the lower index
+                        let (fitter, other) = if fitness[first] >= fitness[second] {
+                            (first, second)
+                        } else {
+                            (second, first)
+                        };
+                        genomes[fitter].crossover(&genomes[other], &mut rng)
+                    } else {
+                        genomes[first].clone()
+                    };
+
+                    child.mutate_weights(
+                        config.weight_mutation_rate,
+                        config.weight_mutation_power,
+                        config.weight_replace_rate,
+                        &mut rng,
+                    );
+                    if rng.gen::<f64>() < config.add_node_rate {
+                        child.add_node(&mut tracker, config.hidden_activation, &mut rng);
+                    }
+                    if rng.gen::<f64>() < config.add_connection_rate {
+                        child.add_connection(&mut tracker, config.allow_recurrent, &mut rng);
+                    }
+                    next_genomes.push(child);
+                }
+            }
+            survivors_fitness.sort_by(|a, b| b.total_cmp(a));
+
+            // The next generation is compared to a random member of the current one
+            let mut rng = stream_rng(seed, gen, 0, RngStream::Selection);
+            for s in species.iter_mut() {
+                let representative = s.members[rng.gen_range(0..s.members.len())];
+                s.representative = genomes[representative].clone();
+            }
+            genomes = next_genomes;
+
+            last_generation = evaluated;
+            last_generation.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let sorted_fitness: Vec<f64> = last_generation.iter().map(|res| res.1).collect();
+            let mut stats = GenerationStats::new(
+                gen,
+                config.weight_mutation_power,
+                (sorted_fitness.len(), nb_non_finite),
+                &sorted_fitness,
+                survivors_fitness,
+            );
+            stats.species = species_stats;
+
+            simulation.on_generation(gen, &mut stream_rng(seed, gen, 0, RngStream::Simulation));
+
+            if run.end_generation(stats, &mut history, nb_simulations)? {
+                break;
+            }
+        }
+
+        let summary = run.finish(config.population_size, history.len(), nb_simulations)?;
+        TrainingResult::new(hall_of_fame, last_generation, history, summary)
+    }
+}
@@ -0,0 +1,390 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::neat::network::NeatNetwork;
+use crate::neuralnetwork::activation::Activation;
+
+/// Role of a node, the inputs and the bias are never computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    /// Always outputs 1.
+    Bias,
+    Hidden,
+    Output,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeGene {
+    pub id: usize,
+    pub kind: NodeKind,
+    pub activation: Activation,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionGene {
+    /// Historical marking of the structural mutation that created the connection.
+    pub innovation: usize,
+    pub from: usize,
+    pub to: usize,
+    pub weight: f64,
+    pub enabled: bool,
+}
+
+/// Innovation numbers shared by a population.
+///
+/// The same structural mutation gets the same number in every genome where it
+/// happens, which is what lines up the genes of two genomes in the crossover
+/// and the compatibility distance.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InnovationTracker {
+    next_innovation: usize,
+    next_node: usize,
+    connections: HashMap<(usize, usize), usize>,
+    /// Node created by splitting the connection of the given innovation.
+    splits: HashMap<usize, usize>,
+}
+
+impl InnovationTracker {
+    /// The node ids below `nb_inputs + 1 + nb_outputs` are the inputs, the bias
+    /// and the outputs.
+    pub fn new(nb_inputs: usize, nb_outputs: usize) -> Self {
+        InnovationTracker {
+            next_node: nb_inputs + 1 + nb_outputs,
+            ..Self::default()
+        }
+    }
+
+    pub fn connection(&mut self, from: usize, to: usize) -> usize {
+        let next = &mut self.next_innovation;
+        *self.connections.entry((from, to)).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    /// Id of the node splitting the connection `innovation`. A genome that
+    /// already has that node, e.g. from a crossover, gets a new one.
+    fn split(&mut self, innovation: usize, genome: &Genome) -> usize {
+        match self.splits.get(&innovation) {
+            Some(&node) if genome.node(node).is_none() => node,
+            _ => {
+                let node = self.next_node;
+                self.next_node += 1;
+                self.splits.entry(innovation).or_insert(node);
+                node
+            }
+        }
+    }
+}
+
+/// Coefficients of the compatibility distance, see `Genome::distance`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Compatibility {
+    pub excess: f64,
+    pub disjoint: f64,
+    pub weight: f64,
+    /// Two genomes closer than this belong to the same species.
+    pub threshold: f64,
+}
+
+impl Default for Compatibility {
+    fn default() -> Self {
+        Compatibility {
+            excess: 1.,
+            disjoint: 1.,
+            weight: 0.4,
+            threshold: 3.,
+        }
+    }
+}
+
+/// NEAT genome: the nodes and the connections of a network.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Genome {
+    pub nb_inputs: usize,
+    pub nb_outputs: usize,
+    /// The inputs, the bias and the outputs come first, in that order.
+    pub nodes: Vec<NodeGene>,
+    /// Sorted by innovation number.
+    pub connections: Vec<ConnectionGene>,
+}
+
+impl Genome {
+    /// Minimal genome with every input and the bias connected to every
+    /// output, the weights are standard normal.
+    pub fn new<R: Rng + ?Sized>(
+        nb_inputs: usize,
+        nb_outputs: usize,
+        output_activation: Activation,
+        tracker: &mut InnovationTracker,
+        rng: &mut R,
+    ) -> Self {
+        let node = |id, kind, activation| NodeGene {
+            id,
+            kind,
+            activation,
+        };
+        let mut nodes: Vec<NodeGene> = (0..nb_inputs)
+            .map(|id| node(id, NodeKind::Input, Activation::Identity))
+            .collect();
+        nodes.push(node(nb_inputs, NodeKind::Bias, Activation::Identity));
+        nodes.extend(
+            (0..nb_outputs).map(|i| node(nb_inputs + 1 + i, NodeKind::Output, output_activation)),
+        );
+
+        let mut connections = Vec::new();
+        for to in nb_inputs + 1..nb_inputs + 1 + nb_outputs {
+            for from in 0..=nb_inputs {
+                connections.push(ConnectionGene {
+                    innovation: tracker.connection(from, to),
+                    from,
+                    to,
+                    weight: rng.sample(StandardNormal),
+                    enabled: true,
+                });
+            }
+        }
+        connections.sort_by_key(|c| c.innovation);
+
+        Genome {
+            nb_inputs,
+            nb_outputs,
+            nodes,
+            connections,
+        }
+    }
+
+    pub fn node(&self, id: usize) -> Option<&NodeGene> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    pub fn nb_hidden(&self) -> usize {
+        self.nodes.len() - self.nb_inputs - 1 - self.nb_outputs
+    }
+
+    pub fn nb_enabled(&self) -> usize {
+        self.connections.iter().filter(|c| c.enabled).count()
+    }
+
+    pub fn network(&self) -> NeatNetwork {
+        NeatNetwork::new(self.clone())
+    }
+
+    /// Every weight is mutated with the probability `rate`: replaced by a
+    /// standard normal value with the probability `replace_rate`, perturbed
+    /// by a normal noise of standard deviation `power` otherwise.
+    pub fn mutate_weights<R: Rng + ?Sized>(
+        &mut self,
+        rate: f64,
+        power: f64,
+        replace_rate: f64,
+        rng: &mut R,
+    ) {
+        for connection in self.connections.iter_mut() {
+            if rng.gen::<f64>() >= rate {
+                continue;
+            }
+            let noise: f64 = rng.sample(StandardNormal);
+            if rng.gen::<f64>() < replace_rate {
+                connection.weight = noise;
+            } else {
+                connection.weight += power * noise;
+            }
+        }
+    }
+
+    /// Connects two unconnected nodes with a standard normal weight. Without
+    /// `allow_recurrent`, the connections that would close a cycle are
+    /// skipped. Returns `false` when no pair was found.
+    pub fn add_connection<R: Rng + ?Sized>(
+        &mut self,
+        tracker: &mut InnovationTracker,
+        allow_recurrent: bool,
+        rng: &mut R,
+    ) -> bool {
+        let sources: Vec<usize> = self.nodes.iter().map(|node| node.id).collect();
+        let targets: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.kind, NodeKind::Hidden | NodeKind::Output))
+            .map(|node| node.id)
+            .collect();
+
+        for _ in 0..20 {
+            let from = sources[rng.gen_range(0..sources.len())];
+            let to = targets[rng.gen_range(0..targets.len())];
+            let exists = self
+                .connections
+                .iter()
+                .any(|c| c.from == from && c.to == to);
+            if exists || (!allow_recurrent && self.reaches(to, from)) {
+                continue;
+            }
+
+            let connection = ConnectionGene {
+                innovation: tracker.connection(from, to),
+                from,
+                to,
+                weight: rng.sample(StandardNormal),
+                enabled: true,
+            };
+            let position = self
+                .connections
+                .partition_point(|c| c.innovation < connection.innovation);
+            self.connections.insert(position, connection);
+            return true;
+        }
+        false
+    }
+
+    /// Splits an enabled connection `a -> b` into `a -> new -> b`: the old
+    /// connection is disabled, the incoming weight is 1 and the outgoing one
+    /// is the old weight, so the network barely changes. Returns `false` when
+    /// no connection is enabled.
+    pub fn add_node<R: Rng + ?Sized>(
+        &mut self,
+        tracker: &mut InnovationTracker,
+        activation: Activation,
+        rng: &mut R,
+    ) -> bool {
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|&i| self.connections[i].enabled)
+            .collect();
+        if enabled.is_empty() {
+            return false;
+        }
+
+        let index = enabled[rng.gen_range(0..enabled.len())];
+        self.connections[index].enabled = false;
+        let ConnectionGene {
+            innovation,
+            from,
+            to,
+            weight,
+            ..
+        } = self.connections[index].clone();
+
+        let node = tracker.split(innovation, self);
+        self.nodes.push(NodeGene {
+            id: node,
+            kind: NodeKind::Hidden,
+            activation,
+        });
+        for (from, to, weight) in [(from, node, 1.), (node, to, weight)] {
+            let connection = ConnectionGene {
+                innovation: tracker.connection(from, to),
+                from,
+                to,
+                weight,
+                enabled: true,
+            };
+            let position = self
+                .connections
+                .partition_point(|c| c.innovation < connection.innovation);
+            self.connections.insert(position, connection);
+        }
+        true
+    }
+
+    /// Whether `to` can be reached from `from` through the connections.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut stack = vec![from];
+        let mut visited = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            for c in self.connections.iter().filter(|c| c.from == node) {
+                if !visited.contains(&c.to) {
+                    visited.push(c.to);
+                    stack.push(c.to);
+                }
+            }
+        }
+        false
+    }
+
+    /// Child of `self`, the fitter parent, and `other`. The matching genes are
+    /// drawn from either parent, the disjoint and excess genes come from
+    /// `self`. A gene disabled in either parent stays disabled with a
+    /// probability of 0.75.
+    pub fn crossover<R: Rng + ?Sized>(&self, other: &Genome, rng: &mut R) -> Genome {
+        let other_genes: HashMap<usize, &ConnectionGene> = other
+            .connections
+            .iter()
+            .map(|c| (c.innovation, c))
+            .collect();
+
+        let connections = self
+            .connections
+            .iter()
+            .map(|gene| match other_genes.get(&gene.innovation) {
+                Some(other_gene) => {
+                    let mut child = if rng.gen::<bool>() {
+                        gene.clone()
+                    } else {
+                        (*other_gene).clone()
+                    };
+                    child.enabled = if !gene.enabled || !other_gene.enabled {
+                        rng.gen::<f64>() >= 0.75
+                    } else {
+                        true
+                    };
+                    child
+                }
+                None => gene.clone(),
+            })
+            .collect();
+
+        Genome {
+            nb_inputs: self.nb_inputs,
+            nb_outputs: self.nb_outputs,
+            nodes: self.nodes.clone(),
+            connections,
+        }
+    }
+
+    /// `excess * E / N + disjoint * D / N + weight * W`, with `E` and `D` the
+    /// numbers of excess and disjoint genes, `N` the number of genes of the
+    /// larger genome (1 below 20 genes) and `W` the mean weight difference of
+    /// the matching genes.
+    pub fn distance(&self, other: &Genome, compatibility: &Compatibility) -> f64 {
+        let (a, b) = (&self.connections, &other.connections);
+        let (mut i, mut j) = (0, 0);
+        let (mut nb_disjoint, mut nb_matching, mut weight_difference) = (0, 0, 0.);
+        while i < a.len() && j < b.len() {
+            match a[i].innovation.cmp(&b[j].innovation) {
+                std::cmp::Ordering::Equal => {
+                    nb_matching += 1;
+                    weight_difference += (a[i].weight - b[j].weight).abs();
+                    i += 1;
+                    j += 1;
+                }
+                std::cmp::Ordering::Less => {
+                    nb_disjoint += 1;
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    nb_disjoint += 1;
+                    j += 1;
+                }
+            }
+        }
+        let nb_excess = (a.len() - i) + (b.len() - j);
+
+        let size = a.len().max(b.len());
+        let n = if size < 20 { 1. } else { size as f64 };
+        let mean_weight_difference = if nb_matching > 0 {
+            weight_difference / nb_matching as f64
+        } else {
+            0.
+        };
+
+        compatibility.excess * nb_excess as f64 / n
+            + compatibility.disjoint * nb_disjoint as f64 / n
+            + compatibility.weight * mean_weight_difference
+    }
+}
@@ -0,0 +1,3 @@
+pub mod evolution;
+pub mod genome;
+pub mod network;
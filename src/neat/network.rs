@@ -0,0 +1,136 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+use std::collections::HashMap;
+
+use crate::genetic_training::agent::Agent;
+use crate::neat::genome::{Compatibility, Genome, NodeKind};
+use crate::neuralnetwork::activation::Activation;
+use crate::neuralnetwork::matrix::Matrix;
+
+/// Network built from a `Genome`, the phenotype evaluated by the simulations.
+///
+/// The nodes are computed once per step in an order where every source of a
+/// forward connection comes before its target. The connections closing a
+/// cycle read the value their source had at the previous step, so recurrent
+/// genomes keep a state between the steps, cleared by `reset`.
+#[derive(Clone, Debug)]
+pub struct NeatNetwork {
+    pub genome: Genome,
+    /// Index of the computed nodes, in order, with their activation.
+    order: Vec<(usize, Activation)>,
+    /// Enabled incoming connections of every node, as `(source index, weight)`.
+    incoming: Vec<Vec<(usize, f64)>>,
+    outputs: Vec<usize>,
+    /// Value of every node for every row of the batch, kept between steps.
+    values: Matrix,
+}
+
+impl NeatNetwork {
+    pub fn new(genome: Genome) -> Self {
+        let index: HashMap<usize, usize> = genome
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+        let mut incoming = vec![Vec::new(); genome.nodes.len()];
+        for c in genome.connections.iter().filter(|c| c.enabled) {
+            incoming[index[&c.to]].push((index[&c.from], c.weight));
+        }
+        let outputs: Vec<usize> = (0..genome.nodes.len())
+            .filter(|&i| genome.nodes[i].kind == NodeKind::Output)
+            .collect();
+
+        // Depth-first post-order from the outputs along the incoming
+        // connections, the nodes that don't reach an output are skipped
+        let mut state = vec![Visit::New; genome.nodes.len()];
+        let mut order = Vec::new();
+        for &output in outputs.iter() {
+            let mut stack = vec![(output, 0)];
+            state[output] = Visit::InProgress;
+            while let Some((node, next)) = stack.pop() {
+                if let Some(&(source, _)) = incoming[node].get(next) {
+                    stack.push((node, next + 1));
+                    let computed = matches!(
+                        genome.nodes[source].kind,
+                        NodeKind::Hidden | NodeKind::Output
+                    );
+                    if computed && state[source] == Visit::New {
+                        state[source] = Visit::InProgress;
+                        stack.push((source, 0));
+                    }
+                } else {
+                    state[node] = Visit::Done;
+                    order.push((node, genome.nodes[node].activation));
+                }
+            }
+        }
+
+        NeatNetwork {
+            genome,
+            order,
+            incoming,
+            outputs,
+            values: Matrix::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    New,
+    InProgress,
+    Done,
+}
+
+impl Agent for NeatNetwork {
+    /// `input` has one row per sample of the batch and one column per input
+    /// node, the state is reset when the batch size changes.
+    fn step(&mut self, input: &Matrix) -> Matrix {
+        let nb_inputs = self.genome.nb_inputs;
+        assert_eq!(input.cols(), nb_inputs, "shape mismatch between matrices");
+        if self.values.rows() != input.rows() {
+            self.values = Matrix::zeros(input.rows(), self.genome.nodes.len());
+        }
+
+        let mut output = Matrix::zeros(input.rows(), self.outputs.len());
+        for (row, values) in self.values.iter_rows_mut().enumerate() {
+            values[..nb_inputs].copy_from_slice(input.row(row));
+            values[nb_inputs] = 1.;
+            // Sources computed later in the order still hold the previous step
+            for &(node, activation) in self.order.iter() {
+                let sum: f64 = self.incoming[node]
+                    .iter()
+                    .map(|&(source, weight)| weight * values[source])
+                    .sum();
+                values[node] = activation.activate(sum);
+            }
+            for (out, &node) in output.row_mut(row).iter_mut().zip(self.outputs.iter()) {
+                *out = values[node];
+            }
+        }
+        output
+    }
+
+    fn reset(&mut self) {
+        self.values.fill(0.);
+    }
+
+    /// Compatibility distance of the genomes, with the default coefficients.
+    fn distance(&self, other: &Self) -> Option<f64> {
+        Some(
+            self.genome
+                .distance(&other.genome, &Compatibility::default()),
+        )
+    }
+
+    /// Perturbs every weight with a normal noise of standard deviation
+    /// `mutation_rate`, the topology only evolves with the `Neat` trainer.
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        let mut genome = self.genome.clone();
+        for connection in genome.connections.iter_mut() {
+            connection.weight += mutation_rate * rng.sample::<f64, _>(StandardNormal);
+        }
+        NeatNetwork::new(genome)
+    }
+}
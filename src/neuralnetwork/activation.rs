@@ -1,33 +1,154 @@
-pub fn relu(input: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::matrix::Matrix;
+
+pub fn relu<T: Float>(mut input: Matrix<T>) -> Matrix<T> {
+    Activation::ReLU.apply_in_place(&mut input);
     input
-        .iter()
-        .map(|batch| {
-            batch
-                .iter()
-                .map(|&x| if x > 0.0 { x } else { 0.0 })
-                .collect()
-        })
-        .collect()
 }
 
-pub fn sigmoid(input: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-    fn sigmoid_activation(x: f64) -> f64 {
-        1.0 / (1.0 + (-x).exp())
-    }
+pub fn sigmoid<T: Float>(mut input: Matrix<T>) -> Matrix<T> {
+    Activation::Sigmoid.apply_in_place(&mut input);
+    input
+}
 
+pub fn tanh<T: Float>(mut input: Matrix<T>) -> Matrix<T> {
+    Activation::Tanh.apply_in_place(&mut input);
     input
-        .iter()
-        .map(|batch| batch.iter().map(|&x| sigmoid_activation(x)).collect())
-        .collect()
 }
 
-pub fn tanh(input: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-    fn tanh_activation(x: f64) -> f64 {
-        x.tanh()
+const SELU_LAMBDA: f64 = 1.050_700_987_355_480_5;
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_2;
+
+/// Activation function stored as data, e.g. in a `Sequential` model.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    Identity,
+    ReLU,
+    LeakyReLU(f64),
+    ELU(f64),
+    SELU,
+    /// Tanh approximation.
+    GELU,
+    Sigmoid,
+    Tanh,
+    /// `x * sigmoid(x)`, also called SiLU.
+    #[serde(alias = "SiLU")]
+    Swish,
+    Softplus,
+    Softsign,
+    /// Clamps to [-1, 1].
+    HardTanh,
+    /// `x / 6 + 1 / 2` clamped to [0, 1].
+    HardSigmoid,
+    /// `exp(-x^2)`
+    Gaussian,
+    Sine,
+    /// 1 for positive inputs and 0, 0 otherwise.
+    Step,
+    /// Normalised exponential over the features of every row of the batch.
+    Softmax,
+}
+
+impl Activation {
+    /// Every activation, with the usual parameter for the parametric ones.
+    pub const ALL: [Activation; 17] = [
+        Activation::Identity,
+        Activation::ReLU,
+        Activation::LeakyReLU(0.01),
+        Activation::ELU(1.),
+        Activation::SELU,
+        Activation::GELU,
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Swish,
+        Activation::Softplus,
+        Activation::Softsign,
+        Activation::HardTanh,
+        Activation::HardSigmoid,
+        Activation::Gaussian,
+        Activation::Sine,
+        Activation::Step,
+        Activation::Softmax,
+    ];
+
+    /// Draws one of `Activation::ALL`, to evolve the activation as a gene.
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self::ALL[rng.gen_range(0..Self::ALL.len())]
     }
 
-    input
-        .iter()
-        .map(|batch| batch.iter().map(|&x| tanh_activation(x)).collect())
-        .collect()
+    /// Value for a single input, `Softmax` needs the whole row and is the
+    /// identity here.
+    pub fn activate<T: Float>(&self, x: T) -> T {
+        let zero = T::zero();
+        let one = T::one();
+        match *self {
+            Activation::Identity | Activation::Softmax => x,
+            Activation::ReLU => x.max(zero),
+            Activation::LeakyReLU(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::from_f64(alpha) * x
+                }
+            }
+            Activation::ELU(alpha) => {
+                if x > zero {
+                    x
+                } else {
+                    T::from_f64(alpha) * x.exp_m1()
+                }
+            }
+            Activation::SELU => {
+                if x > zero {
+                    T::from_f64(SELU_LAMBDA) * x
+                } else {
+                    T::from_f64(SELU_LAMBDA * SELU_ALPHA) * x.exp_m1()
+                }
+            }
+            Activation::GELU => {
+                let k = T::from_f64((2. / std::f64::consts::PI).sqrt());
+                let cubic = T::from_f64(0.044715) * x.powi(3);
+                T::from_f64(0.5) * x * (one + (k * (x + cubic)).tanh())
+            }
+            Activation::Sigmoid => one / (one + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Swish => x / (one + (-x).exp()),
+            Activation::Softplus => x.max(zero) + (-x.abs()).exp().ln_1p(),
+            Activation::Softsign => x / (one + x.abs()),
+            Activation::HardTanh => x.max(-one).min(one),
+            Activation::HardSigmoid => (x / T::from_f64(6.) + T::from_f64(0.5)).max(zero).min(one),
+            Activation::Gaussian => (-x * x).exp(),
+            Activation::Sine => x.sin(),
+            Activation::Step => {
+                if x >= zero {
+                    one
+                } else {
+                    zero
+                }
+            }
+        }
+    }
+
+    pub fn apply_in_place<T: Float>(&self, values: &mut Matrix<T>) {
+        match self {
+            Activation::Identity => {}
+            Activation::Softmax => values.iter_rows_mut().for_each(softmax_in_place),
+            activation => values.map_in_place(|x| activation.activate(x)),
+        }
+    }
+
+    pub fn apply<T: Float>(&self, mut input: Matrix<T>) -> Matrix<T> {
+        self.apply_in_place(&mut input);
+        input
+    }
+}
+
+fn softmax_in_place<T: Float>(row: &mut [T]) {
+    let max = row.iter().cloned().fold(T::neg_infinity(), T::max);
+    row.iter_mut().for_each(|x| *x = (*x - max).exp());
+    let sum: T = row.iter().cloned().sum();
+    row.iter_mut().for_each(|x| *x /= sum);
 }
@@ -0,0 +1,562 @@
+use std::any::Any;
+
+use crate::genetic_training::agent::AgentBatch;
+use crate::neuralnetwork::activation::Activation;
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::layer::{GRULayer, LSTMLayer, LinearLayer};
+use crate::neuralnetwork::matrix::{batched_matmul_transposed_slices, Matrix};
+use crate::neuralnetwork::sequential::{Layer, Sequential};
+
+/// Input of a stacked layer, shared by every agent or one block of rows per agent.
+#[derive(Clone, Copy)]
+enum Input<'a, T: Float> {
+    Shared(&'a Matrix<T>),
+    Each(&'a Matrix<T>),
+}
+
+impl<'a, T: Float> Input<'a, T> {
+    /// The input matrix, the offset between the inputs of two agents and the
+    /// number of rows per agent.
+    fn strided(self, nb_agents: usize) -> (&'a Matrix<T>, usize, usize) {
+        match self {
+            Input::Shared(x) => (x, 0, x.rows()),
+            Input::Each(x) => {
+                assert!(x.rows() % nb_agents == 0, "shape mismatch between matrices");
+                let rows = x.rows() / nb_agents;
+                (x, rows * x.cols(), rows)
+            }
+        }
+    }
+}
+
+/// Adds `x_a . w_a^T` for every agent `a` to `out`, one block of rows per
+/// agent. Agent `a` owns the rows `a * n..(a + 1) * n` of `weights`, the
+/// products of the whole population are a single strided batched product.
+fn add_stacked_product<T: Float>(
+    input: Input<T>,
+    weights: &Matrix<T>,
+    nb_agents: usize,
+    out: &mut Matrix<T>,
+) {
+    let (x, stride, rows) = input.strided(nb_agents);
+    let (n, k) = (weights.rows() / nb_agents, weights.cols());
+    assert!(
+        x.cols() == k && out.shape() == (nb_agents * rows, n),
+        "shape mismatch between matrices"
+    );
+    batched_matmul_transposed_slices(
+        (x.as_slice(), stride),
+        weights.as_slice(),
+        (nb_agents, rows, k, n),
+        out.as_mut_slice(),
+    );
+}
+
+/// Writes `x_a . w_a^T` for every agent `a` into `out`, see `add_stacked_product`.
+fn stacked_product_into<T: Float>(
+    input: Input<T>,
+    weights: &Matrix<T>,
+    nb_agents: usize,
+    out: &mut Matrix<T>,
+) {
+    let rows = input.strided(nb_agents).2;
+    out.reset_zeros(nb_agents * rows, weights.rows() / nb_agents);
+    add_stacked_product(input, weights, nb_agents, out);
+}
+
+/// Applies `f(x, row_a)` to every row of the block of agent `a` of `values`,
+/// `per_agent` having one row per agent.
+fn zip_agent_rows<T: Float, F: Fn(&mut [T], &[T])>(
+    values: &mut Matrix<T>,
+    per_agent: &Matrix<T>,
+    f: F,
+) {
+    let rows = values.rows() / per_agent.rows();
+    for (i, row) in values.iter_rows_mut().enumerate() {
+        f(row, per_agent.row(i / rows));
+    }
+}
+
+fn add_agent_rows<T: Float>(values: &mut Matrix<T>, per_agent: &Matrix<T>) {
+    zip_agent_rows(values, per_agent, |row, bias| {
+        row.iter_mut().zip(bias.iter()).for_each(|(x, b)| *x += *b)
+    });
+}
+
+/// Rows of one `Vec` per agent.
+fn stack_vectors<'a, T: Float, I>(vectors: I) -> Matrix<T>
+where
+    I: Iterator<Item = &'a Vec<T>>,
+{
+    Matrix::from(vectors.cloned().collect::<Vec<_>>())
+}
+
+/// Weights of one gate for the whole population.
+#[derive(Clone)]
+struct Gate<T: Float> {
+    w: Matrix<T>,
+    u: Matrix<T>,
+    b: Matrix<T>,
+}
+
+impl<T: Float> Gate<T> {
+    fn stack<'a, I>(gates: I) -> Self
+    where
+        I: Iterator<Item = (&'a Matrix<T>, &'a Matrix<T>, &'a Vec<T>)> + Clone,
+    {
+        Gate {
+            w: Matrix::concat_rows(gates.clone().map(|gate| gate.0)),
+            u: Matrix::concat_rows(gates.clone().map(|gate| gate.1)),
+            b: stack_vectors(gates.map(|gate| gate.2)),
+        }
+    }
+
+    /// Writes `x_a . w_a^T + h_a . u_a^T + b_a` into `out`.
+    fn forward_into(&self, input: Input<T>, hidden_state: &Matrix<T>, out: &mut Matrix<T>) {
+        let nb_agents = self.b.rows();
+        stacked_product_into(input, &self.w, nb_agents, out);
+        add_stacked_product(Input::Each(hidden_state), &self.u, nb_agents, out);
+        add_agent_rows(out, &self.b);
+    }
+}
+
+/// Adds `peephole_a * cell_state` to the block of every agent of `gate`.
+fn add_peephole<T: Float>(
+    gate: &mut Matrix<T>,
+    peephole: Option<&Matrix<T>>,
+    cell_state: &Matrix<T>,
+) {
+    if let Some(peephole) = peephole {
+        let rows = gate.rows() / peephole.rows();
+        for (i, (row, cell_row)) in gate.iter_rows_mut().zip(cell_state.iter_rows()).enumerate() {
+            let p = peephole.row(i / rows);
+            for ((x, p), c) in row.iter_mut().zip(p.iter()).zip(cell_row.iter()) {
+                *x += *p * *c;
+            }
+        }
+    }
+}
+
+/// Borrowed layer of a model stacked by `SequentialBatch::stack_layers`, so
+/// that agents built directly from layers can be stacked without cloning them.
+#[derive(Clone, Copy)]
+pub enum LayerRef<'a, T: Float = f64> {
+    Linear(&'a LinearLayer<T>),
+    Gru(&'a GRULayer<T>),
+    Lstm(&'a LSTMLayer<T>),
+    Activation(Activation),
+}
+
+impl<'a, T: Float> From<&'a Layer<T>> for LayerRef<'a, T> {
+    fn from(layer: &'a Layer<T>) -> Self {
+        match layer {
+            Layer::Linear(layer) => LayerRef::Linear(layer),
+            Layer::Gru(layer) => LayerRef::Gru(layer),
+            Layer::Lstm(layer) => LayerRef::Lstm(layer),
+            Layer::Activation(activation) => LayerRef::Activation(*activation),
+        }
+    }
+}
+
+impl<'a, T: Float> From<&'a LinearLayer<T>> for LayerRef<'a, T> {
+    fn from(layer: &'a LinearLayer<T>) -> Self {
+        LayerRef::Linear(layer)
+    }
+}
+
+impl<'a, T: Float> From<&'a GRULayer<T>> for LayerRef<'a, T> {
+    fn from(layer: &'a GRULayer<T>) -> Self {
+        LayerRef::Gru(layer)
+    }
+}
+
+impl<'a, T: Float> From<&'a LSTMLayer<T>> for LayerRef<'a, T> {
+    fn from(layer: &'a LSTMLayer<T>) -> Self {
+        LayerRef::Lstm(layer)
+    }
+}
+
+impl<T: Float> From<Activation> for LayerRef<'_, T> {
+    fn from(activation: Activation) -> Self {
+        LayerRef::Activation(activation)
+    }
+}
+
+/// One layer of a `SequentialBatch`, the states and outputs hold one block of
+/// rows per agent.
+// A handful of them per batch, boxing the recurrent layers wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum StackedLayer<T: Float> {
+    Linear {
+        weights: Matrix<T>,
+        bias: Matrix<T>,
+        output: Matrix<T>,
+    },
+    Gru {
+        reset: Gate<T>,
+        update: Gate<T>,
+        candidate: Gate<T>,
+        hidden_state: Matrix<T>,
+        /// Update, reset, candidate and reset hidden state.
+        gates: [Matrix<T>; 4],
+    },
+    Lstm {
+        input: Gate<T>,
+        forget: Gate<T>,
+        output: Gate<T>,
+        candidate: Gate<T>,
+        /// Input, forget and output peepholes, one row per agent.
+        peepholes: Option<[Matrix<T>; 3]>,
+        hidden_state: Matrix<T>,
+        cell_state: Matrix<T>,
+        /// Input, forget, output and candidate.
+        gates: [Matrix<T>; 4],
+    },
+    Activation {
+        activation: Activation,
+        output: Matrix<T>,
+    },
+}
+
+impl<T: Float> StackedLayer<T> {
+    /// `None` when the layers are not all of the same kind and shape.
+    fn stack(layers: &[LayerRef<T>]) -> Option<Self> {
+        macro_rules! all_of {
+            ($variant:path, $same:expr) => {
+                layers
+                    .iter()
+                    .map(|layer| match layer {
+                        $variant(inner) if $same(inner) => Some(inner),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?
+            };
+        }
+
+        let stacked = match layers.first()? {
+            LayerRef::Linear(first) => {
+                let linears = all_of!(LayerRef::Linear, |layer: &LinearLayer<T>| {
+                    layer.weights.shape() == first.weights.shape()
+                });
+                StackedLayer::Linear {
+                    weights: Matrix::concat_rows(linears.iter().map(|layer| &layer.weights)),
+                    bias: stack_vectors(linears.iter().map(|layer| &layer.bias)),
+                    output: Matrix::default(),
+                }
+            }
+            LayerRef::Gru(first) => {
+                let grus = all_of!(LayerRef::Gru, |layer: &GRULayer<T>| {
+                    layer.w_reset.shape() == first.w_reset.shape()
+                        && layer.hidden_state.shape() == first.hidden_state.shape()
+                });
+                StackedLayer::Gru {
+                    reset: Gate::stack(grus.iter().map(|l| (&l.w_reset, &l.u_reset, &l.b_reset))),
+                    update: Gate::stack(
+                        grus.iter().map(|l| (&l.w_update, &l.u_update, &l.b_update)),
+                    ),
+                    candidate: Gate::stack(
+                        grus.iter()
+                            .map(|l| (&l.w_candidate, &l.u_candidate, &l.b_candidate)),
+                    ),
+                    hidden_state: Matrix::concat_rows(grus.iter().map(|l| &l.hidden_state)),
+                    gates: Default::default(),
+                }
+            }
+            LayerRef::Lstm(first) => {
+                let lstms = all_of!(LayerRef::Lstm, |layer: &LSTMLayer<T>| {
+                    layer.w_input.shape() == first.w_input.shape()
+                        && layer.hidden_state.shape() == first.hidden_state.shape()
+                        && layer.peepholes.is_some() == first.peepholes.is_some()
+                });
+                let peepholes = first.peepholes.as_ref().map(|_| {
+                    let peepholes = || lstms.iter().filter_map(|l| l.peepholes.as_ref());
+                    [
+                        stack_vectors(peepholes().map(|p| &p.input)),
+                        stack_vectors(peepholes().map(|p| &p.forget)),
+                        stack_vectors(peepholes().map(|p| &p.output)),
+                    ]
+                });
+                StackedLayer::Lstm {
+                    input: Gate::stack(lstms.iter().map(|l| (&l.w_input, &l.u_input, &l.b_input))),
+                    forget: Gate::stack(
+                        lstms
+                            .iter()
+                            .map(|l| (&l.w_forget, &l.u_forget, &l.b_forget)),
+                    ),
+                    output: Gate::stack(
+                        lstms
+                            .iter()
+                            .map(|l| (&l.w_output, &l.u_output, &l.b_output)),
+                    ),
+                    candidate: Gate::stack(
+                        lstms
+                            .iter()
+                            .map(|l| (&l.w_candidate, &l.u_candidate, &l.b_candidate)),
+                    ),
+                    peepholes,
+                    hidden_state: Matrix::concat_rows(lstms.iter().map(|l| &l.hidden_state)),
+                    cell_state: Matrix::concat_rows(lstms.iter().map(|l| &l.cell_state)),
+                    gates: Default::default(),
+                }
+            }
+            LayerRef::Activation(first) => {
+                all_of!(LayerRef::Activation, |activation: &Activation| activation
+                    == first);
+                StackedLayer::Activation {
+                    activation: *first,
+                    output: Matrix::default(),
+                }
+            }
+        };
+        Some(stacked)
+    }
+
+    /// Output of the last `forward`.
+    fn output(&self) -> &Matrix<T> {
+        match self {
+            StackedLayer::Linear { output, .. } | StackedLayer::Activation { output, .. } => output,
+            StackedLayer::Gru { hidden_state, .. } | StackedLayer::Lstm { hidden_state, .. } => {
+                hidden_state
+            }
+        }
+    }
+
+    /// Same computations as the layers of `layer.rs`, on every agent at once.
+    /// The result is written into the buffer returned by `output`.
+    fn forward(&mut self, input: Input<T>, nb_agents: usize) {
+        match self {
+            StackedLayer::Linear {
+                weights,
+                bias,
+                output,
+            } => {
+                stacked_product_into(input, weights, nb_agents, output);
+                add_agent_rows(output, bias);
+            }
+            StackedLayer::Gru {
+                reset,
+                update,
+                candidate,
+                hidden_state,
+                gates,
+            } => {
+                let [z, r, cand, reset_hidden] = gates;
+
+                update.forward_into(input, hidden_state, z);
+                Activation::Sigmoid.apply_in_place(z);
+                reset.forward_into(input, hidden_state, r);
+                Activation::Sigmoid.apply_in_place(r);
+
+                reset_hidden.copy_from(r);
+                reset_hidden.mul_assign(hidden_state);
+                candidate.forward_into(input, reset_hidden, cand);
+                Activation::Tanh.apply_in_place(cand);
+
+                // h = (1 - z) * h + z * candidate
+                cand.sub_assign(hidden_state);
+                cand.mul_assign(z);
+                hidden_state.add_assign(cand);
+            }
+            StackedLayer::Lstm {
+                input: input_gate,
+                forget,
+                output,
+                candidate,
+                peepholes,
+                hidden_state,
+                cell_state,
+                gates,
+            } => {
+                let [i, f, o, cand] = gates;
+                let peephole = |k: usize| peepholes.as_ref().map(|p| &p[k]);
+
+                input_gate.forward_into(input, hidden_state, i);
+                add_peephole(i, peephole(0), cell_state);
+                Activation::Sigmoid.apply_in_place(i);
+
+                forget.forward_into(input, hidden_state, f);
+                add_peephole(f, peephole(1), cell_state);
+                Activation::Sigmoid.apply_in_place(f);
+
+                candidate.forward_into(input, hidden_state, cand);
+                Activation::Tanh.apply_in_place(cand);
+
+                // c = f * c + i * candidate
+                cell_state.mul_assign(f);
+                cand.mul_assign(i);
+                cell_state.add_assign(cand);
+
+                output.forward_into(input, hidden_state, o);
+                add_peephole(o, peephole(2), cell_state);
+                Activation::Sigmoid.apply_in_place(o);
+
+                // h = o * tanh(c)
+                hidden_state.copy_from(cell_state);
+                Activation::Tanh.apply_in_place(hidden_state);
+                hidden_state.mul_assign(o);
+            }
+            StackedLayer::Activation { activation, output } => {
+                match input {
+                    Input::Shared(x) => {
+                        output.reset_zeros(nb_agents * x.rows(), x.cols());
+                        for block in output.as_mut_slice().chunks_exact_mut(x.as_slice().len()) {
+                            block.copy_from_slice(x.as_slice());
+                        }
+                    }
+                    Input::Each(x) => output.copy_from(x),
+                }
+                activation.apply_in_place(output);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            StackedLayer::Gru { hidden_state, .. } => hidden_state.fill(T::zero()),
+            StackedLayer::Lstm {
+                hidden_state,
+                cell_state,
+                ..
+            } => {
+                hidden_state.fill(T::zero());
+                cell_state.fill(T::zero());
+            }
+            StackedLayer::Linear { .. } | StackedLayer::Activation { .. } => {}
+        }
+    }
+}
+
+/// Structurally identical models whose weights are stacked, so that the
+/// population is stepped with batched matrix products.
+///
+/// Every matrix product of a layer, input or recurrent, is a single strided
+/// batched product over the whole population, and every element-wise
+/// operation runs once on the stacked states. The outputs are written into
+/// buffers reused from one step to the next. The `sequential_batch` benchmark
+/// compares it with stepping every model.
+///
+/// It gives the same outputs as stepping every model on its own.
+#[derive(Clone)]
+pub struct SequentialBatch<T: Float = f64> {
+    layers: Vec<StackedLayer<T>>,
+    nb_agents: usize,
+    /// Inputs converted to `T` and outputs converted to `f64` by the
+    /// `AgentBatch` steps, unused when `T` is `f64`.
+    input: Matrix<T>,
+    output: Matrix,
+}
+
+impl<T: Float> SequentialBatch<T> {
+    /// `None` when the models don't have the same layers, shapes and batch
+    /// size, or have no layer.
+    pub fn stack(models: &[Sequential<T>]) -> Option<Self> {
+        let first = models.first()?;
+        if models
+            .iter()
+            .any(|model| model.batch_size() != first.batch_size())
+        {
+            return None;
+        }
+
+        let models: Vec<Vec<LayerRef<T>>> = models
+            .iter()
+            .map(|model| model.layers().iter().map(LayerRef::from).collect())
+            .collect();
+        Self::stack_layers(&models)
+    }
+
+    /// Stacks models given as their list of layers, e.g. to implement
+    /// `Agent::stack` for an agent holding its layers:
+    /// `vec![(&agent.hidden).into(), Activation::Tanh.into(), (&agent.output).into()]`.
+    ///
+    /// `None` when the models don't have the same layers and shapes, or have
+    /// no layer.
+    pub fn stack_layers(models: &[Vec<LayerRef<T>>]) -> Option<Self> {
+        let first = models.first()?;
+        if first.is_empty() || models.iter().any(|model| model.len() != first.len()) {
+            return None;
+        }
+
+        let layers = (0..first.len())
+            .map(|i| {
+                let layers: Vec<LayerRef<T>> = models.iter().map(|model| model[i]).collect();
+                StackedLayer::stack(&layers)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(SequentialBatch {
+            layers,
+            nb_agents: models.len(),
+            input: Matrix::default(),
+            output: Matrix::default(),
+        })
+    }
+
+    fn forward(&mut self, input: Input<T>) -> &Matrix<T> {
+        for i in 0..self.layers.len() {
+            let (previous, next) = self.layers.split_at_mut(i);
+            let input = previous
+                .last()
+                .map_or(input, |layer| Input::Each(layer.output()));
+            next[0].forward(input, self.nb_agents);
+        }
+        self.layers[self.layers.len() - 1].output()
+    }
+
+    /// Feeds the same `input` to every model, see `AgentBatch` for the layout
+    /// of the output.
+    pub fn forward_shared(&mut self, input: &Matrix<T>) -> &Matrix<T> {
+        self.forward(Input::Shared(input))
+    }
+
+    /// Feeds every model its own block of rows of `inputs`.
+    pub fn forward_each(&mut self, inputs: &Matrix<T>) -> &Matrix<T> {
+        self.forward(Input::Each(inputs))
+    }
+
+    /// Steps the models on `f64` inputs, used as they are when `T` is `f64`.
+    fn step_f64(&mut self, input: &Matrix, shared: bool) -> &Matrix {
+        let mut buffer = std::mem::take(&mut self.input);
+        let converted = match (input as &dyn Any).downcast_ref::<Matrix<T>>() {
+            Some(input) => input,
+            None => {
+                input.cast_into(&mut buffer);
+                &buffer
+            }
+        };
+        if shared {
+            self.forward(Input::Shared(converted));
+        } else {
+            self.forward(Input::Each(converted));
+        }
+        self.input = buffer;
+
+        let output = self.layers[self.layers.len() - 1].output();
+        match (output as &dyn Any).downcast_ref::<Matrix>() {
+            Some(output) => output,
+            None => {
+                output.cast_into(&mut self.output);
+                &self.output
+            }
+        }
+    }
+}
+
+impl<T: Float> AgentBatch for SequentialBatch<T> {
+    fn nb_agents(&self) -> usize {
+        self.nb_agents
+    }
+
+    fn step(&mut self, input: &Matrix) -> &Matrix {
+        self.step_f64(input, true)
+    }
+
+    fn step_each(&mut self, inputs: &Matrix) -> &Matrix {
+        self.step_f64(inputs, false)
+    }
+
+    fn reset(&mut self) {
+        self.layers.iter_mut().for_each(|layer| layer.reset());
+    }
+}
@@ -0,0 +1,61 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::matrix::Matrix;
+
+/// Recombination operator used by the layers' `crossover`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Crossover {
+    /// Every parameter is taken from one parent or the other with equal probability.
+    Uniform,
+    /// Parameters before a random cut point come from the first parent, the rest from the second.
+    SinglePoint,
+    /// Every parameter is `alpha * a + (1 - alpha) * b` with `alpha` drawn once per call.
+    Arithmetic,
+}
+
+impl Crossover {
+    pub fn apply_1d<T: Float, R: Rng + ?Sized>(&self, a: &[T], b: &[T], rng: &mut R) -> Vec<T> {
+        assert_eq!(a.len(), b.len(), "crossover of mismatched shapes");
+
+        match self {
+            Crossover::Uniform => a
+                .iter()
+                .zip(b.iter())
+                .map(|(x, y)| if rng.gen::<bool>() { *x } else { *y })
+                .collect(),
+            Crossover::SinglePoint => {
+                let point = rng.gen_range(0..=a.len());
+                a[..point]
+                    .iter()
+                    .chain(b[point..].iter())
+                    .cloned()
+                    .collect()
+            }
+            Crossover::Arithmetic => {
+                let alpha = T::from_f64(rng.gen::<f64>());
+                a.iter()
+                    .zip(b.iter())
+                    .map(|(&x, &y)| alpha * x + (T::one() - alpha) * y)
+                    .collect()
+            }
+        }
+    }
+
+    /// Matrices are recombined as their row-major flattening.
+    pub fn apply_2d<T: Float, R: Rng + ?Sized>(
+        &self,
+        a: &Matrix<T>,
+        b: &Matrix<T>,
+        rng: &mut R,
+    ) -> Matrix<T> {
+        assert_eq!(a.shape(), b.shape(), "crossover of mismatched shapes");
+
+        Matrix::from_vec(
+            a.rows(),
+            a.cols(),
+            self.apply_1d(a.as_slice(), b.as_slice(), rng),
+        )
+    }
+}
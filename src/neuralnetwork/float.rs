@@ -0,0 +1,54 @@
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::ops::{AddAssign, DivAssign, MulAssign, SubAssign};
+
+use crate::neuralnetwork::simd::Kernels;
+
+/// Precision of the weights and activations of the neural networks, `f32` or `f64`.
+///
+/// Hyperparameters such as the mutation rate stay `f64` and are converted
+/// with `from_f64` where they meet the weights. The matrix operations go
+/// through the vectorised `Kernels`.
+pub trait Float:
+    num_traits::Float
+    + Kernels
+    + Default
+    + Debug
+    + Display
+    + Sum
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + 'static
+{
+    fn from_f64(value: f64) -> Self;
+
+    fn as_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::sync::Arc;
+
+use rand::{Rng, RngCore};
+use rand_distr::{Distribution, Normal};
+
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::matrix::Matrix;
+
+/// Function called once per weight with `(input_size, output_size, rng)`.
+pub type InitFn = dyn Fn(usize, usize, &mut dyn RngCore) -> f64 + Send + Sync;
+
+/// Weight initialisation scheme of a layer.
+///
+/// `fan_in` is the input size of the layer and `fan_out` its output size. The
+/// values are drawn in `f64` whatever the precision of the weights.
+#[derive(Clone, Default)]
+pub enum Initializer {
+    /// Uniform in `±sqrt(2 / (fan_in + fan_out))`, the weights of the layers
+    /// built with `new` and `new_with_rng`.
+    #[default]
+    ScaledUniform,
+    /// Uniform in `±sqrt(6 / (fan_in + fan_out))`.
+    XavierUniform,
+    /// Normal with a standard deviation of `sqrt(2 / (fan_in + fan_out))`.
+    XavierNormal,
+    /// Uniform in `±sqrt(6 / fan_in)`, for ReLU activations.
+    HeUniform,
+    /// Normal with a standard deviation of `sqrt(2 / fan_in)`.
+    HeNormal,
+    /// Normal with a standard deviation of `sqrt(1 / fan_in)`, for SELU and tanh.
+    LeCun,
+    /// Orthogonal rows (or columns if there are more rows than columns) scaled
+    /// by the gain, usually 1. Suited to the recurrent matrices.
+    Orthogonal(f64),
+    Zeros,
+    Constant(f64),
+    Custom(Arc<InitFn>),
+}
+
+impl Initializer {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(usize, usize, &mut dyn RngCore) -> f64 + Send + Sync + 'static,
+    {
+        Initializer::Custom(Arc::new(f))
+    }
+
+    /// Weights of a layer, one row per output.
+    pub fn init<T: Float, R: Rng + ?Sized>(
+        &self,
+        input_size: usize,
+        output_size: usize,
+        rng: &mut R,
+    ) -> Matrix<T> {
+        let fan_in = input_size as f64;
+        let fan_out = output_size as f64;
+        let len = input_size * output_size;
+
+        let values: Vec<f64> = match self {
+            Initializer::ScaledUniform => uniform(len, (2. / (fan_in + fan_out)).sqrt(), rng),
+            Initializer::XavierUniform => uniform(len, (6. / (fan_in + fan_out)).sqrt(), rng),
+            Initializer::XavierNormal => normal(len, (2. / (fan_in + fan_out)).sqrt(), rng),
+            Initializer::HeUniform => uniform(len, (6. / fan_in).sqrt(), rng),
+            Initializer::HeNormal => normal(len, (2. / fan_in).sqrt(), rng),
+            Initializer::LeCun => normal(len, (1. / fan_in).sqrt(), rng),
+            Initializer::Orthogonal(gain) => orthogonal(output_size, input_size, *gain, rng),
+            Initializer::Zeros => vec![0.; len],
+            Initializer::Constant(value) => vec![*value; len],
+            Initializer::Custom(f) => {
+                let mut rng = rng;
+                (0..len)
+                    .map(|_| f(input_size, output_size, &mut rng))
+                    .collect()
+            }
+        };
+
+        Matrix::from_vec(
+            output_size,
+            input_size,
+            values.into_iter().map(T::from_f64).collect(),
+        )
+    }
+}
+
+impl fmt::Debug for Initializer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Initializer::ScaledUniform => write!(f, "ScaledUniform"),
+            Initializer::XavierUniform => write!(f, "XavierUniform"),
+            Initializer::XavierNormal => write!(f, "XavierNormal"),
+            Initializer::HeUniform => write!(f, "HeUniform"),
+            Initializer::HeNormal => write!(f, "HeNormal"),
+            Initializer::LeCun => write!(f, "LeCun"),
+            Initializer::Orthogonal(gain) => write!(f, "Orthogonal({})", gain),
+            Initializer::Zeros => write!(f, "Zeros"),
+            Initializer::Constant(value) => write!(f, "Constant({})", value),
+            Initializer::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+fn uniform<R: Rng + ?Sized>(len: usize, amplitude: f64, rng: &mut R) -> Vec<f64> {
+    (0..len)
+        .map(|_| rng.gen_range(-amplitude..amplitude))
+        .collect()
+}
+
+fn normal<R: Rng + ?Sized>(len: usize, std_dev: f64, rng: &mut R) -> Vec<f64> {
+    let normal = Normal::new(0., std_dev).expect("invalid standard deviation");
+    (0..len).map(|_| normal.sample(rng)).collect()
+}
+
+/// Row-major `rows x cols` matrix with orthonormal rows if `rows <= cols`,
+/// orthonormal columns otherwise, from the Gram-Schmidt of a Gaussian matrix.
+fn orthogonal<R: Rng + ?Sized>(rows: usize, cols: usize, gain: f64, rng: &mut R) -> Vec<f64> {
+    let (nb_vectors, len) = (rows.min(cols), rows.max(cols));
+    let mut vectors: Vec<Vec<f64>> = (0..nb_vectors).map(|_| normal(len, 1., rng)).collect();
+
+    for i in 0..nb_vectors {
+        for j in 0..i {
+            let (done, rest) = vectors.split_at_mut(i);
+            let projection: f64 = done[j].iter().zip(rest[0].iter()).map(|(a, b)| a * b).sum();
+            rest[0]
+                .iter_mut()
+                .zip(done[j].iter())
+                .for_each(|(v, q)| *v -= projection * q);
+        }
+        let norm = vectors[i].iter().map(|v| v * v).sum::<f64>().sqrt();
+        vectors[i].iter_mut().for_each(|v| *v /= norm);
+    }
+
+    if rows <= cols {
+        vectors.into_iter().flatten().map(|v| v * gain).collect()
+    } else {
+        (0..rows)
+            .flat_map(|r| vectors.iter().map(move |column| column[r]))
+            .map(|v| v * gain)
+            .collect()
+    }
+}
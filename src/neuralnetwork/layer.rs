@@ -1,143 +1,778 @@
-use serde_derive::{Serialize, Deserialize};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
 
-use crate::neuralnetwork::activation::{sigmoid, tanh};
-use crate::neuralnetwork::linalgebra::{
-    add_bias, m_addition, m_element_mul, m_substraction, mutate_1d, mutate_2d, w_dot_x,
-    w_random_init,
-};
+use crate::genetic_training::error::TrainingError;
+use crate::neuralnetwork::activation::Activation;
+use crate::neuralnetwork::crossover::Crossover;
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::initializer::Initializer;
+use crate::neuralnetwork::matrix::Matrix;
+use crate::neuralnetwork::mutation::LayerMutation;
+use crate::neuralnetwork::parameters::Parameters;
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct LinearLayer {
-    pub weights: Vec<Vec<f64>>,
-    pub bias: Vec<f64>,
+#[serde(bound = "T: Float")]
+pub struct LinearLayer<T: Float = f64> {
+    /// One row per output.
+    pub weights: Matrix<T>,
+    pub bias: Vec<T>,
+
+    #[serde(default)]
+    pub mutation: LayerMutation,
 }
 
-impl LinearLayer {
+impl<T: Float> LinearLayer<T> {
     pub fn new(input_size: usize, output_size: usize) -> Self {
-        let weights = w_random_init(input_size, output_size);
-        let bias = vec![0.0; output_size];
+        Self::new_with_rng(input_size, output_size, &mut rand::thread_rng())
+    }
+
+    pub fn new_with_rng<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        rng: &mut R,
+    ) -> Self {
+        Self::new_with_initializer(input_size, output_size, &Initializer::default(), rng)
+    }
+
+    pub fn new_with_initializer<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        initializer: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        LinearLayer {
+            weights: initializer.init(input_size, output_size, rng),
+            bias: vec![T::zero(); output_size],
+            mutation: LayerMutation::default(),
+        }
+    }
 
-        LinearLayer { weights, bias }
+    /// Panics if `mutation` is rejected by `LayerMutation::validate`,
+    /// `try_with_mutation` returns the error instead.
+    pub fn with_mutation(self, mutation: LayerMutation) -> Self {
+        self.try_with_mutation(mutation)
+            .unwrap_or_else(|err| panic!("{}", err))
     }
 
-    pub fn set_weights(&mut self, weights: Vec<Vec<f64>>, bias: Vec<f64>) {
-        self.weights = weights;
+    pub fn try_with_mutation(mut self, mutation: LayerMutation) -> Result<Self, TrainingError> {
+        mutation.validate()?;
+        self.mutation = mutation;
+        Ok(self)
+    }
+
+    pub fn set_weights<M: Into<Matrix<T>>>(&mut self, weights: M, bias: Vec<T>) {
+        self.weights = weights.into();
         self.bias = bias;
     }
 
-    pub fn forward(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-        let temp = w_dot_x(&self.weights, input);
-        add_bias(&self.bias, &temp)
+    pub fn forward(&mut self, input: &Matrix<T>) -> Matrix<T> {
+        let mut output = input.matmul_transposed(&self.weights);
+        output.add_row(&self.bias);
+        output
     }
 
-    pub fn mutate(&self, mutation_rate: f64) -> Self {
+    pub fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
         let mut new_layer = self.clone();
-        let coef = (2.0 / (self.weights.len() + self.weights[0].len()) as f64).sqrt();
-        mutate_2d(&mut new_layer.weights, mutation_rate * coef);
-        mutate_1d(&mut new_layer.bias, mutation_rate);
+        let coef = (2.0 / (self.weights.rows() + self.weights.cols()) as f64).sqrt();
+        let LayerMutation { weights, biases } = self.mutation;
+        weights.mutate(new_layer.weights.as_mut_slice(), mutation_rate * coef, rng);
+        biases.mutate(&mut new_layer.bias, mutation_rate, rng);
 
         new_layer
     }
+
+    pub fn crossover<R: Rng + ?Sized>(
+        &self,
+        other: &Self,
+        crossover: Crossover,
+        rng: &mut R,
+    ) -> Self {
+        LinearLayer {
+            weights: crossover.apply_2d(&self.weights, &other.weights, rng),
+            bias: crossover.apply_1d(&self.bias, &other.bias, rng),
+            mutation: self.mutation,
+        }
+    }
+}
+
+impl<T: Float> Parameters<T> for LinearLayer<T> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        Box::new(self.weights.as_slice().iter().chain(self.bias.iter()))
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        Box::new(
+            self.weights
+                .as_mut_slice()
+                .iter_mut()
+                .chain(self.bias.iter_mut()),
+        )
+    }
+
+    fn num_params(&self) -> usize {
+        self.weights.as_slice().len() + self.bias.len()
+    }
+}
+
+/// Writes `input . w^T + hidden_state . u^T + b` into `out`.
+fn gate_into<T: Float>(
+    (w, u, b): (&Matrix<T>, &Matrix<T>, &[T]),
+    input: &Matrix<T>,
+    hidden_state: &Matrix<T>,
+    tmp: &mut Matrix<T>,
+    out: &mut Matrix<T>,
+) {
+    input.matmul_transposed_into(w, out);
+    hidden_state.matmul_transposed_into(u, tmp);
+    out.add_assign(tmp);
+    out.add_row(b);
+}
+
+/// Which parameter groups of a `GRULayer` are mutated, and how strongly.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedGRUMutation")]
+pub struct GRUMutation {
+    /// `w_reset`, `w_update` and `w_candidate`.
+    pub input_weights: bool,
+    /// `u_reset`, `u_update` and `u_candidate`.
+    pub recurrent_weights: bool,
+    pub biases: bool,
+    /// Scale of the recurrent weights mutation relative to the input weights one.
+    pub recurrent_coef: f64,
+    /// Operators applied to the weights, input and recurrent, and to the biases.
+    pub operators: LayerMutation,
+}
+
+impl Default for GRUMutation {
+    fn default() -> Self {
+        GRUMutation {
+            input_weights: true,
+            recurrent_weights: true,
+            biases: true,
+            recurrent_coef: 1.,
+            operators: LayerMutation::default(),
+        }
+    }
+}
+
+impl GRUMutation {
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        if !(self.recurrent_coef.is_finite() && self.recurrent_coef >= 0.) {
+            return Err(TrainingError::InvalidConfig(format!(
+                "recurrent_coef must be a non-negative number, got {}",
+                self.recurrent_coef
+            )));
+        }
+        self.operators.validate()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct UncheckedGRUMutation {
+    input_weights: bool,
+    recurrent_weights: bool,
+    biases: bool,
+    recurrent_coef: f64,
+    operators: LayerMutation,
+}
+
+impl Default for UncheckedGRUMutation {
+    fn default() -> Self {
+        let mutation = GRUMutation::default();
+        UncheckedGRUMutation {
+            input_weights: mutation.input_weights,
+            recurrent_weights: mutation.recurrent_weights,
+            biases: mutation.biases,
+            recurrent_coef: mutation.recurrent_coef,
+            operators: mutation.operators,
+        }
+    }
+}
+
+impl TryFrom<UncheckedGRUMutation> for GRUMutation {
+    type Error = TrainingError;
+
+    fn try_from(mutation: UncheckedGRUMutation) -> Result<Self, TrainingError> {
+        let mutation = GRUMutation {
+            input_weights: mutation.input_weights,
+            recurrent_weights: mutation.recurrent_weights,
+            biases: mutation.biases,
+            recurrent_coef: mutation.recurrent_coef,
+            operators: mutation.operators,
+        };
+        mutation.validate()?;
+        Ok(mutation)
+    }
+}
+
+/// Intermediate results of `GRULayer::forward`, kept to avoid allocating at every step.
+#[derive(Clone, Default)]
+struct GRUBuffers<T: Float> {
+    update: Matrix<T>,
+    reset: Matrix<T>,
+    candidate: Matrix<T>,
+    reset_hidden: Matrix<T>,
+    tmp: Matrix<T>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct GRULayer {
-    pub w_reset: Vec<Vec<f64>>,
-    pub u_reset: Vec<Vec<f64>>,
-    pub b_reset: Vec<f64>,
+#[serde(bound = "T: Float")]
+pub struct GRULayer<T: Float = f64> {
+    pub w_reset: Matrix<T>,
+    pub u_reset: Matrix<T>,
+    pub b_reset: Vec<T>,
+
+    pub w_update: Matrix<T>,
+    pub u_update: Matrix<T>,
+    pub b_update: Vec<T>,
 
-    pub w_update: Vec<Vec<f64>>,
-    pub u_update: Vec<Vec<f64>>,
-    pub b_update: Vec<f64>,
+    pub w_candidate: Matrix<T>,
+    pub u_candidate: Matrix<T>,
+    pub b_candidate: Vec<T>,
 
-    pub w_candidate: Vec<Vec<f64>>,
-    pub u_candidate: Vec<Vec<f64>>,
-    pub b_candidate: Vec<f64>,
+    pub hidden_state: Matrix<T>,
 
-    pub hidden_state: Vec<Vec<f64>>,
+    #[serde(default)]
+    pub mutation: GRUMutation,
+
+    #[serde(skip)]
+    buffers: GRUBuffers<T>,
 }
 
-impl GRULayer {
+impl<T: Float> GRULayer<T> {
     pub fn new(input_size: usize, output_size: usize, batch_size: usize) -> Self {
-        let w_reset = w_random_init(input_size, output_size);
-        let u_reset = w_random_init(output_size, output_size);
-        let b_reset = vec![0.0; output_size];
+        Self::new_with_rng(input_size, output_size, batch_size, &mut rand::thread_rng())
+    }
+
+    pub fn new_with_rng<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Self {
+        let default = Initializer::default();
+        Self::new_with_initializers(input_size, output_size, batch_size, &default, &default, rng)
+    }
+
+    /// `input` initialises the `w_*` matrices and `recurrent` the `u_*` ones,
+    /// `Initializer::Orthogonal(1.)` stabilises the evolved recurrences.
+    pub fn new_with_initializers<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        batch_size: usize,
+        input: &Initializer,
+        recurrent: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        GRULayer {
+            w_reset: input.init(input_size, output_size, rng),
+            u_reset: recurrent.init(output_size, output_size, rng),
+            b_reset: vec![T::zero(); output_size],
+            w_update: input.init(input_size, output_size, rng),
+            u_update: recurrent.init(output_size, output_size, rng),
+            b_update: vec![T::zero(); output_size],
+            w_candidate: input.init(input_size, output_size, rng),
+            u_candidate: recurrent.init(output_size, output_size, rng),
+            b_candidate: vec![T::zero(); output_size],
+            hidden_state: Matrix::zeros(batch_size, output_size),
+            mutation: GRUMutation::default(),
+            buffers: GRUBuffers::default(),
+        }
+    }
+
+    /// Panics if `mutation` is rejected by `GRUMutation::validate`,
+    /// `try_with_mutation` returns the error instead.
+    pub fn with_mutation(self, mutation: GRUMutation) -> Self {
+        self.try_with_mutation(mutation)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_with_mutation(mut self, mutation: GRUMutation) -> Result<Self, TrainingError> {
+        mutation.validate()?;
+        self.mutation = mutation;
+        Ok(self)
+    }
+
+    pub fn forward(&mut self, input: &Matrix<T>) -> Matrix<T> {
+        let GRUBuffers {
+            update,
+            reset,
+            candidate,
+            reset_hidden,
+            tmp,
+        } = &mut self.buffers;
+
+        gate_into(
+            (&self.w_update, &self.u_update, &self.b_update),
+            input,
+            &self.hidden_state,
+            tmp,
+            update,
+        );
+        Activation::Sigmoid.apply_in_place(update);
+
+        gate_into(
+            (&self.w_reset, &self.u_reset, &self.b_reset),
+            input,
+            &self.hidden_state,
+            tmp,
+            reset,
+        );
+        Activation::Sigmoid.apply_in_place(reset);
+
+        reset_hidden.copy_from(reset);
+        reset_hidden.mul_assign(&self.hidden_state);
+        gate_into(
+            (&self.w_candidate, &self.u_candidate, &self.b_candidate),
+            input,
+            reset_hidden,
+            tmp,
+            candidate,
+        );
+        Activation::Tanh.apply_in_place(candidate);
+
+        // h = (1 - z) * h + z * candidate
+        candidate.sub_assign(&self.hidden_state);
+        candidate.mul_assign(update);
+        self.hidden_state.add_assign(candidate);
 
-        let w_update = w_random_init(input_size, output_size);
-        let u_update = w_random_init(output_size, output_size);
-        let b_update = vec![0.0; output_size];
+        self.hidden_state.clone()
+    }
 
-        let w_candidate = w_random_init(input_size, output_size);
-        let u_candidate = w_random_init(output_size, output_size);
-        let b_candidate = vec![0.0; output_size];
+    pub fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        let mut new_layer = self.clone();
+        let mutation = self.mutation;
+        let LayerMutation { weights, biases } = mutation.operators;
+
+        if mutation.input_weights {
+            let coef = (1.0 / (self.w_reset.rows() + self.w_reset.cols()) as f64).sqrt();
+            weights.mutate(new_layer.w_reset.as_mut_slice(), mutation_rate * coef, rng);
+            weights.mutate(new_layer.w_update.as_mut_slice(), mutation_rate * coef, rng);
+            weights.mutate(
+                new_layer.w_candidate.as_mut_slice(),
+                mutation_rate * coef,
+                rng,
+            );
+        }
+
+        if mutation.recurrent_weights {
+            let coef = mutation.recurrent_coef * (1.0 / (2 * self.u_reset.rows()) as f64).sqrt();
+            weights.mutate(new_layer.u_reset.as_mut_slice(), mutation_rate * coef, rng);
+            weights.mutate(new_layer.u_update.as_mut_slice(), mutation_rate * coef, rng);
+            weights.mutate(
+                new_layer.u_candidate.as_mut_slice(),
+                mutation_rate * coef,
+                rng,
+            );
+        }
+
+        if mutation.biases {
+            biases.mutate(&mut new_layer.b_reset, mutation_rate, rng);
+            biases.mutate(&mut new_layer.b_update, mutation_rate, rng);
+            biases.mutate(&mut new_layer.b_candidate, mutation_rate, rng);
+        }
 
-        let hidden_state = vec![vec![0.0; output_size]; batch_size];
+        new_layer
+    }
 
+    /// The hidden state and the mutation settings of the child are taken from `self`.
+    pub fn crossover<R: Rng + ?Sized>(
+        &self,
+        other: &Self,
+        crossover: Crossover,
+        rng: &mut R,
+    ) -> Self {
         GRULayer {
-            w_reset,
-            u_reset,
-            b_reset,
-            w_update,
-            u_update,
-            b_update,
-            w_candidate,
-            u_candidate,
-            b_candidate,
-            hidden_state,
+            w_reset: crossover.apply_2d(&self.w_reset, &other.w_reset, rng),
+            u_reset: crossover.apply_2d(&self.u_reset, &other.u_reset, rng),
+            b_reset: crossover.apply_1d(&self.b_reset, &other.b_reset, rng),
+            w_update: crossover.apply_2d(&self.w_update, &other.w_update, rng),
+            u_update: crossover.apply_2d(&self.u_update, &other.u_update, rng),
+            b_update: crossover.apply_1d(&self.b_update, &other.b_update, rng),
+            w_candidate: crossover.apply_2d(&self.w_candidate, &other.w_candidate, rng),
+            u_candidate: crossover.apply_2d(&self.u_candidate, &other.u_candidate, rng),
+            b_candidate: crossover.apply_1d(&self.b_candidate, &other.b_candidate, rng),
+            hidden_state: self.hidden_state.clone(),
+            mutation: self.mutation,
+            buffers: GRUBuffers::default(),
         }
     }
 
-    pub fn forward(&mut self, input: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-        let z = sigmoid(add_bias(
-            &self.b_update,
-            &m_addition(
-                &w_dot_x(&self.w_update, &input),
-                &w_dot_x(&self.u_update, &self.hidden_state),
-            ),
-        ));
+    pub fn reset(&mut self) {
+        self.hidden_state.fill(T::zero());
+    }
+}
 
-        let r = sigmoid(add_bias(
-            &self.b_reset,
-            &m_addition(
-                &w_dot_x(&self.w_reset, &input),
-                &w_dot_x(&self.u_reset, &self.hidden_state),
-            ),
-        ));
+impl<T: Float> Parameters<T> for GRULayer<T> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        let gates = [
+            (&self.w_reset, &self.u_reset, &self.b_reset),
+            (&self.w_update, &self.u_update, &self.b_update),
+            (&self.w_candidate, &self.u_candidate, &self.b_candidate),
+        ];
 
-        let h_candidate = tanh(add_bias(
-            &self.b_candidate,
-            &m_addition(
-                &w_dot_x(&self.w_candidate, &input),
-                &w_dot_x(&self.u_candidate, &m_element_mul(&r, &self.hidden_state)),
-            ),
-        ));
+        Box::new(gates.into_iter().flat_map(|(w, u, b)| {
+            w.as_slice()
+                .iter()
+                .chain(u.as_slice().iter())
+                .chain(b.iter())
+        }))
+    }
 
-        self.hidden_state = m_addition(
-            &m_element_mul(
-                &m_substraction(&vec![vec![1.; z[0].len()]; z.len()], &z),
-                &self.hidden_state,
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        let gates = [
+            (&mut self.w_reset, &mut self.u_reset, &mut self.b_reset),
+            (&mut self.w_update, &mut self.u_update, &mut self.b_update),
+            (
+                &mut self.w_candidate,
+                &mut self.u_candidate,
+                &mut self.b_candidate,
             ),
-            &m_element_mul(&z, &h_candidate),
+        ];
+
+        Box::new(gates.into_iter().flat_map(|(w, u, b)| {
+            w.as_mut_slice()
+                .iter_mut()
+                .chain(u.as_mut_slice().iter_mut())
+                .chain(b.iter_mut())
+        }))
+    }
+}
+
+/// Diagonal connections from the cell state to the gates of an `LSTMLayer`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct Peepholes<T: Float = f64> {
+    pub input: Vec<T>,
+    pub forget: Vec<T>,
+    pub output: Vec<T>,
+}
+
+/// Intermediate results of `LSTMLayer::forward`, kept to avoid allocating at every step.
+#[derive(Clone, Default)]
+struct LSTMBuffers<T: Float> {
+    input: Matrix<T>,
+    forget: Matrix<T>,
+    output: Matrix<T>,
+    candidate: Matrix<T>,
+    tmp: Matrix<T>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct LSTMLayer<T: Float = f64> {
+    pub w_input: Matrix<T>,
+    pub u_input: Matrix<T>,
+    pub b_input: Vec<T>,
+
+    pub w_forget: Matrix<T>,
+    pub u_forget: Matrix<T>,
+    pub b_forget: Vec<T>,
+
+    pub w_output: Matrix<T>,
+    pub u_output: Matrix<T>,
+    pub b_output: Vec<T>,
+
+    pub w_candidate: Matrix<T>,
+    pub u_candidate: Matrix<T>,
+    pub b_candidate: Vec<T>,
+
+    #[serde(default)]
+    pub peepholes: Option<Peepholes<T>>,
+
+    #[serde(default)]
+    pub mutation: LayerMutation,
+
+    pub hidden_state: Matrix<T>,
+    pub cell_state: Matrix<T>,
+
+    #[serde(skip)]
+    buffers: LSTMBuffers<T>,
+}
+
+/// Adds `peephole * cell_state` to every row of `gate` when the layer has peepholes.
+fn add_peephole<T: Float>(gate: &mut Matrix<T>, peephole: Option<&[T]>, cell_state: &Matrix<T>) {
+    if let Some(peephole) = peephole {
+        for (row, cell_row) in gate.iter_rows_mut().zip(cell_state.iter_rows()) {
+            for ((x, p), c) in row.iter_mut().zip(peephole.iter()).zip(cell_row.iter()) {
+                *x += *p * *c;
+            }
+        }
+    }
+}
+
+impl<T: Float> LSTMLayer<T> {
+    pub fn new(input_size: usize, output_size: usize, batch_size: usize) -> Self {
+        Self::new_with_rng(input_size, output_size, batch_size, &mut rand::thread_rng())
+    }
+
+    /// The forget gate bias starts at 1 so that the cell state is kept by default.
+    pub fn new_with_rng<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Self {
+        let default = Initializer::default();
+        Self::new_with_initializers(input_size, output_size, batch_size, &default, &default, rng)
+    }
+
+    /// Same as `GRULayer::new_with_initializers`, the forget gate bias starts at 1.
+    pub fn new_with_initializers<R: Rng + ?Sized>(
+        input_size: usize,
+        output_size: usize,
+        batch_size: usize,
+        input: &Initializer,
+        recurrent: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        LSTMLayer {
+            w_input: input.init(input_size, output_size, rng),
+            u_input: recurrent.init(output_size, output_size, rng),
+            b_input: vec![T::zero(); output_size],
+            w_forget: input.init(input_size, output_size, rng),
+            u_forget: recurrent.init(output_size, output_size, rng),
+            b_forget: vec![T::one(); output_size],
+            w_output: input.init(input_size, output_size, rng),
+            u_output: recurrent.init(output_size, output_size, rng),
+            b_output: vec![T::zero(); output_size],
+            w_candidate: input.init(input_size, output_size, rng),
+            u_candidate: recurrent.init(output_size, output_size, rng),
+            b_candidate: vec![T::zero(); output_size],
+            peepholes: None,
+            mutation: LayerMutation::default(),
+            hidden_state: Matrix::zeros(batch_size, output_size),
+            cell_state: Matrix::zeros(batch_size, output_size),
+            buffers: LSTMBuffers::default(),
+        }
+    }
+
+    /// Adds peephole connections, initialised at 0, to the input, forget and output gates.
+    pub fn with_peepholes(mut self) -> Self {
+        let output_size = self.b_input.len();
+        self.peepholes = Some(Peepholes {
+            input: vec![T::zero(); output_size],
+            forget: vec![T::zero(); output_size],
+            output: vec![T::zero(); output_size],
+        });
+        self
+    }
+
+    /// The peepholes are mutated with the operator of the biases.
+    /// Panics if `mutation` is rejected by `LayerMutation::validate`,
+    /// `try_with_mutation` returns the error instead.
+    pub fn with_mutation(self, mutation: LayerMutation) -> Self {
+        self.try_with_mutation(mutation)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_with_mutation(mut self, mutation: LayerMutation) -> Result<Self, TrainingError> {
+        mutation.validate()?;
+        self.mutation = mutation;
+        Ok(self)
+    }
+
+    pub fn forward(&mut self, input: &Matrix<T>) -> Matrix<T> {
+        let peepholes = self.peepholes.as_ref();
+        let LSTMBuffers {
+            input: i,
+            forget: f,
+            output: o,
+            candidate,
+            tmp,
+        } = &mut self.buffers;
+
+        gate_into(
+            (&self.w_input, &self.u_input, &self.b_input),
+            input,
+            &self.hidden_state,
+            tmp,
+            i,
+        );
+        add_peephole(i, peepholes.map(|p| p.input.as_slice()), &self.cell_state);
+        Activation::Sigmoid.apply_in_place(i);
+
+        gate_into(
+            (&self.w_forget, &self.u_forget, &self.b_forget),
+            input,
+            &self.hidden_state,
+            tmp,
+            f,
+        );
+        add_peephole(f, peepholes.map(|p| p.forget.as_slice()), &self.cell_state);
+        Activation::Sigmoid.apply_in_place(f);
+
+        gate_into(
+            (&self.w_candidate, &self.u_candidate, &self.b_candidate),
+            input,
+            &self.hidden_state,
+            tmp,
+            candidate,
         );
+        Activation::Tanh.apply_in_place(candidate);
+
+        // c = f * c + i * candidate
+        self.cell_state.mul_assign(f);
+        candidate.mul_assign(i);
+        self.cell_state.add_assign(candidate);
+
+        // The output gate looks at the updated cell state
+        gate_into(
+            (&self.w_output, &self.u_output, &self.b_output),
+            input,
+            &self.hidden_state,
+            tmp,
+            o,
+        );
+        add_peephole(o, peepholes.map(|p| p.output.as_slice()), &self.cell_state);
+        Activation::Sigmoid.apply_in_place(o);
+
+        // h = o * tanh(c)
+        self.hidden_state.copy_from(&self.cell_state);
+        Activation::Tanh.apply_in_place(&mut self.hidden_state);
+        self.hidden_state.mul_assign(o);
 
         self.hidden_state.clone()
     }
 
-    pub fn mutate(&self, mutation_rate: f64) -> Self {
+    pub fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
         let mut new_layer = self.clone();
-        let coef = (1.0 / (self.w_reset.len() + self.w_reset[0].len()) as f64).sqrt();
-        mutate_2d(&mut new_layer.w_reset, mutation_rate * coef);
-        mutate_2d(&mut new_layer.w_update, mutation_rate * coef);
-        mutate_2d(&mut new_layer.w_candidate, mutation_rate * coef);
+        let LayerMutation { weights, biases } = self.mutation;
 
-        mutate_1d(&mut new_layer.b_reset, mutation_rate);
-        mutate_1d(&mut new_layer.b_update, mutation_rate);
-        mutate_1d(&mut new_layer.b_candidate, mutation_rate);
+        let coef = (1.0 / (self.w_input.rows() + self.w_input.cols()) as f64).sqrt();
+        weights.mutate(new_layer.w_input.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(new_layer.w_forget.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(new_layer.w_output.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(
+            new_layer.w_candidate.as_mut_slice(),
+            mutation_rate * coef,
+            rng,
+        );
+
+        let coef = (1.0 / (2 * self.u_input.rows()) as f64).sqrt();
+        weights.mutate(new_layer.u_input.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(new_layer.u_forget.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(new_layer.u_output.as_mut_slice(), mutation_rate * coef, rng);
+        weights.mutate(
+            new_layer.u_candidate.as_mut_slice(),
+            mutation_rate * coef,
+            rng,
+        );
+
+        biases.mutate(&mut new_layer.b_input, mutation_rate, rng);
+        biases.mutate(&mut new_layer.b_forget, mutation_rate, rng);
+        biases.mutate(&mut new_layer.b_output, mutation_rate, rng);
+        biases.mutate(&mut new_layer.b_candidate, mutation_rate, rng);
+
+        if let Some(peepholes) = new_layer.peepholes.as_mut() {
+            biases.mutate(&mut peepholes.input, mutation_rate, rng);
+            biases.mutate(&mut peepholes.forget, mutation_rate, rng);
+            biases.mutate(&mut peepholes.output, mutation_rate, rng);
+        }
 
         new_layer
     }
 
+    /// The states of the child are taken from `self`, both parents must
+    /// have peepholes or none.
+    pub fn crossover<R: Rng + ?Sized>(
+        &self,
+        other: &Self,
+        crossover: Crossover,
+        rng: &mut R,
+    ) -> Self {
+        let peepholes = match (&self.peepholes, &other.peepholes) {
+            (Some(a), Some(b)) => Some(Peepholes {
+                input: crossover.apply_1d(&a.input, &b.input, rng),
+                forget: crossover.apply_1d(&a.forget, &b.forget, rng),
+                output: crossover.apply_1d(&a.output, &b.output, rng),
+            }),
+            (None, None) => None,
+            _ => panic!("crossover of mismatched shapes"),
+        };
+
+        LSTMLayer {
+            w_input: crossover.apply_2d(&self.w_input, &other.w_input, rng),
+            u_input: crossover.apply_2d(&self.u_input, &other.u_input, rng),
+            b_input: crossover.apply_1d(&self.b_input, &other.b_input, rng),
+            w_forget: crossover.apply_2d(&self.w_forget, &other.w_forget, rng),
+            u_forget: crossover.apply_2d(&self.u_forget, &other.u_forget, rng),
+            b_forget: crossover.apply_1d(&self.b_forget, &other.b_forget, rng),
+            w_output: crossover.apply_2d(&self.w_output, &other.w_output, rng),
+            u_output: crossover.apply_2d(&self.u_output, &other.u_output, rng),
+            b_output: crossover.apply_1d(&self.b_output, &other.b_output, rng),
+            w_candidate: crossover.apply_2d(&self.w_candidate, &other.w_candidate, rng),
+            u_candidate: crossover.apply_2d(&self.u_candidate, &other.u_candidate, rng),
+            b_candidate: crossover.apply_1d(&self.b_candidate, &other.b_candidate, rng),
+            peepholes,
+            mutation: self.mutation,
+            hidden_state: self.hidden_state.clone(),
+            cell_state: self.cell_state.clone(),
+            buffers: LSTMBuffers::default(),
+        }
+    }
+
     pub fn reset(&mut self) {
-        self.hidden_state = vec![vec![0.0; self.hidden_state[0].len()]; self.hidden_state.len()];
+        self.hidden_state.fill(T::zero());
+        self.cell_state.fill(T::zero());
+    }
+}
+
+impl<T: Float> Parameters<T> for LSTMLayer<T> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        let gates = [
+            (&self.w_input, &self.u_input, &self.b_input),
+            (&self.w_forget, &self.u_forget, &self.b_forget),
+            (&self.w_output, &self.u_output, &self.b_output),
+            (&self.w_candidate, &self.u_candidate, &self.b_candidate),
+        ];
+        let peepholes = self
+            .peepholes
+            .iter()
+            .flat_map(|p| p.input.iter().chain(p.forget.iter()).chain(p.output.iter()));
+
+        Box::new(
+            gates
+                .into_iter()
+                .flat_map(|(w, u, b)| {
+                    w.as_slice()
+                        .iter()
+                        .chain(u.as_slice().iter())
+                        .chain(b.iter())
+                })
+                .chain(peepholes),
+        )
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        let gates = [
+            (&mut self.w_input, &mut self.u_input, &mut self.b_input),
+            (&mut self.w_forget, &mut self.u_forget, &mut self.b_forget),
+            (&mut self.w_output, &mut self.u_output, &mut self.b_output),
+            (
+                &mut self.w_candidate,
+                &mut self.u_candidate,
+                &mut self.b_candidate,
+            ),
+        ];
+        let peepholes = self.peepholes.iter_mut().flat_map(|p| {
+            p.input
+                .iter_mut()
+                .chain(p.forget.iter_mut())
+                .chain(p.output.iter_mut())
+        });
+
+        Box::new(
+            gates
+                .into_iter()
+                .flat_map(|(w, u, b)| {
+                    w.as_mut_slice()
+                        .iter_mut()
+                        .chain(u.as_mut_slice().iter_mut())
+                        .chain(b.iter_mut())
+                })
+                .chain(peepholes),
+        )
     }
 }
@@ -0,0 +1,428 @@
+use serde_derive::{Deserialize, Serialize};
+use std::ops::{Index, IndexMut};
+
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::simd::{Kernels, SimdLevel};
+
+/// Side of the square tiles used by the blocked matrix products.
+const BLOCK_SIZE: usize = 64;
+
+/// Contiguous row-major matrix, a batch of inputs has one row per sample.
+///
+/// Serialized with its shape, so that a matrix without rows keeps its number
+/// of columns. The list of rows of the `Vec<Vec<f64>>` it replaces is still read.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "MatrixData<T>", bound = "T: Float")]
+pub struct Matrix<T: Float = f64> {
+    rows: usize,
+    cols: usize,
+    data: Vec<T>,
+}
+
+impl<T: Float> Matrix<T> {
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self::filled(rows, cols, T::zero())
+    }
+
+    pub fn filled(rows: usize, cols: usize, value: T) -> Self {
+        Matrix {
+            rows,
+            cols,
+            data: vec![value; rows * cols],
+        }
+    }
+
+    /// Panics if `data` doesn't hold `rows * cols` values.
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<T>) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "{} values can't fill a {}x{} matrix",
+            data.len(),
+            rows,
+            cols
+        );
+        Matrix { rows, cols, data }
+    }
+
+    /// Panics if the rows don't all have the same length.
+    pub fn from_rows(rows: &[Vec<T>]) -> Self {
+        let cols = rows.first().map_or(0, |row| row.len());
+        let mut data = Vec::with_capacity(rows.len() * cols);
+        for row in rows {
+            assert_eq!(row.len(), cols, "rows of different lengths");
+            data.extend_from_slice(row);
+        }
+        Matrix {
+            rows: rows.len(),
+            cols,
+            data,
+        }
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<T>> {
+        self.iter_rows().map(|row| row.to_vec()).collect()
+    }
+
+    /// Stacks the rows of `matrices` one after the other, panics if they
+    /// don't have the same number of columns.
+    pub fn concat_rows<'a, I>(matrices: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Matrix<T>>,
+    {
+        let mut stacked = Matrix::default();
+        for (i, matrix) in matrices.into_iter().enumerate() {
+            if i == 0 {
+                stacked.cols = matrix.cols;
+            }
+            assert_eq!(matrix.cols, stacked.cols, "shape mismatch between matrices");
+            stacked.rows += matrix.rows;
+            stacked.data.extend_from_slice(&matrix.data);
+        }
+        stacked
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    /// Converts to another precision, e.g. `f64` inputs for a `f32` network.
+    pub fn cast<U: Float>(&self) -> Matrix<U> {
+        let mut out = Matrix::default();
+        self.cast_into(&mut out);
+        out
+    }
+
+    /// Writes the conversion of `self` into `out`, reusing its allocation.
+    pub fn cast_into<U: Float>(&self, out: &mut Matrix<U>) {
+        out.rows = self.rows;
+        out.cols = self.cols;
+        out.data.clear();
+        out.data
+            .extend(self.data.iter().map(|x| U::from_f64(x.as_f64())));
+    }
+
+    pub fn row(&self, row: usize) -> &[T] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [T] {
+        &mut self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn iter_rows(&self) -> impl Iterator<Item = &[T]> {
+        // chunks panics on 0, a matrix without columns has no data anyway
+        self.data.chunks(self.cols.max(1))
+    }
+
+    pub fn iter_rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        self.data.chunks_mut(self.cols.max(1))
+    }
+
+    /// Changes the shape and sets every value to 0, reusing the allocation.
+    pub fn reset_zeros(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        self.data.clear();
+        self.data.resize(rows * cols, T::zero());
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.data.iter_mut().for_each(|x| *x = value);
+    }
+
+    /// Copies `other` into `self`, reusing the allocation.
+    pub fn copy_from(&mut self, other: &Matrix<T>) {
+        self.rows = other.rows;
+        self.cols = other.cols;
+        self.data.clear();
+        self.data.extend_from_slice(&other.data);
+    }
+
+    fn assert_same_shape(&self, other: &Matrix<T>) {
+        assert_eq!(
+            self.shape(),
+            other.shape(),
+            "shape mismatch between matrices"
+        );
+    }
+
+    pub fn map_in_place<F: FnMut(T) -> T>(&mut self, mut f: F) {
+        self.data.iter_mut().for_each(|x| *x = f(*x));
+    }
+
+    /// Applies `f(self, other)` element-wise into `self`.
+    pub fn zip_apply<F: FnMut(T, T) -> T>(&mut self, other: &Matrix<T>, mut f: F) {
+        self.assert_same_shape(other);
+        self.data
+            .iter_mut()
+            .zip(other.data.iter())
+            .for_each(|(x, y)| *x = f(*x, *y));
+    }
+
+    pub fn add_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape(other);
+        <T as Kernels>::add_assign(SimdLevel::detect(), &mut self.data, &other.data);
+    }
+
+    pub fn sub_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape(other);
+        <T as Kernels>::sub_assign(SimdLevel::detect(), &mut self.data, &other.data);
+    }
+
+    /// Element-wise product.
+    pub fn mul_assign(&mut self, other: &Matrix<T>) {
+        self.assert_same_shape(other);
+        <T as Kernels>::mul_assign(SimdLevel::detect(), &mut self.data, &other.data);
+    }
+
+    pub fn scale(&mut self, factor: T) {
+        self.map_in_place(|x| x * factor);
+    }
+
+    /// Adds `bias` to every row.
+    pub fn add_row(&mut self, bias: &[T]) {
+        assert_eq!(bias.len(), self.cols, "shape mismatch between matrices");
+        for row in self.iter_rows_mut() {
+            row.iter_mut().zip(bias.iter()).for_each(|(x, b)| *x += *b);
+        }
+    }
+
+    /// `self . other`, see `matmul_into`.
+    pub fn matmul(&self, other: &Matrix<T>) -> Matrix<T> {
+        let mut out = Matrix::default();
+        self.matmul_into(other, &mut out);
+        out
+    }
+
+    /// Writes `self . other` into `out`, resized to `self.rows x other.cols`.
+    pub fn matmul_into(&self, other: &Matrix<T>, out: &mut Matrix<T>) {
+        assert_eq!(self.cols, other.rows, "shape mismatch between matrices");
+        let (m, k, n) = (self.rows, self.cols, other.cols);
+        out.reset_zeros(m, n);
+        let level = SimdLevel::detect();
+
+        for i0 in (0..m).step_by(BLOCK_SIZE) {
+            for p0 in (0..k).step_by(BLOCK_SIZE) {
+                for j0 in (0..n).step_by(BLOCK_SIZE) {
+                    let j1 = (j0 + BLOCK_SIZE).min(n);
+                    for i in i0..(i0 + BLOCK_SIZE).min(m) {
+                        let out_row = &mut out.data[i * n + j0..i * n + j1];
+                        for p in p0..(p0 + BLOCK_SIZE).min(k) {
+                            let a = self.data[i * k + p];
+                            let b_row = &other.data[p * n + j0..p * n + j1];
+                            T::axpy(level, a, b_row, out_row);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// `self . other^T`, see `matmul_transposed_into`.
+    pub fn matmul_transposed(&self, other: &Matrix<T>) -> Matrix<T> {
+        let mut out = Matrix::default();
+        self.matmul_transposed_into(other, &mut out);
+        out
+    }
+
+    /// Writes `self . other^T` into `out`, resized to `self.rows x other.rows`.
+    ///
+    /// This is the product of a batch of inputs by the weights of a layer,
+    /// stored one row per output.
+    pub fn matmul_transposed_into(&self, other: &Matrix<T>, out: &mut Matrix<T>) {
+        assert_eq!(self.cols, other.cols, "shape mismatch between matrices");
+        let (m, k, n) = (self.rows, self.cols, other.rows);
+        out.reset_zeros(m, n);
+        matmul_transposed_slices(&self.data, &other.data, (m, k, n), &mut out.data);
+    }
+}
+
+/// Adds `a . b^T` to `out`, with `a` of shape `m x k`, `b` of shape `n x k`
+/// and `out` of shape `m x n`, all row-major.
+pub(crate) fn matmul_transposed_slices<T: Float>(
+    a: &[T],
+    b: &[T],
+    (m, k, n): (usize, usize, usize),
+    out: &mut [T],
+) {
+    let level = SimdLevel::detect();
+
+    for i0 in (0..m).step_by(BLOCK_SIZE) {
+        for j0 in (0..n).step_by(BLOCK_SIZE) {
+            for p0 in (0..k).step_by(BLOCK_SIZE) {
+                let p1 = (p0 + BLOCK_SIZE).min(k);
+                for i in i0..(i0 + BLOCK_SIZE).min(m) {
+                    let a_row = &a[i * k + p0..i * k + p1];
+                    for j in j0..(j0 + BLOCK_SIZE).min(n) {
+                        let b_row = &b[j * k + p0..j * k + p1];
+                        out[i * n + j] += T::dot(level, a_row, b_row);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adds `a_i . b_i^T` to `out_i` for the `nb` products of a strided batch,
+/// with `a_i` of shape `m x k` starting at `i * a_stride` (0 shares `a` with
+/// every product), `b_i` the `i`-th block of `n x k` rows of `b` and `out_i`
+/// the `i`-th block of `m x n` rows of `out`.
+pub(crate) fn batched_matmul_transposed_slices<T: Float>(
+    (a, a_stride): (&[T], usize),
+    b: &[T],
+    (nb, m, k, n): (usize, usize, usize, usize),
+    out: &mut [T],
+) {
+    assert!(
+        b.len() == nb * n * k
+            && out.len() == nb * m * n
+            && (nb == 0 || a.len() >= (nb - 1) * a_stride + m * k),
+        "shape mismatch between matrices"
+    );
+    let level = SimdLevel::detect();
+
+    for (i, (b, out)) in b
+        .chunks_exact((n * k).max(1))
+        .zip(out.chunks_exact_mut((m * n).max(1)))
+        .enumerate()
+    {
+        let a = &a[i * a_stride..i * a_stride + m * k];
+        // The products are small, four rows of `b` share every load of `a`.
+        // The sums are blocked like `matmul_transposed_slices`, which gives
+        // the same results.
+        for p0 in (0..k).step_by(BLOCK_SIZE) {
+            let p1 = (p0 + BLOCK_SIZE).min(k);
+            for (a_row, out_row) in a.chunks_exact(k).zip(out.chunks_exact_mut(n)) {
+                let a_row = &a_row[p0..p1];
+                let b_row = |j: usize| &b[j * k + p0..j * k + p1];
+                let end = n - n % 4;
+                for j in (0..end).step_by(4) {
+                    let sums = T::dot4(
+                        level,
+                        a_row,
+                        [b_row(j), b_row(j + 1), b_row(j + 2), b_row(j + 3)],
+                    );
+                    out_row[j..j + 4]
+                        .iter_mut()
+                        .zip(sums)
+                        .for_each(|(out, sum)| *out += sum);
+                }
+                for (j, out) in out_row.iter_mut().enumerate().skip(end) {
+                    *out += T::dot(level, a_row, b_row(j));
+                }
+            }
+        }
+    }
+}
+
+impl<T: Float> Index<(usize, usize)> for Matrix<T> {
+    type Output = T;
+
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        assert!(col < self.cols, "column {} out of {}", col, self.cols);
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl<T: Float> IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        assert!(col < self.cols, "column {} out of {}", col, self.cols);
+        &mut self.data[row * self.cols + col]
+    }
+}
+
+/// `matrix[row][col]`, like the nested vectors.
+impl<T: Float> Index<usize> for Matrix<T> {
+    type Output = [T];
+
+    fn index(&self, row: usize) -> &[T] {
+        self.row(row)
+    }
+}
+
+impl<T: Float> IndexMut<usize> for Matrix<T> {
+    fn index_mut(&mut self, row: usize) -> &mut [T] {
+        self.row_mut(row)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, bound = "T: Float")]
+enum MatrixData<T: Float> {
+    Shaped {
+        rows: usize,
+        cols: usize,
+        data: Vec<T>,
+    },
+    Rows(Vec<Vec<T>>),
+}
+
+impl<T: Float> TryFrom<MatrixData<T>> for Matrix<T> {
+    type Error = String;
+
+    fn try_from(matrix: MatrixData<T>) -> Result<Self, String> {
+        match matrix {
+            MatrixData::Shaped { rows, cols, data } => {
+                if data.len() != rows * cols {
+                    return Err(format!(
+                        "{} values can't fill a {}x{} matrix",
+                        data.len(),
+                        rows,
+                        cols
+                    ));
+                }
+                Ok(Matrix { rows, cols, data })
+            }
+            MatrixData::Rows(rows) => {
+                let cols = rows.first().map_or(0, |row| row.len());
+                if rows.iter().any(|row| row.len() != cols) {
+                    return Err("rows of different lengths".to_string());
+                }
+                Ok(Matrix::from_rows(&rows))
+            }
+        }
+    }
+}
+
+impl<T: Float> From<Vec<Vec<T>>> for Matrix<T> {
+    fn from(rows: Vec<Vec<T>>) -> Self {
+        Matrix::from_rows(&rows)
+    }
+}
+
+impl<T: Float> From<&Vec<Vec<T>>> for Matrix<T> {
+    fn from(rows: &Vec<Vec<T>>) -> Self {
+        Matrix::from_rows(rows)
+    }
+}
+
+impl<T: Float> From<Matrix<T>> for Vec<Vec<T>> {
+    fn from(matrix: Matrix<T>) -> Self {
+        matrix.to_rows()
+    }
+}
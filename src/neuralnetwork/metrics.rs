@@ -1,12 +1,15 @@
-pub fn calculate_mse(target: &Vec<Vec<f64>>, prediction: &Vec<Vec<f64>>) -> f64 {
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::matrix::Matrix;
+
+pub fn calculate_mse<T: Float>(target: &Matrix<T>, prediction: &Matrix<T>) -> f64 {
     let squared_errors: Vec<f64> = target
-        .iter()
-        .zip(prediction)
+        .iter_rows()
+        .zip(prediction.iter_rows())
         .flat_map(|(target_batch, prediction_batch)| {
             target_batch
                 .iter()
                 .zip(prediction_batch)
-                .map(|(&t, &p)| (t - p).powi(2))
+                .map(|(&t, &p)| (t.as_f64() - p.as_f64()).powi(2))
         })
         .collect();
 
@@ -15,24 +18,22 @@ pub fn calculate_mse(target: &Vec<Vec<f64>>, prediction: &Vec<Vec<f64>>) -> f64
     mean_squared_error
 }
 
-pub fn calculate_mse_time_series(
-    target: &Vec<Vec<Vec<f64>>>,
-    prediction: &Vec<Vec<Vec<f64>>>,
-) -> f64 {
-    let squared_errors: Vec<f64> =
-        target
-            .iter()
-            .zip(prediction.iter())
-            .flat_map(|(target_t, prediction_t)| {
-                target_t.iter().zip(prediction_t.iter()).flat_map(
-                    |(target_batch, prediction_batch)| {
-                        target_batch.iter().zip(prediction_batch.iter()).map(
-                            |(&target_val, &prediction_val)| (target_val - prediction_val).abs(),
-                        )
-                    },
-                )
-            })
-            .collect();
+pub fn calculate_mse_time_series<T: Float>(target: &[Matrix<T>], prediction: &[Matrix<T>]) -> f64 {
+    let squared_errors: Vec<f64> = target
+        .iter()
+        .zip(prediction.iter())
+        .flat_map(|(target_t, prediction_t)| {
+            target_t.iter_rows().zip(prediction_t.iter_rows()).flat_map(
+                |(target_batch, prediction_batch)| {
+                    target_batch.iter().zip(prediction_batch.iter()).map(
+                        |(&target_val, &prediction_val)| {
+                            (target_val.as_f64() - prediction_val.as_f64()).abs()
+                        },
+                    )
+                },
+            )
+        })
+        .collect();
 
     let mean_squared_error = squared_errors.iter().sum::<f64>() / squared_errors.len() as f64;
 
@@ -1,4 +1,12 @@
 pub mod activation;
+pub mod batch;
+pub mod crossover;
+pub mod float;
+pub mod initializer;
 pub mod layer;
-mod linalgebra;
+pub mod matrix;
 pub mod metrics;
+pub mod mutation;
+pub mod parameters;
+pub mod sequential;
+pub mod simd;
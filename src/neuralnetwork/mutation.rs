@@ -0,0 +1,231 @@
+use rand::Rng;
+use rand_distr::{Cauchy, Distribution, Normal};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::genetic_training::error::TrainingError;
+use crate::neuralnetwork::float::Float;
+
+/// Distribution of the perturbation added to a mutated weight.
+///
+/// The scale of the operator is multiplied by the mutation rate of the layer:
+/// it's the half-width of `Uniform`, the standard deviation of `Gaussian` and
+/// the half-width at half-maximum of `Cauchy`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Noise {
+    Uniform,
+    Gaussian,
+    /// Heavy tailed, occasionally makes large jumps.
+    Cauchy,
+    /// Replaces the weight by a value drawn uniformly in `±scale`, whatever the
+    /// mutation rate as long as it's positive.
+    Reset,
+}
+
+/// How the mutated weights are kept in a range.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Bound {
+    Unbounded,
+    /// The perturbations going away from 0 shrink linearly with the weight and
+    /// vanish at `±max`.
+    Soft(f64),
+    /// The mutated weights are clamped in `±max`.
+    Clamp(f64),
+}
+
+/// Mutation applied to a group of weights, e.g. the weights or the biases of a layer.
+///
+/// The constructors and builders panic on the values rejected by `validate`,
+/// and so does a layer given an invalid operator. A deserialized operator is
+/// validated too.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedOperator")]
+pub struct MutationOperator {
+    pub noise: Noise,
+    pub scale: f64,
+    /// Probability of every weight to be mutated.
+    pub probability: f64,
+    pub bound: Bound,
+}
+
+impl MutationOperator {
+    fn with_noise(noise: Noise, scale: f64) -> Self {
+        MutationOperator {
+            noise,
+            scale,
+            probability: 1.,
+            bound: Bound::Unbounded,
+        }
+        .checked()
+    }
+
+    pub fn uniform(scale: f64) -> Self {
+        Self::with_noise(Noise::Uniform, scale)
+    }
+
+    pub fn gaussian(std_dev: f64) -> Self {
+        Self::with_noise(Noise::Gaussian, std_dev)
+    }
+
+    pub fn cauchy(scale: f64) -> Self {
+        Self::with_noise(Noise::Cauchy, scale)
+    }
+
+    pub fn reset(amplitude: f64) -> Self {
+        Self::with_noise(Noise::Reset, amplitude)
+    }
+
+    /// Mutates every weight with the given probability only.
+    pub fn sparse(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self.checked()
+    }
+
+    pub fn bounded(mut self, bound: Bound) -> Self {
+        self.bound = bound;
+        self.checked()
+    }
+
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        let invalid = |reason: String| Err(TrainingError::InvalidConfig(reason));
+
+        if !(self.scale.is_finite() && self.scale >= 0.) {
+            return invalid(format!(
+                "the mutation scale must be a non-negative number, got {}",
+                self.scale
+            ));
+        }
+        if !(0. ..=1.).contains(&self.probability) {
+            return invalid(format!(
+                "the mutation probability must be in [0, 1], got {}",
+                self.probability
+            ));
+        }
+        if let Bound::Soft(max) | Bound::Clamp(max) = self.bound {
+            if !(max.is_finite() && max > 0.) {
+                return invalid(format!(
+                    "the mutation bound must be greater than 0, got {}",
+                    max
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn checked(self) -> Self {
+        self.validate().unwrap_or_else(|err| panic!("{}", err));
+        self
+    }
+
+    /// Default mutation of the weights: every weight gets a uniform noise of
+    /// half-width 0.1, softly bounded in `±3`.
+    pub fn weights() -> Self {
+        Self::uniform(0.1).bounded(Bound::Soft(3.))
+    }
+
+    /// Default mutation of the biases: 20% of them get a uniform noise of
+    /// half-width 0.05, softly bounded in `±3`.
+    pub fn biases() -> Self {
+        Self::uniform(0.05).sparse(0.2).bounded(Bound::Soft(3.))
+    }
+
+    /// The perturbations are drawn in `f64` whatever the precision of the weights.
+    pub fn mutate<T: Float, R: Rng + ?Sized>(
+        &self,
+        weights: &mut [T],
+        mutation_rate: f64,
+        rng: &mut R,
+    ) {
+        // The sampling ranges below would be empty or unbounded
+        let scale = self.scale * mutation_rate;
+        if !scale.is_finite() || scale <= 0. || self.probability <= 0. {
+            return;
+        }
+
+        for weight in weights.iter_mut() {
+            if self.probability < 1. && rng.gen::<f64>() >= self.probability {
+                continue;
+            }
+
+            let w = weight.as_f64();
+            let (pos_mult, neg_mult) = match self.bound {
+                Bound::Soft(max) => (
+                    if w > 0. { (1. - w / max).max(0.) } else { 1. },
+                    if w < 0. { (1. + w / max).max(0.) } else { 1. },
+                ),
+                _ => (1., 1.),
+            };
+
+            let mutated = match self.noise {
+                Noise::Uniform if pos_mult + neg_mult > 0. => {
+                    w + rng.gen_range(
+                        -(self.scale * neg_mult * mutation_rate)
+                            ..(self.scale * pos_mult * mutation_rate),
+                    )
+                }
+                Noise::Uniform => w,
+                Noise::Gaussian | Noise::Cauchy => {
+                    let delta = if self.noise == Noise::Gaussian {
+                        Normal::new(0., scale).expect("invalid scale").sample(rng)
+                    } else {
+                        Cauchy::new(0., scale).expect("invalid scale").sample(rng)
+                    };
+                    w + delta * if delta > 0. { pos_mult } else { neg_mult }
+                }
+                Noise::Reset => rng.gen_range(-self.scale..self.scale),
+            };
+
+            *weight = T::from_f64(match self.bound {
+                Bound::Clamp(max) => mutated.clamp(-max, max),
+                _ => mutated,
+            });
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UncheckedOperator {
+    noise: Noise,
+    scale: f64,
+    probability: f64,
+    bound: Bound,
+}
+
+impl TryFrom<UncheckedOperator> for MutationOperator {
+    type Error = TrainingError;
+
+    fn try_from(operator: UncheckedOperator) -> Result<Self, TrainingError> {
+        let operator = MutationOperator {
+            noise: operator.noise,
+            scale: operator.scale,
+            probability: operator.probability,
+            bound: operator.bound,
+        };
+        operator.validate()?;
+        Ok(operator)
+    }
+}
+
+/// Operators of the weights and of the biases of a layer.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerMutation {
+    pub weights: MutationOperator,
+    pub biases: MutationOperator,
+}
+
+impl Default for LayerMutation {
+    fn default() -> Self {
+        LayerMutation {
+            weights: MutationOperator::weights(),
+            biases: MutationOperator::biases(),
+        }
+    }
+}
+
+impl LayerMutation {
+    pub fn validate(&self) -> Result<(), TrainingError> {
+        self.weights.validate()?;
+        self.biases.validate()
+    }
+}
@@ -0,0 +1,123 @@
+use crate::neuralnetwork::float::Float;
+
+/// Flat view over the trainable parameters of a layer or a network.
+///
+/// The order of the parameters is stable: fields in declaration order and
+/// matrices row by row. Recurrent states are not parameters.
+///
+/// `T` is the precision of the parameters, `f64` unless the layers are `f32`.
+pub trait Parameters<T: Float = f64> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_>;
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_>;
+
+    fn num_params(&self) -> usize {
+        self.params().count()
+    }
+
+    fn to_flat_vec(&self) -> Vec<T> {
+        self.params().cloned().collect()
+    }
+
+    /// Overwrites the parameters, panics if `params` doesn't have exactly
+    /// `num_params()` values.
+    #[allow(clippy::wrong_self_convention)]
+    fn from_flat_vec(&mut self, params: &[T]) {
+        assert_eq!(
+            params.len(),
+            self.num_params(),
+            "flat vector length doesn't match the number of parameters"
+        );
+        self.params_mut()
+            .zip(params.iter())
+            .for_each(|(param, value)| *param = *value);
+    }
+}
+
+impl<T: Float, P: Parameters<T>> Parameters<T> for Vec<P> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        Box::new(self.iter().flat_map(|item| item.params()))
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        Box::new(self.iter_mut().flat_map(|item| item.params_mut()))
+    }
+}
+
+/// Root mean square difference between the parameters of `a` and `b`, which
+/// doesn't grow with the size of the network. Infinite when they don't have
+/// the same number of parameters.
+pub fn parameter_distance<T, P>(a: &P, b: &P) -> f64
+where
+    T: Float,
+    P: Parameters<T> + ?Sized,
+{
+    let n = a.num_params();
+    if n != b.num_params() {
+        return f64::INFINITY;
+    }
+    if n == 0 {
+        return 0.;
+    }
+    let sum: f64 = a
+        .params()
+        .zip(b.params())
+        .map(|(x, y)| (*x - *y).as_f64().powi(2))
+        .sum();
+    (sum / n as f64).sqrt()
+}
+
+/// Implements `Parameters` for a struct by chaining the parameters of the
+/// listed fields, in that order. The precision defaults to `f64`, networks
+/// of `f32` layers give it after the type: `impl_parameters!(NeuralNet: f32 { gru, output })`.
+///
+/// ```
+/// use genetic_rl::impl_parameters;
+/// use genetic_rl::neuralnetwork::layer::{GRULayer, LinearLayer};
+/// use genetic_rl::neuralnetwork::parameters::Parameters;
+///
+/// struct NeuralNet {
+///     gru: GRULayer,
+///     output: LinearLayer,
+/// }
+///
+/// impl_parameters!(NeuralNet { gru, output });
+///
+/// let net = NeuralNet {
+///     gru: GRULayer::new(1, 4, 1),
+///     output: LinearLayer::new(4, 1),
+/// };
+/// assert_eq!(net.num_params(), 3 * (4 + 16 + 4) + 5);
+/// ```
+#[macro_export]
+macro_rules! impl_parameters {
+    ($type:ty { $($field:ident),+ $(,)? }) => {
+        $crate::impl_parameters!($type: f64 { $($field),+ });
+    };
+    ($type:ty: $float:ty { $($field:ident),+ $(,)? }) => {
+        impl $crate::neuralnetwork::parameters::Parameters<$float> for $type {
+            fn params(&self) -> Box<dyn Iterator<Item = &$float> + '_> {
+                let params = std::iter::empty();
+                $(
+                    let params = params
+                        .chain($crate::neuralnetwork::parameters::Parameters::<$float>::params(&self.$field));
+                )+
+                Box::new(params)
+            }
+
+            fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut $float> + '_> {
+                let params = std::iter::empty();
+                $(
+                    let params = params.chain(
+                        $crate::neuralnetwork::parameters::Parameters::<$float>::params_mut(&mut self.$field),
+                    );
+                )+
+                Box::new(params)
+            }
+
+            fn num_params(&self) -> usize {
+                0 $(+ $crate::neuralnetwork::parameters::Parameters::<$float>::num_params(&self.$field))+
+            }
+        }
+    };
+}
@@ -0,0 +1,394 @@
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::genetic_training::agent::{Agent, AgentBatch};
+use crate::neuralnetwork::activation::Activation;
+use crate::neuralnetwork::batch::SequentialBatch;
+use crate::neuralnetwork::crossover::Crossover;
+use crate::neuralnetwork::float::Float;
+use crate::neuralnetwork::initializer::Initializer;
+use crate::neuralnetwork::layer::{GRULayer, LSTMLayer, LinearLayer};
+use crate::neuralnetwork::matrix::Matrix;
+use crate::neuralnetwork::parameters::{parameter_distance, Parameters};
+
+/// One step of a `Sequential` model.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub enum Layer<T: Float = f64> {
+    Linear(LinearLayer<T>),
+    Gru(GRULayer<T>),
+    Lstm(LSTMLayer<T>),
+    Activation(Activation),
+}
+
+impl<T: Float> Layer<T> {
+    /// `None` for activations, they keep the size of their input.
+    pub fn input_size(&self) -> Option<usize> {
+        match self {
+            Layer::Linear(layer) => Some(layer.weights.cols()),
+            Layer::Gru(layer) => Some(layer.w_reset.cols()),
+            Layer::Lstm(layer) => Some(layer.w_input.cols()),
+            Layer::Activation(_) => None,
+        }
+    }
+
+    pub fn output_size(&self) -> Option<usize> {
+        match self {
+            Layer::Linear(layer) => Some(layer.weights.rows()),
+            Layer::Gru(layer) => Some(layer.w_reset.rows()),
+            Layer::Lstm(layer) => Some(layer.w_input.rows()),
+            Layer::Activation(_) => None,
+        }
+    }
+
+    pub fn forward(&mut self, input: Matrix<T>) -> Matrix<T> {
+        match self {
+            Layer::Linear(layer) => layer.forward(&input),
+            Layer::Gru(layer) => layer.forward(&input),
+            Layer::Lstm(layer) => layer.forward(&input),
+            Layer::Activation(activation) => activation.apply(input),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            Layer::Gru(layer) => layer.reset(),
+            Layer::Lstm(layer) => layer.reset(),
+            Layer::Linear(_) | Layer::Activation(_) => {}
+        }
+    }
+
+    pub fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        match self {
+            Layer::Linear(layer) => Layer::Linear(layer.mutate(mutation_rate, rng)),
+            Layer::Gru(layer) => Layer::Gru(layer.mutate(mutation_rate, rng)),
+            Layer::Lstm(layer) => Layer::Lstm(layer.mutate(mutation_rate, rng)),
+            Layer::Activation(activation) => Layer::Activation(*activation),
+        }
+    }
+
+    /// Panics if the layers are not of the same kind and shape.
+    pub fn crossover<R: Rng + ?Sized>(
+        &self,
+        other: &Self,
+        crossover: Crossover,
+        rng: &mut R,
+    ) -> Self {
+        match (self, other) {
+            (Layer::Linear(a), Layer::Linear(b)) => Layer::Linear(a.crossover(b, crossover, rng)),
+            (Layer::Gru(a), Layer::Gru(b)) => Layer::Gru(a.crossover(b, crossover, rng)),
+            (Layer::Lstm(a), Layer::Lstm(b)) => Layer::Lstm(a.crossover(b, crossover, rng)),
+            (Layer::Activation(a), Layer::Activation(_)) => Layer::Activation(*a),
+            _ => panic!("crossover of mismatched shapes"),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Layer::Linear(_) => "Linear".to_string(),
+            Layer::Gru(_) => "GRU".to_string(),
+            Layer::Lstm(layer) if layer.peepholes.is_some() => "LSTM (peepholes)".to_string(),
+            Layer::Lstm(_) => "LSTM".to_string(),
+            Layer::Activation(activation) => format!("{:?}", activation),
+        }
+    }
+}
+
+impl<T: Float> Parameters<T> for Layer<T> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        match self {
+            Layer::Linear(layer) => layer.params(),
+            Layer::Gru(layer) => layer.params(),
+            Layer::Lstm(layer) => layer.params(),
+            Layer::Activation(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        match self {
+            Layer::Linear(layer) => layer.params_mut(),
+            Layer::Gru(layer) => layer.params_mut(),
+            Layer::Lstm(layer) => layer.params_mut(),
+            Layer::Activation(_) => Box::new(std::iter::empty()),
+        }
+    }
+}
+
+/// Stack of layers applied one after the other, usable directly as an `Agent`.
+///
+/// The builder methods panic when the input size of a layer doesn't match the
+/// output size of the previous one, `try_layer` returns an error instead. A
+/// deserialized model is checked the same way.
+///
+/// The layers compute in `T`, a `Sequential::<f32>::with_batch_size(1)` model
+/// takes half the memory. As an `Agent` it still takes and returns `f64` matrices.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "Layers<T>", bound = "T: Float")]
+pub struct Sequential<T: Float = f64> {
+    layers: Vec<Layer<T>>,
+    batch_size: usize,
+}
+
+impl<T: Float> Default for Sequential<T> {
+    fn default() -> Self {
+        Self::with_batch_size(1)
+    }
+}
+
+impl Sequential {
+    /// `f64` model with a batch size of 1.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: Float> Sequential<T> {
+    /// Batch size of the recurrent states of the GRU and LSTM layers.
+    pub fn with_batch_size(batch_size: usize) -> Self {
+        Sequential {
+            layers: Vec::new(),
+            batch_size,
+        }
+    }
+
+    /// Panics when the input size of `layer` doesn't match the output size of
+    /// the model.
+    pub fn layer(self, layer: Layer<T>) -> Self {
+        self.try_layer(layer)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_layer(mut self, layer: Layer<T>) -> Result<Self, String> {
+        if let (Some(previous), Some(input)) = (self.output_size(), layer.input_size()) {
+            if previous != input {
+                return Err(format!(
+                    "layer {} takes {} inputs but the previous layer outputs {}",
+                    self.layers.len(),
+                    input,
+                    previous
+                ));
+            }
+        }
+        self.layers.push(layer);
+        Ok(self)
+    }
+
+    /// Xavier uniform weights drawn from the thread rng, `linear_with_initializer`
+    /// builds a reproducible model.
+    pub fn linear(self, input_size: usize, output_size: usize) -> Self {
+        self.layer(Layer::Linear(LinearLayer::new(input_size, output_size)))
+    }
+
+    pub fn linear_with_initializer<R: Rng + ?Sized>(
+        self,
+        input_size: usize,
+        output_size: usize,
+        initializer: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        self.layer(Layer::Linear(LinearLayer::new_with_initializer(
+            input_size,
+            output_size,
+            initializer,
+            rng,
+        )))
+    }
+
+    pub fn gru(self, input_size: usize, output_size: usize) -> Self {
+        let batch_size = self.batch_size;
+        self.layer(Layer::Gru(GRULayer::new(
+            input_size,
+            output_size,
+            batch_size,
+        )))
+    }
+
+    /// See `GRULayer::new_with_initializers`.
+    pub fn gru_with_initializers<R: Rng + ?Sized>(
+        self,
+        input_size: usize,
+        output_size: usize,
+        input: &Initializer,
+        recurrent: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        let batch_size = self.batch_size;
+        self.layer(Layer::Gru(GRULayer::new_with_initializers(
+            input_size,
+            output_size,
+            batch_size,
+            input,
+            recurrent,
+            rng,
+        )))
+    }
+
+    pub fn lstm(self, input_size: usize, output_size: usize) -> Self {
+        let batch_size = self.batch_size;
+        self.layer(Layer::Lstm(LSTMLayer::new(
+            input_size,
+            output_size,
+            batch_size,
+        )))
+    }
+
+    /// See `LSTMLayer::new_with_initializers`.
+    pub fn lstm_with_initializers<R: Rng + ?Sized>(
+        self,
+        input_size: usize,
+        output_size: usize,
+        input: &Initializer,
+        recurrent: &Initializer,
+        rng: &mut R,
+    ) -> Self {
+        let batch_size = self.batch_size;
+        self.layer(Layer::Lstm(LSTMLayer::new_with_initializers(
+            input_size,
+            output_size,
+            batch_size,
+            input,
+            recurrent,
+            rng,
+        )))
+    }
+
+    pub fn activation(self, activation: Activation) -> Self {
+        self.layer(Layer::Activation(activation))
+    }
+
+    pub fn relu(self) -> Self {
+        self.activation(Activation::ReLU)
+    }
+
+    pub fn sigmoid(self) -> Self {
+        self.activation(Activation::Sigmoid)
+    }
+
+    pub fn tanh(self) -> Self {
+        self.activation(Activation::Tanh)
+    }
+
+    pub fn layers(&self) -> &[Layer<T>] {
+        &self.layers
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn input_size(&self) -> Option<usize> {
+        self.layers.iter().find_map(|layer| layer.input_size())
+    }
+
+    pub fn output_size(&self) -> Option<usize> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.output_size())
+    }
+
+    pub fn forward(&mut self, input: &Matrix<T>) -> Matrix<T> {
+        self.forward_owned(input.clone())
+    }
+
+    fn forward_owned(&mut self, input: Matrix<T>) -> Matrix<T> {
+        self.layers
+            .iter_mut()
+            .fold(input, |output, layer| layer.forward(output))
+    }
+
+    /// One line per layer with its output size and number of parameters.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{:<20}{:>10}{:>10}\n", "Layer", "Output", "Params");
+        let mut output_size = None;
+        for layer in self.layers.iter() {
+            output_size = layer.output_size().or(output_size);
+            let output = output_size.map_or("-".to_string(), |size| size.to_string());
+            summary += &format!(
+                "{:<20}{:>10}{:>10}\n",
+                layer.name(),
+                output,
+                layer.num_params()
+            );
+        }
+        summary += &format!("Total params: {}", self.num_params());
+        summary
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(bound = "T: Float")]
+struct Layers<T: Float> {
+    layers: Vec<Layer<T>>,
+    batch_size: usize,
+}
+
+impl<T: Float> TryFrom<Layers<T>> for Sequential<T> {
+    type Error = String;
+
+    fn try_from(Layers { layers, batch_size }: Layers<T>) -> Result<Self, String> {
+        layers
+            .into_iter()
+            .try_fold(Sequential::with_batch_size(batch_size), |model, layer| {
+                model.try_layer(layer)
+            })
+    }
+}
+
+impl<T: Float> Parameters<T> for Sequential<T> {
+    fn params(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        self.layers.params()
+    }
+
+    fn params_mut(&mut self) -> Box<dyn Iterator<Item = &mut T> + '_> {
+        self.layers.params_mut()
+    }
+}
+
+impl<T: Float> Agent for Sequential<T> {
+    fn step(&mut self, input: &Matrix) -> Matrix {
+        self.forward_owned(input.cast()).cast()
+    }
+
+    fn reset(&mut self) {
+        self.layers.iter_mut().for_each(|layer| layer.reset());
+    }
+
+    fn mutate<R: Rng + ?Sized>(&self, mutation_rate: f64, rng: &mut R) -> Self {
+        Sequential {
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.mutate(mutation_rate, rng))
+                .collect(),
+            batch_size: self.batch_size,
+        }
+    }
+
+    /// Uniform crossover of every layer.
+    fn crossover<R: Rng + ?Sized>(&self, other: &Self, rng: &mut R) -> Self {
+        assert_eq!(
+            self.layers.len(),
+            other.layers.len(),
+            "crossover of mismatched shapes"
+        );
+        Sequential {
+            layers: self
+                .layers
+                .iter()
+                .zip(other.layers.iter())
+                .map(|(a, b)| a.crossover(b, Crossover::Uniform, rng))
+                .collect(),
+            batch_size: self.batch_size,
+        }
+    }
+
+    /// Root mean square difference of the parameters.
+    fn distance(&self, other: &Self) -> Option<f64> {
+        Some(parameter_distance(self, other))
+    }
+
+    fn stack(agents: &[Self]) -> Option<Box<dyn AgentBatch>> {
+        SequentialBatch::stack(agents).map(|batch| Box::new(batch) as Box<dyn AgentBatch>)
+    }
+}
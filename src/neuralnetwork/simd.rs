@@ -0,0 +1,369 @@
+//! Vectorised kernels of the matrix operations, with runtime CPU feature detection.
+//!
+//! On x86_64 the kernels use AVX2 and FMA when the CPU has them, SSE2 otherwise.
+//! Other architectures use the scalar loops.
+//!
+//! `add_assign`, `sub_assign` and `mul_assign` give exactly the scalar results.
+//! `dot` and `axpy` sum in a different order and fuse multiply-adds with FMA:
+//! the difference with the scalar path is at most `n * EPSILON * sum(|a_i * b_i|)`
+//! for a dot product of length `n`, and `EPSILON * (|alpha * x_i| + |y_i|)` per
+//! element for `axpy`.
+
+use std::iter::Sum;
+use std::ops::{AddAssign, MulAssign, SubAssign};
+use std::sync::OnceLock;
+
+/// Instruction set used by the kernels, ordered from the slowest to the fastest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Avx2Fma,
+}
+
+impl SimdLevel {
+    /// Best level supported by the CPU, detected on the first call.
+    pub fn detect() -> Self {
+        static LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+
+        *LEVEL.get_or_init(|| {
+            #[cfg(target_arch = "x86_64")]
+            {
+                if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                    SimdLevel::Avx2Fma
+                } else {
+                    SimdLevel::Sse2
+                }
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            {
+                SimdLevel::Scalar
+            }
+        })
+    }
+
+    /// Levels the CPU supports, e.g. to compare them with the scalar path.
+    pub fn supported() -> Vec<SimdLevel> {
+        [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2Fma]
+            .into_iter()
+            .filter(|level| *level <= SimdLevel::detect())
+            .collect()
+    }
+}
+
+/// Kernels of the matrix operations at a given `SimdLevel`.
+///
+/// A level the CPU doesn't support falls back to the best supported one. The
+/// default implementations are the scalar loops, `f32` and `f64` override them.
+pub trait Kernels: num_traits::Float + AddAssign + SubAssign + MulAssign + Sum {
+    /// `sum(a_i * b_i)`
+    fn dot(_level: SimdLevel, a: &[Self], b: &[Self]) -> Self {
+        scalar::dot(a, b)
+    }
+
+    /// `dot(a, b_r)` for the four rows `b_r`, with the same results as four
+    /// calls to `dot` but loading `a` once.
+    fn dot4(level: SimdLevel, a: &[Self], b: [&[Self]; 4]) -> [Self; 4] {
+        b.map(|b| Self::dot(level, a, b))
+    }
+
+    /// `y_i += alpha * x_i`
+    fn axpy(_level: SimdLevel, alpha: Self, x: &[Self], y: &mut [Self]) {
+        scalar::axpy(alpha, x, y)
+    }
+
+    /// `a_i += b_i`
+    fn add_assign(_level: SimdLevel, a: &mut [Self], b: &[Self]) {
+        scalar::add_assign(a, b)
+    }
+
+    /// `a_i -= b_i`
+    fn sub_assign(_level: SimdLevel, a: &mut [Self], b: &[Self]) {
+        scalar::sub_assign(a, b)
+    }
+
+    /// `a_i *= b_i`
+    fn mul_assign(_level: SimdLevel, a: &mut [Self], b: &[Self]) {
+        scalar::mul_assign(a, b)
+    }
+}
+
+mod scalar {
+    use super::Kernels;
+
+    pub(super) fn dot<T: Kernels>(a: &[T], b: &[T]) -> T {
+        a.iter().zip(b.iter()).map(|(x, y)| *x * *y).sum()
+    }
+
+    pub(super) fn axpy<T: Kernels>(alpha: T, x: &[T], y: &mut [T]) {
+        y.iter_mut()
+            .zip(x.iter())
+            .for_each(|(y, x)| *y += alpha * *x);
+    }
+
+    pub(super) fn add_assign<T: Kernels>(a: &mut [T], b: &[T]) {
+        a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a += *b);
+    }
+
+    pub(super) fn sub_assign<T: Kernels>(a: &mut [T], b: &[T]) {
+        a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a -= *b);
+    }
+
+    pub(super) fn mul_assign<T: Kernels>(a: &mut [T], b: &[T]) {
+        a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a *= *b);
+    }
+}
+
+/// Generates the kernels of one float type for one instruction set.
+///
+/// The callers check that the CPU supports `$feature` and that the slices
+/// have the same length.
+#[cfg(target_arch = "x86_64")]
+macro_rules! x86_kernels {
+    (
+        $name:ident, $t:ty, $feature:literal, $lanes:literal,
+        $load:ident, $store:ident, $splat:ident, $zero:ident,
+        $add:ident, $sub:ident, $mul:ident, $madd:path
+    ) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+            use std::arch::x86_64::*;
+
+            #[target_feature(enable = $feature)]
+            pub(super) unsafe fn dot(a: &[$t], b: &[$t]) -> $t {
+                let n = a.len();
+                let end = n - n % (2 * $lanes);
+                let (pa, pb) = (a.as_ptr(), b.as_ptr());
+
+                // Two accumulators to hide the latency of the additions
+                let mut acc0 = $zero();
+                let mut acc1 = $zero();
+                let mut i = 0;
+                while i < end {
+                    acc0 = $madd($load(pa.add(i)), $load(pb.add(i)), acc0);
+                    acc1 = $madd($load(pa.add(i + $lanes)), $load(pb.add(i + $lanes)), acc1);
+                    i += 2 * $lanes;
+                }
+
+                let mut lanes = [0. as $t; $lanes];
+                $store(lanes.as_mut_ptr(), $add(acc0, acc1));
+                let mut sum: $t = lanes.iter().sum();
+                for j in end..n {
+                    sum += a[j] * b[j];
+                }
+                sum
+            }
+
+            /// Same accumulators and summation order as `dot`, for every row.
+            #[target_feature(enable = $feature)]
+            pub(super) unsafe fn dot4(a: &[$t], b: [&[$t]; 4]) -> [$t; 4] {
+                let n = a.len();
+                let end = n - n % (2 * $lanes);
+                let pa = a.as_ptr();
+                let pb = b.map(|b| b.as_ptr());
+
+                let mut acc0 = [$zero(); 4];
+                let mut acc1 = [$zero(); 4];
+                let mut i = 0;
+                while i < end {
+                    let (a0, a1) = ($load(pa.add(i)), $load(pa.add(i + $lanes)));
+                    for r in 0..4 {
+                        acc0[r] = $madd(a0, $load(pb[r].add(i)), acc0[r]);
+                        acc1[r] = $madd(a1, $load(pb[r].add(i + $lanes)), acc1[r]);
+                    }
+                    i += 2 * $lanes;
+                }
+
+                let mut sums = [0. as $t; 4];
+                for r in 0..4 {
+                    let mut lanes = [0. as $t; $lanes];
+                    $store(lanes.as_mut_ptr(), $add(acc0[r], acc1[r]));
+                    sums[r] = lanes.iter().sum();
+                    for j in end..n {
+                        sums[r] += a[j] * b[r][j];
+                    }
+                }
+                sums
+            }
+
+            #[target_feature(enable = $feature)]
+            pub(super) unsafe fn axpy(alpha: $t, x: &[$t], y: &mut [$t]) {
+                let n = y.len();
+                let end = n - n % $lanes;
+                let (px, py) = (x.as_ptr(), y.as_mut_ptr());
+
+                let alpha_v = $splat(alpha);
+                let mut i = 0;
+                while i < end {
+                    $store(py.add(i), $madd(alpha_v, $load(px.add(i)), $load(py.add(i))));
+                    i += $lanes;
+                }
+                for j in end..n {
+                    y[j] += alpha * x[j];
+                }
+            }
+
+            x86_kernels!(@binary add_assign, $t, $feature, $lanes, $load, $store, $add, +=);
+            x86_kernels!(@binary sub_assign, $t, $feature, $lanes, $load, $store, $sub, -=);
+            x86_kernels!(@binary mul_assign, $t, $feature, $lanes, $load, $store, $mul, *=);
+        }
+    };
+    (@binary $fn:ident, $t:ty, $feature:literal, $lanes:literal, $load:ident, $store:ident, $op:ident, $scalar_op:tt) => {
+        #[target_feature(enable = $feature)]
+        pub(super) unsafe fn $fn(a: &mut [$t], b: &[$t]) {
+            let n = a.len();
+            let end = n - n % $lanes;
+            let (pa, pb) = (a.as_mut_ptr(), b.as_ptr());
+
+            let mut i = 0;
+            while i < end {
+                $store(pa.add(i), $op($load(pa.add(i)), $load(pb.add(i))));
+                i += $lanes;
+            }
+            for j in end..n {
+                a[j] $scalar_op b[j];
+            }
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2_madd {
+    use std::arch::x86_64::*;
+
+    /// `a * b + c` without FMA.
+    #[inline(always)]
+    pub(super) unsafe fn madd_pd(a: __m128d, b: __m128d, c: __m128d) -> __m128d {
+        _mm_add_pd(_mm_mul_pd(a, b), c)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn madd_ps(a: __m128, b: __m128, c: __m128) -> __m128 {
+        _mm_add_ps(_mm_mul_ps(a, b), c)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+x86_kernels!(
+    avx2_f64,
+    f64,
+    "avx2,fma",
+    4,
+    _mm256_loadu_pd,
+    _mm256_storeu_pd,
+    _mm256_set1_pd,
+    _mm256_setzero_pd,
+    _mm256_add_pd,
+    _mm256_sub_pd,
+    _mm256_mul_pd,
+    _mm256_fmadd_pd
+);
+#[cfg(target_arch = "x86_64")]
+x86_kernels!(
+    avx2_f32,
+    f32,
+    "avx2,fma",
+    8,
+    _mm256_loadu_ps,
+    _mm256_storeu_ps,
+    _mm256_set1_ps,
+    _mm256_setzero_ps,
+    _mm256_add_ps,
+    _mm256_sub_ps,
+    _mm256_mul_ps,
+    _mm256_fmadd_ps
+);
+#[cfg(target_arch = "x86_64")]
+x86_kernels!(
+    sse2_f64,
+    f64,
+    "sse2",
+    2,
+    _mm_loadu_pd,
+    _mm_storeu_pd,
+    _mm_set1_pd,
+    _mm_setzero_pd,
+    _mm_add_pd,
+    _mm_sub_pd,
+    _mm_mul_pd,
+    sse2_madd::madd_pd
+);
+#[cfg(target_arch = "x86_64")]
+x86_kernels!(
+    sse2_f32,
+    f32,
+    "sse2",
+    4,
+    _mm_loadu_ps,
+    _mm_storeu_ps,
+    _mm_set1_ps,
+    _mm_setzero_ps,
+    _mm_add_ps,
+    _mm_sub_ps,
+    _mm_mul_ps,
+    sse2_madd::madd_ps
+);
+
+/// Implements `Kernels` by dispatching to the x86_64 modules.
+macro_rules! impl_kernels {
+    ($t:ty, $avx2:ident, $sse2:ident) => {
+        impl Kernels for $t {
+            fn dot(level: SimdLevel, a: &[$t], b: &[$t]) -> $t {
+                assert_eq!(a.len(), b.len(), "shape mismatch between matrices");
+                match level.min(SimdLevel::detect()) {
+                    // SAFETY: the CPU supports the level and the lengths are equal
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Avx2Fma => unsafe { $avx2::dot(a, b) },
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Sse2 => unsafe { $sse2::dot(a, b) },
+                    _ => scalar::dot(a, b),
+                }
+            }
+
+            fn dot4(level: SimdLevel, a: &[$t], b: [&[$t]; 4]) -> [$t; 4] {
+                assert!(
+                    b.iter().all(|b| b.len() == a.len()),
+                    "shape mismatch between matrices"
+                );
+                match level.min(SimdLevel::detect()) {
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Avx2Fma => unsafe { $avx2::dot4(a, b) },
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Sse2 => unsafe { $sse2::dot4(a, b) },
+                    _ => b.map(|b| scalar::dot(a, b)),
+                }
+            }
+
+            fn axpy(level: SimdLevel, alpha: $t, x: &[$t], y: &mut [$t]) {
+                assert_eq!(x.len(), y.len(), "shape mismatch between matrices");
+                match level.min(SimdLevel::detect()) {
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Avx2Fma => unsafe { $avx2::axpy(alpha, x, y) },
+                    #[cfg(target_arch = "x86_64")]
+                    SimdLevel::Sse2 => unsafe { $sse2::axpy(alpha, x, y) },
+                    _ => scalar::axpy(alpha, x, y),
+                }
+            }
+
+            impl_kernels!(@binary add_assign, $t, $avx2, $sse2);
+            impl_kernels!(@binary sub_assign, $t, $avx2, $sse2);
+            impl_kernels!(@binary mul_assign, $t, $avx2, $sse2);
+        }
+    };
+    (@binary $fn:ident, $t:ty, $avx2:ident, $sse2:ident) => {
+        fn $fn(level: SimdLevel, a: &mut [$t], b: &[$t]) {
+            assert_eq!(a.len(), b.len(), "shape mismatch between matrices");
+            match level.min(SimdLevel::detect()) {
+                #[cfg(target_arch = "x86_64")]
+                SimdLevel::Avx2Fma => unsafe { $avx2::$fn(a, b) },
+                #[cfg(target_arch = "x86_64")]
+                SimdLevel::Sse2 => unsafe { $sse2::$fn(a, b) },
+                _ => scalar::$fn(a, b),
+            }
+        }
+    };
+}
+
+impl_kernels!(f64, avx2_f64, sse2_f64);
+impl_kernels!(f32, avx2_f32, sse2_f32);